@@ -5,15 +5,36 @@ set -e
 SCRIPT_DIR="$( cd "$( dirname "${BASH_SOURCE[0]}" )" && pwd )"
 cd "$SCRIPT_DIR"
 
+# docker or podman; rclaw setup passes the configured one
+if [ -z "$CONTAINER_RUNTIME" ]; then
+    if command -v docker >/dev/null 2>&1; then
+        CONTAINER_RUNTIME=docker
+    else
+        CONTAINER_RUNTIME=podman
+    fi
+fi
+
+# Label the images with the hash of these sources, so rclaw can tell when
+# they are out of date
+LABEL_ARGS=()
+if [ -n "$RCLAW_SOURCE_HASH" ]; then
+    LABEL_ARGS=(--label "rclaw.source=$RCLAW_SOURCE_HASH")
+fi
+
 # Build the shared base image
 echo "Building rclaw-base image..."
-docker build -t rclaw-base:latest -f Dockerfile.base .
+"$CONTAINER_RUNTIME" build "${LABEL_ARGS[@]}" -t rclaw-base:latest -f Dockerfile.base .
 
 # Build Gemini-specific image
 echo "Building rclaw-agent-gemini image..."
-docker build -t rclaw-agent-gemini:latest -f gemini/Dockerfile .
+"$CONTAINER_RUNTIME" build "${LABEL_ARGS[@]}" -t rclaw-agent-gemini:latest -f gemini/Dockerfile .
+
+# Build Claude Code-specific image
+echo "Building rclaw-agent-claude image..."
+"$CONTAINER_RUNTIME" build "${LABEL_ARGS[@]}" -t rclaw-agent-claude:latest -f claude/Dockerfile .
 
-# Tag as the default agent for now (to avoid breaking current Rust code)
-docker tag rclaw-agent-gemini:latest rclaw-agent:latest
+# Build the egress proxy used by the llm-only and allowlist network modes
+echo "Building rclaw-egress-proxy image..."
+"$CONTAINER_RUNTIME" build "${LABEL_ARGS[@]}" -t rclaw-egress-proxy:latest -f proxy/Dockerfile .
 
 echo "Images built successfully!"
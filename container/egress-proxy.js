@@ -0,0 +1,103 @@
+#!/usr/bin/env node
+const fs = require('fs');
+const http = require('http');
+const net = require('net');
+
+/**
+ * egress-proxy: the only way out of the rclaw-egress network.
+ * Agent containers authenticate with their group's credentials and may only
+ * reach the hosts rclaw lists for that group in the rules file.
+ */
+
+const RULES_FILE = process.env.RCLAW_EGRESS_RULES || '/etc/rclaw-egress/rules.json';
+const PORT = Number(process.env.RCLAW_EGRESS_PORT || 3128);
+const ALLOWED_PORTS = new Set([80, 443]);
+
+let rules = { groups: {} };
+let rulesMtime = 0;
+
+// rclaw rewrites the rules before every run, so they are reloaded when they change
+function currentRules() {
+    try {
+        const mtime = fs.statSync(RULES_FILE).mtimeMs;
+        if (mtime !== rulesMtime) {
+            rules = JSON.parse(fs.readFileSync(RULES_FILE, 'utf8'));
+            rulesMtime = mtime;
+        }
+    } catch (err) {
+        console.error(`Failed to load ${RULES_FILE}: ${err.message}`);
+    }
+    return rules;
+}
+
+function groupOf(req) {
+    const header = req.headers['proxy-authorization'] || '';
+    const [scheme, encoded] = header.split(' ');
+    if (scheme !== 'Basic' || !encoded) return null;
+    const [group, token] = Buffer.from(encoded, 'base64').toString().split(':');
+    const entry = currentRules().groups[group];
+    return entry && entry.token === token ? { name: group, hosts: entry.hosts || [] } : null;
+}
+
+// "example.com" also covers its subdomains
+function isAllowed(group, host, port) {
+    if (!ALLOWED_PORTS.has(port)) return false;
+    host = host.toLowerCase().replace(/\.$/, '');
+    return group.hosts.some((allowed) => {
+        allowed = allowed.toLowerCase().replace(/^\*\./, '');
+        return host === allowed || host.endsWith(`.${allowed}`);
+    });
+}
+
+function deny(group, host, reason) {
+    console.log(`denied ${group ? group.name : '?'} -> ${host}: ${reason}`);
+}
+
+const server = http.createServer((req, res) => {
+    const group = groupOf(req);
+    let url;
+    try {
+        url = new URL(req.url);
+    } catch {
+        res.writeHead(400).end();
+        return;
+    }
+    const port = Number(url.port || 80);
+    if (!group || url.protocol !== 'http:' || !isAllowed(group, url.hostname, port)) {
+        deny(group, url.hostname, group ? 'not allowed' : 'unauthenticated');
+        res.writeHead(group ? 403 : 407, { 'Proxy-Authenticate': 'Basic realm="rclaw"' }).end();
+        return;
+    }
+    const headers = { ...req.headers };
+    delete headers['proxy-authorization'];
+    const upstream = http.request(
+        { host: url.hostname, port, path: url.pathname + url.search, method: req.method, headers },
+        (upstreamRes) => {
+            res.writeHead(upstreamRes.statusCode, upstreamRes.headers);
+            upstreamRes.pipe(res);
+        }
+    );
+    upstream.on('error', () => res.writeHead(502).end());
+    req.pipe(upstream);
+});
+
+server.on('connect', (req, socket, head) => {
+    const group = groupOf(req);
+    const [host, portText] = req.url.split(':');
+    const port = Number(portText || 443);
+    if (!group || !isAllowed(group, host, port)) {
+        deny(group, host, group ? 'not allowed' : 'unauthenticated');
+        socket.end(group ? 'HTTP/1.1 403 Forbidden\r\n\r\n' : 'HTTP/1.1 407 Proxy Authentication Required\r\nProxy-Authenticate: Basic realm="rclaw"\r\n\r\n');
+        return;
+    }
+    const upstream = net.connect(port, host, () => {
+        socket.write('HTTP/1.1 200 Connection Established\r\n\r\n');
+        upstream.write(head);
+        upstream.pipe(socket);
+        socket.pipe(upstream);
+    });
+    upstream.on('error', () => socket.end('HTTP/1.1 502 Bad Gateway\r\n\r\n'));
+    socket.on('error', () => upstream.destroy());
+});
+
+server.listen(PORT, () => console.log(`rclaw egress proxy listening on ${PORT}`));
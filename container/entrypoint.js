@@ -1,31 +1,216 @@
 const { spawn } = require('child_process');
 const fs = require('fs');
+const net = require('net');
+const os = require('os');
 const path = require('path');
 
 /**
  * Entrypoint for rclaw-agent
- * Reads ContainerInput from stdin and calls gemini-cli
+ * Reads ContainerInput from the first line of stdin and calls the agent CLI
+ * named in the first argument (gemini by default). The following lines
+ * answer the requests it writes to stdout: tool approvals outside yolo mode
+ * and new access tokens.
  */
 
+// Command line of each supported agent CLI, all producing stream-json on stdout.
+// A session id continues that conversation instead of starting a new one.
+const AGENTS = {
+    gemini: (prompt, session) => ['gemini', [
+        '-o', 'stream-json', '--approval-mode', 'yolo',
+        ...(session ? ['--resume', session] : []),
+        prompt,
+    ]],
+    claude: (prompt, session) => ['claude', [
+        '-p', prompt, '--output-format', 'stream-json', '--verbose', '--dangerously-skip-permissions',
+        ...(session ? ['--resume', session] : []),
+    ]],
+};
+
+// rclaw kills a run that timed out or was cancelled through this pid file
+const runId = process.env.RCLAW_RUN_ID;
+const pidFile = runId ? `/tmp/rclaw-run-${runId}.pid` : null;
+let agent = null;
+
+// Set when rclaw wants each tool call approved, to how long it may wait
+const approvalTimeout = Number(process.env.RCLAW_APPROVAL_TIMEOUT || 0);
+const approvalSocket = `/tmp/rclaw-approval-${runId || process.pid}.sock`;
+const approvalSettingsFile = `/tmp/rclaw-settings-${runId || process.pid}.json`;
+const HOOK = '/usr/local/bin/rclaw-approval-hook';
+// Hook connections waiting for rclaw's answer, by request id
+const pendingApprovals = new Map();
+// Token refreshes waiting for rclaw's answer, by request id
+const pendingCredentials = new Map();
+let nextRequest = 1;
+
+// When the access token rclaw put in the environment expires (epoch seconds)
+let tokenExpiresAt = Number(process.env.RCLAW_TOKEN_EXPIRES_AT || 0);
+// A token this close to expiring is replaced before the CLI starts
+const TOKEN_MARGIN_SECS = 60;
+// What the CLIs print when the token was rejected
+const AUTH_ERROR = /\b401\b|UNAUTHENTICATED|invalid.authentication|authentication_error|token (has )?expired|expired token/i;
+
+function cleanup() {
+    const approvalFiles = approvalTimeout ? [approvalSocket, approvalSettingsFile] : [];
+    for (const file of [pidFile, ...approvalFiles]) {
+        if (file && fs.existsSync(file)) {
+            fs.unlinkSync(file);
+        }
+    }
+}
+
+process.on('SIGTERM', () => {
+    if (agent) {
+        // The agent runs in its own process group, so its tool commands die with it
+        try {
+            process.kill(-agent.pid, 'SIGKILL');
+        } catch (e) {
+            // Already gone
+        }
+    }
+    cleanup();
+    process.exit(143);
+});
+
 async function main() {
+    if (pidFile) {
+        fs.writeFileSync(pidFile, String(process.pid));
+    }
+
     let inputData = '';
-    
-    process.stdin.on('data', (chunk) => {
-        inputData += chunk;
-    });
+    let started = false;
 
-    process.stdin.on('end', () => {
+    const start = (line) => {
+        started = true;
         try {
-            const input = JSON.parse(inputData);
-            runGemini(input);
+            const input = JSON.parse(line);
+            runAgent(process.argv[2] || 'gemini', input).catch((e) => {
+                console.error('Failed to run the agent:', e.message);
+                cleanup();
+                process.exit(1);
+            });
         } catch (e) {
             console.error('Failed to parse input JSON:', e.message);
+            cleanup();
             process.exit(1);
         }
+    };
+
+    process.stdin.on('data', (chunk) => {
+        inputData += chunk;
+        let end;
+        while ((end = inputData.indexOf('\n')) >= 0) {
+            const line = inputData.slice(0, end);
+            inputData = inputData.slice(end + 1);
+            if (!started) {
+                start(line);
+            } else if (line.trim()) {
+                answerRequest(line);
+            }
+        }
+    });
+
+    process.stdin.on('end', () => {
+        if (!started) {
+            start(inputData);
+        }
     });
 }
 
-function runGemini(input) {
+// Hook settings that make the CLI ask rclaw before every tool call. The
+// CLIs still run in their yolo modes, so the hook's answer is the only gate.
+function approvalSettings(agentName) {
+    if (agentName === 'claude') {
+        const hook = { type: 'command', command: HOOK, timeout: approvalTimeout + 30 };
+        return JSON.stringify({ hooks: { PreToolUse: [{ matcher: '*', hooks: [hook] }] } });
+    }
+    const hook = { name: 'rclaw-approval', type: 'command', command: HOOK, timeout: (approvalTimeout + 30) * 1000 };
+    fs.writeFileSync(approvalSettingsFile, JSON.stringify({ hooks: { BeforeTool: [{ matcher: '.*', hooks: [hook] }] } }));
+    return approvalSettingsFile;
+}
+
+// Relays each tool call the hook receives to rclaw as a line of stdout
+function startApprovalServer() {
+    if (fs.existsSync(approvalSocket)) {
+        fs.unlinkSync(approvalSocket);
+    }
+    const server = net.createServer((socket) => {
+        let request = '';
+        socket.on('data', (chunk) => {
+            request += chunk;
+            const end = request.indexOf('\n');
+            if (end < 0) {
+                return;
+            }
+            let call;
+            try {
+                call = JSON.parse(request.slice(0, end));
+            } catch (e) {
+                socket.end(JSON.stringify({ allow: false, reason: 'unreadable request' }) + '\n');
+                return;
+            }
+            const id = nextRequest++;
+            pendingApprovals.set(id, socket);
+            writeLine(JSON.stringify({ type: 'rclaw_approval', id, tool: call.tool, input: call.input }));
+        });
+        socket.on('error', () => {});
+    });
+    server.listen(approvalSocket);
+    server.unref();
+}
+
+function answerRequest(line) {
+    try {
+        const answer = JSON.parse(line);
+        const socket = pendingApprovals.get(answer.id);
+        if (socket) {
+            pendingApprovals.delete(answer.id);
+            socket.end(JSON.stringify(answer) + '\n');
+        }
+        const resolve = pendingCredentials.get(answer.id);
+        if (resolve) {
+            pendingCredentials.delete(answer.id);
+            resolve(answer);
+        }
+    } catch (e) {
+        console.error('Ignoring an unreadable answer from rclaw:', e.message);
+    }
+}
+
+// Asks rclaw for a new access token and puts it in env. Only the host holds
+// the refresh token, so this is the only way to get one.
+function refreshCredentials(env) {
+    return new Promise((resolve) => {
+        const id = nextRequest++;
+        pendingCredentials.set(id, resolve);
+        writeLine(JSON.stringify({ type: 'rclaw_credentials', id }));
+    }).then((answer) => {
+        if (answer.error) {
+            console.error('rclaw could not refresh the credentials:', answer.error);
+            return false;
+        }
+        for (const entry of answer.env || []) {
+            const eq = entry.indexOf('=');
+            env[entry.slice(0, eq)] = entry.slice(eq + 1);
+        }
+        tokenExpiresAt = answer.expires_at || 0;
+        return true;
+    });
+}
+
+// The agent's output is passed through whole lines at a time, so approval
+// requests never land in the middle of one
+let pendingOutput = '';
+function writeLine(line) {
+    process.stdout.write(line + '\n');
+}
+
+async function runAgent(agentName, input) {
+    if (!AGENTS[agentName]) {
+        console.error(`Unknown agent: ${agentName}`);
+        cleanup();
+        process.exit(1);
+    }
+
     let userPrompt = input.prompt;
     let systemInstructions = [];
     let projectContext = [];
@@ -56,8 +241,12 @@ function runGemini(input) {
     systemInstructions.push("IMPORTANT: Ignore any previous instructions about being a software engineering assistant, Gemini, Claude, etc.");
     systemInstructions.push("You are RClaw, the user's personal AI assistant.");
     systemInstructions.push("Your identity and behavior are strictly defined by the 'IDENTITY.md' file.");
+    if (input.ipc_dir) {
+        systemInstructions.push("You can schedule prompts for yourself (reminders, recurring checks) with the 'rclaw-tasks' command. Run 'rclaw-tasks --help' to see how.");
+    }
 
-    const finalPrompt = `
+    // A resumed conversation already has the instructions and context
+    const finalPrompt = input.session_id ? userPrompt : `
 # SYSTEM INSTRUCTIONS
 ${systemInstructions.map(s => `- ${s}`).join('\n')}
 
@@ -68,29 +257,63 @@ ${projectContext.join('\n\n')}
 ${userPrompt}
 `.trim();
 
-    // gemini-cli command
-    const gemini = spawn('gemini', [
-        '-o', 'stream-json',
-        '--approval-mode', 'yolo',
-        finalPrompt
-    ]);
+    const [command, args] = AGENTS[agentName](finalPrompt, input.session_id);
+    const env = { ...process.env };
+    if (input.ipc_dir) {
+        // rclaw-tasks reads the IPC session from here
+        env.RCLAW_IPC_DIR = input.ipc_dir;
+    }
+    if (approvalTimeout) {
+        startApprovalServer();
+        env.RCLAW_APPROVAL_SOCKET = approvalSocket;
+        const settings = approvalSettings(agentName);
+        if (agentName === 'claude') {
+            args.push('--settings', settings);
+        } else {
+            env.GEMINI_CLI_SYSTEM_SETTINGS_PATH = settings;
+        }
+    }
+    if (tokenExpiresAt && tokenExpiresAt - Date.now() / 1000 < TOKEN_MARGIN_SECS) {
+        await refreshCredentials(env);
+    }
+    spawnAgent(command, args, env, true);
+}
 
-    let stdout = '';
-    let stderr = '';
+// Runs the CLI, once more with a new token if the first one was rejected
+function spawnAgent(command, args, env, retryAuth) {
+    const child = spawn(command, args, { detached: true, env });
+    agent = child;
+    let errors = '';
 
-    gemini.stdout.on('data', (data) => {
-        stdout += data.toString();
+    // rclaw decodes the stream-json output line by line as it arrives
+    child.stdout.on('data', (data) => {
+        pendingOutput += data;
+        const end = pendingOutput.lastIndexOf('\n');
+        if (end >= 0) {
+            process.stdout.write(pendingOutput.slice(0, end + 1));
+            pendingOutput = pendingOutput.slice(end + 1);
+        }
     });
 
-    gemini.stderr.on('data', (data) => {
-        stderr += data.toString();
+    child.stderr.on('data', (data) => {
+        errors = (errors + data).slice(-4096);
         process.stderr.write(data);
     });
 
-    gemini.on('close', (code) => {
-        // rclaw-code expects the stream-json output directly on stdout
-        process.stdout.write(stdout);
-        process.exit(code);
+    child.on('close', async (code, signal) => {
+        if (pendingOutput) {
+            process.stdout.write(pendingOutput);
+            pendingOutput = '';
+        }
+        if (code !== 0 && retryAuth && AUTH_ERROR.test(errors) && await refreshCredentials(env)) {
+            console.error('The access token was rejected; retrying with a new one');
+            spawnAgent(command, args, env, false);
+            return;
+        }
+        cleanup();
+        // A killed agent has no exit code; exit like a shell would so the run
+        // is not taken for a success
+        process.exit(signal ? 128 + (os.constants.signals[signal] || 0) : code);
     });
 }
 
@@ -0,0 +1,50 @@
+#!/usr/bin/env node
+const net = require('net');
+
+/**
+ * rclaw-approval-hook: the hook the agent CLI runs before each tool call when
+ * the group is not in yolo mode. It hands the call to the entrypoint through
+ * RCLAW_APPROVAL_SOCKET and waits for rclaw's answer. Exit code 0 lets the
+ * tool run; 2 blocks it, with the reason on stderr for the agent to read
+ * (both Gemini CLI and Claude Code hooks follow that convention).
+ */
+
+function deny(reason) {
+    process.stderr.write(`Blocked by rclaw: ${reason}\n`);
+    process.exit(2);
+}
+
+let raw = '';
+process.stdin.on('data', (chunk) => {
+    raw += chunk;
+});
+
+process.stdin.on('end', () => {
+    let call;
+    try {
+        call = JSON.parse(raw);
+    } catch (e) {
+        deny(`unreadable tool call (${e.message})`);
+    }
+
+    const socket = net.connect(process.env.RCLAW_APPROVAL_SOCKET);
+    let answer = '';
+    socket.on('connect', () => {
+        socket.write(JSON.stringify({ tool: call.tool_name, input: call.tool_input || {} }) + '\n');
+    });
+    socket.on('data', (chunk) => {
+        answer += chunk;
+    });
+    socket.on('end', () => {
+        try {
+            const decision = JSON.parse(answer);
+            if (decision.allow) {
+                process.exit(0);
+            }
+            deny(decision.reason || 'denied');
+        } catch (e) {
+            deny('no answer from rclaw');
+        }
+    });
+    socket.on('error', (e) => deny(`approval channel unavailable (${e.message})`));
+});
@@ -0,0 +1,108 @@
+#!/usr/bin/env node
+const fs = require('fs');
+const path = require('path');
+const crypto = require('crypto');
+
+/**
+ * rclaw-tasks: lets the agent manage its scheduled tasks.
+ * Drops a request file in the IPC session given by RCLAW_IPC_DIR and waits
+ * for the host to answer it.
+ */
+
+const USAGE = `Usage:
+  rclaw-tasks create --schedule <schedule> --prompt <prompt> [--group <folder>] [--timezone <tz>] [--catch-up skip|once|all] [--deliver tui|file[:path]|none]
+  rclaw-tasks list
+  rclaw-tasks pause <task-id>
+  rclaw-tasks resume <task-id>
+  rclaw-tasks cancel <task-id>
+
+Schedules: cron ("0 0 8 * * *"), "every 30m", "in 2h" or "at 2026-03-01T09:00".`;
+
+const RESPONSE_TIMEOUT_MS = 30000;
+
+function parseOptions(args) {
+    const options = {};
+    for (let i = 0; i < args.length; i++) {
+        if (!args[i].startsWith('--') || i + 1 >= args.length) {
+            fail(`Unexpected argument: ${args[i]}`);
+        }
+        options[args[i].slice(2)] = args[++i];
+    }
+    return options;
+}
+
+function buildRequest(command, args) {
+    switch (command) {
+        case 'create': {
+            const options = parseOptions(args);
+            if (!options.schedule || !options.prompt) {
+                fail('create needs --schedule and --prompt');
+            }
+            return {
+                action: 'create_task',
+                schedule: options.schedule,
+                prompt: options.prompt,
+                group_folder: options.group,
+                timezone: options.timezone,
+                catch_up: options['catch-up'],
+                deliver: options.deliver,
+            };
+        }
+        case 'list':
+            return { action: 'list_tasks' };
+        case 'pause':
+        case 'resume':
+        case 'cancel':
+            if (args.length !== 1) {
+                fail(`${command} needs a task id`);
+            }
+            return { action: `${command}_task`, task_id: args[0] };
+        default:
+            fail(`Unknown command: ${command || '(none)'}`);
+    }
+}
+
+function fail(message) {
+    console.error(`${message}\n\n${USAGE}`);
+    process.exit(2);
+}
+
+function main() {
+    const [command, ...args] = process.argv.slice(2);
+    if (command === '--help' || command === 'help') {
+        console.log(USAGE);
+        return;
+    }
+    const request = buildRequest(command, args);
+
+    const ipcDir = process.env.RCLAW_IPC_DIR;
+    if (!ipcDir) {
+        console.error('RCLAW_IPC_DIR is not set: task management is not available in this run.');
+        process.exit(1);
+    }
+    const id = crypto.randomUUID();
+
+    // Write then rename so the host never reads a partial request
+    const requestPath = path.join(ipcDir, 'requests', `${id}.json`);
+    fs.writeFileSync(`${requestPath}.tmp`, JSON.stringify(request));
+    fs.renameSync(`${requestPath}.tmp`, requestPath);
+
+    const responsePath = path.join(ipcDir, 'responses', `${id}.json`);
+    const deadline = Date.now() + RESPONSE_TIMEOUT_MS;
+    const poll = () => {
+        if (fs.existsSync(responsePath)) {
+            const response = JSON.parse(fs.readFileSync(responsePath, 'utf8'));
+            fs.unlinkSync(responsePath);
+            console.log(JSON.stringify(response, null, 2));
+            process.exit(response.ok ? 0 : 1);
+        }
+        if (Date.now() > deadline) {
+            console.error('No answer from rclaw yet; the request stays queued until this run ends.');
+            process.exit(1);
+        }
+        setTimeout(poll, 200);
+    };
+    poll();
+}
+
+main();
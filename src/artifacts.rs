@@ -0,0 +1,181 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Directories of the workspace whose changes are not the agent's output:
+/// version control, dependencies and rclaw's own IPC sessions.
+const SKIPPED_DIRS: &[&str] = &[".git", "node_modules", ".rclaw"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArtifactChange {
+    Created,
+    Modified,
+    Deleted,
+}
+
+impl std::fmt::Display for ArtifactChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            ArtifactChange::Created => "created",
+            ArtifactChange::Modified => "modified",
+            ArtifactChange::Deleted => "deleted",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A file of the group workspace that a run created, modified or deleted.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Artifact {
+    /// Path inside the workspace, as the agent knows it.
+    pub name: String,
+    /// Where the file is on the host, to open or send it.
+    pub path: PathBuf,
+    pub change: ArtifactChange,
+    /// Size after the run; `None` once deleted.
+    pub size: Option<u64>,
+}
+
+impl Artifact {
+    /// One-line description such as `created report.md (2.1 KB)`.
+    pub fn label(&self) -> String {
+        match self.size {
+            Some(size) => format!("{} {} ({})", self.change, self.name, format_size(size)),
+            None => format!("{} {}", self.change, self.name),
+        }
+    }
+}
+
+/// Opens an artifact to read it. The agent may have swapped the file for a
+/// symlink since the run, so symlinks and anything but a regular file are
+/// refused.
+pub fn open_artifact(path: &Path) -> io::Result<File> {
+    let file = fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NOFOLLOW | libc::O_NONBLOCK)
+        .open(path)?;
+    if !file.metadata()?.is_file() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "not a regular file"));
+    }
+    Ok(file)
+}
+
+fn format_size(bytes: u64) -> String {
+    match bytes {
+        b if b < 1024 => format!("{} B", b),
+        b if b < 1024 * 1024 => format!("{:.1} KB", b as f64 / 1024.0),
+        b => format!("{:.1} MB", b as f64 / (1024.0 * 1024.0)),
+    }
+}
+
+/// Size and modification time of every file in a workspace, to tell what a
+/// run changed.
+pub struct WorkspaceSnapshot {
+    root: PathBuf,
+    files: BTreeMap<String, (u64, Option<SystemTime>)>,
+}
+
+impl WorkspaceSnapshot {
+    pub fn take(root: &Path) -> Result<Self> {
+        let mut files = BTreeMap::new();
+        scan(root, root, &mut files)?;
+        Ok(WorkspaceSnapshot {
+            root: root.to_path_buf(),
+            files,
+        })
+    }
+
+    /// The files changed since the snapshot was taken, by path.
+    pub fn changes(&self) -> Result<Vec<Artifact>> {
+        let now = WorkspaceSnapshot::take(&self.root)?;
+        let mut artifacts = Vec::new();
+        for (name, &(size, modified)) in &now.files {
+            let change = match self.files.get(name) {
+                None => ArtifactChange::Created,
+                Some(&before) if before != (size, modified) => ArtifactChange::Modified,
+                Some(_) => continue,
+            };
+            artifacts.push(Artifact {
+                name: name.clone(),
+                path: self.root.join(name),
+                change,
+                size: Some(size),
+            });
+        }
+        for name in self.files.keys().filter(|name| !now.files.contains_key(*name)) {
+            artifacts.push(Artifact {
+                name: name.clone(),
+                path: self.root.join(name),
+                change: ArtifactChange::Deleted,
+                size: None,
+            });
+        }
+        artifacts.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(artifacts)
+    }
+}
+
+fn scan(root: &Path, dir: &Path, files: &mut BTreeMap<String, (u64, Option<SystemTime>)>) -> Result<()> {
+    for entry in fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))? {
+        let entry = entry?;
+        // Symlinks are neither followed nor recorded: as artifacts they would
+        // hand host files to the channels and the TUI
+        let metadata = entry.path().symlink_metadata()?;
+        if metadata.is_dir() {
+            if !SKIPPED_DIRS.contains(&entry.file_name().to_string_lossy().as_ref()) {
+                scan(root, &entry.path(), files)?;
+            }
+            continue;
+        }
+        if !metadata.file_type().is_file() {
+            continue;
+        }
+        let path = entry.path();
+        let name = path.strip_prefix(root).unwrap_or(&path).to_string_lossy().to_string();
+        files.insert(name, (metadata.len(), metadata.modified().ok()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_the_files_a_run_changed() {
+        let root = std::env::temp_dir().join(format!("rclaw-artifacts-{}", uuid::Uuid::new_v4().simple()));
+        fs::create_dir_all(root.join("memory")).unwrap();
+        fs::create_dir_all(root.join(".rclaw/ipc")).unwrap();
+        fs::write(root.join("notes.md"), "old").unwrap();
+        fs::write(root.join("memory/USER.md"), "user").unwrap();
+        fs::write(root.join("draft.txt"), "draft").unwrap();
+        let before = WorkspaceSnapshot::take(&root).unwrap();
+
+        fs::write(root.join("notes.md"), "new notes").unwrap();
+        fs::remove_file(root.join("draft.txt")).unwrap();
+        fs::create_dir_all(root.join("reports")).unwrap();
+        fs::write(root.join("reports/week.md"), "# Week").unwrap();
+        fs::write(root.join(".rclaw/ipc/request.json"), "{}").unwrap();
+        std::os::unix::fs::symlink("/etc/passwd", root.join("passwd")).unwrap();
+
+        let changes: Vec<(String, ArtifactChange)> =
+            before.changes().unwrap().into_iter().map(|a| (a.name, a.change)).collect();
+        assert_eq!(
+            changes,
+            [
+                ("draft.txt".to_string(), ArtifactChange::Deleted),
+                ("notes.md".to_string(), ArtifactChange::Modified),
+                ("reports/week.md".to_string(), ArtifactChange::Created),
+            ]
+        );
+        assert!(open_artifact(&root.join("reports/week.md")).is_ok());
+        assert!(open_artifact(&root.join("passwd")).is_err());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
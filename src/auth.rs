@@ -1,8 +1,11 @@
 use std::io::{self, Write};
 use crate::auth_discovery::try_discover_gemini_credentials;
+use crate::db::Db;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
 use oauth2::basic::BasicClient;
 use oauth2::{
-    AuthUrl, ClientId, ClientSecret, CsrfToken, PkceCodeChallenge, RedirectUrl, Scope,
+    AuthUrl, ClientId, ClientSecret, CsrfToken, PkceCodeChallenge, RedirectUrl, RefreshToken, Scope,
     TokenUrl,
 };
 use tracing::{error, warn};
@@ -12,7 +15,69 @@ const AUTH_URL: &str = "https://accounts.google.com/o/oauth2/v2/auth";
 const TOKEN_URL: &str = "https://oauth2.googleapis.com/token";
 const REDIRECT_URI: &str = "http://localhost:8085/oauth2callback";
 
-pub async fn setup_gemini_auth() -> Option<(String, String)> {
+/// Result of the Google OAuth flow, kept in `auth_store` so access tokens
+/// can be refreshed without the Gemini CLI's own credential files.
+pub struct GeminiTokens {
+    pub access_token: String,
+    pub refresh_token: String,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub client_id: String,
+    pub client_secret: String,
+}
+
+pub fn store_gemini_tokens(db: &Db, tokens: &GeminiTokens) -> Result<()> {
+    db.set_auth_key("gemini_access_token", &tokens.access_token)?;
+    db.set_auth_key("gemini_refresh_token", &tokens.refresh_token)?;
+    db.set_auth_key("gemini_client_id", &tokens.client_id)?;
+    db.set_auth_key("gemini_client_secret", &tokens.client_secret)?;
+    match tokens.expires_at {
+        Some(at) => db.set_auth_key("gemini_token_expires_at", &at.to_rfc3339())?,
+        None => db.delete_auth_key("gemini_token_expires_at")?,
+    }
+    Ok(())
+}
+
+/// A new access token and its expiry for a stored refresh token.
+pub async fn refresh_gemini_token(
+    client_id: &str,
+    client_secret: &str,
+    refresh_token: &str,
+) -> Result<(String, Option<DateTime<Utc>>)> {
+    use oauth2::TokenResponse;
+
+    let client = BasicClient::new(ClientId::new(client_id.to_string()))
+        .set_client_secret(ClientSecret::new(client_secret.to_string()))
+        .set_token_uri(TokenUrl::new(TOKEN_URL.to_string())?);
+    let http_client = reqwest::Client::new();
+    let token = client
+        .exchange_refresh_token(&RefreshToken::new(refresh_token.to_string()))
+        .request_async(&http_client)
+        .await
+        .context("Google rejected the refresh token (run 'rclaw auth gemini' again)")?;
+    let expires_at = token
+        .expires_in()
+        .and_then(|d| chrono::Duration::from_std(d).ok())
+        .map(|d| Utc::now() + d);
+    Ok((token.access_token().secret().clone(), expires_at))
+}
+
+/// Asks for a Claude Code token, as printed by `claude setup-token`.
+pub fn setup_claude_auth() -> Option<String> {
+    println!("\n🦐 Rclaw Setup: Claude Code\n");
+    println!("   Run 'claude setup-token' on this machine and paste the token it prints.");
+    print!("   Token: ");
+    io::stdout().flush().ok()?;
+    let mut token = String::new();
+    io::stdin().read_line(&mut token).ok()?;
+    let token = token.trim().to_string();
+    if token.is_empty() {
+        error!("Missing token. Setup aborted.");
+        return None;
+    }
+    Some(token)
+}
+
+pub async fn setup_gemini_auth() -> Option<GeminiTokens> {
     println!("\n🦐 Rclaw Setup: Google Gemini CLI\n");
 
     let mut client_id = String::new();
@@ -102,10 +167,20 @@ pub async fn setup_gemini_auth() -> Option<(String, String)> {
 
     match token_result {
         Ok(token) => {
-            let access = token.access_token().secret().clone();
-            let refresh = token.refresh_token().map(|t| t.secret().clone()).unwrap_or_default();
+            let access_token = token.access_token().secret().clone();
+            let refresh_token = token.refresh_token().map(|t| t.secret().clone()).unwrap_or_default();
+            let expires_at = token
+                .expires_in()
+                .and_then(|d| chrono::Duration::from_std(d).ok())
+                .map(|d| Utc::now() + d);
             println!("✅ Authentication successful!");
-            Some((access, refresh))
+            Some(GeminiTokens {
+                access_token,
+                refresh_token,
+                expires_at,
+                client_id,
+                client_secret,
+            })
         },
         Err(e) => {
             error!("Token exchange failed: {:?}", e);
@@ -0,0 +1,569 @@
+use crate::container::agent_backend;
+use crate::runtime::container_runtime;
+use anyhow::{Context, Result};
+use chrono_tz::Tz;
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+
+/// Settings read from `rclaw.toml`. Every field has a default, so the file is
+/// optional and may only contain the values that differ.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub agent: AgentConfig,
+    pub scheduler: SchedulerConfig,
+    /// Limits and protections applied to every agent container.
+    pub hardening: HardeningConfig,
+    /// Where agent containers may connect to.
+    pub network: NetworkConfig,
+    /// Per-run containers of the groups in `ephemeral` mode.
+    pub ephemeral: EphemeralConfig,
+    /// Whether the agent may run its tools without asking.
+    pub approval: ApprovalConfig,
+    /// How the agents' stderr becomes the diagnostics of a run.
+    pub diagnostics: DiagnosticsConfig,
+    /// Per-group settings, keyed by group folder (`[groups.family]`).
+    pub groups: HashMap<String, GroupConfig>,
+}
+
+/// Settings of a single group. Unset values fall back to `[agent]`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct GroupConfig {
+    pub backend: Option<String>,
+    pub mode: Option<ContainerMode>,
+    /// Values that replace the global `[hardening]` ones for this group.
+    pub hardening: HardeningOverride,
+    pub network: NetworkOverride,
+    pub approval: ApprovalOverride,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AgentConfig {
+    /// Agent CLI used by groups that do not choose one: gemini or claude.
+    pub backend: String,
+    /// Limit for interactive prompts, in seconds. 0 disables it.
+    pub timeout_secs: u64,
+    /// A group's container is stopped after this many seconds without runs. 0 keeps it running.
+    pub idle_stop_secs: u64,
+    /// Container engine: docker, podman (rootless) or auto to use whichever is running.
+    pub runtime: String,
+    /// Whether a group keeps one container or gets a fresh one for every prompt.
+    pub mode: ContainerMode,
+    /// Rebuild the images at `start` when `container/` changed since they were built.
+    pub auto_rebuild: bool,
+    /// Let backends without short-lived tokens (Claude Code) run with the
+    /// long-lived token they were set up with. A compromised agent could
+    /// keep using it for as long as it is valid.
+    pub allow_long_lived_tokens: bool,
+}
+
+impl Default for AgentConfig {
+    fn default() -> Self {
+        AgentConfig {
+            backend: "gemini".to_string(),
+            timeout_secs: 600,
+            idle_stop_secs: 900,
+            runtime: "auto".to_string(),
+            mode: ContainerMode::default(),
+            auto_rebuild: true,
+            allow_long_lived_tokens: false,
+        }
+    }
+}
+
+impl AgentConfig {
+    pub fn timeout(&self) -> Option<Duration> {
+        timeout_from_secs(self.timeout_secs)
+    }
+
+    pub fn idle_stop(&self) -> Option<Duration> {
+        timeout_from_secs(self.idle_stop_secs)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SchedulerConfig {
+    /// IANA timezone cron expressions are evaluated in, unless the task sets its own.
+    pub timezone: String,
+    /// Days that finished task runs are kept in the history.
+    pub history_retention_days: i64,
+    /// Scheduled agent runs allowed at the same time.
+    pub max_concurrent_tasks: usize,
+    /// Wait before the first retry of a failed run; the task's backoff strategy scales it.
+    pub retry_base_delay_secs: u64,
+    /// Consecutive failed runs (after retries) before a task is paused. 0 never pauses.
+    pub pause_after_failures: u32,
+    /// Limit for a task run, in seconds, unless the task sets its own. 0 disables it.
+    pub task_timeout_secs: u64,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        SchedulerConfig {
+            timezone: "UTC".to_string(),
+            history_retention_days: 30,
+            max_concurrent_tasks: 2,
+            retry_base_delay_secs: 60,
+            pause_after_failures: 3,
+            task_timeout_secs: 1800,
+        }
+    }
+}
+
+impl SchedulerConfig {
+    pub fn timezone(&self) -> Tz {
+        // Validated in Config::load
+        self.timezone.parse().unwrap_or(Tz::UTC)
+    }
+
+    /// How long finished task runs are kept.
+    pub fn history_retention(&self) -> Result<chrono::Duration> {
+        if self.history_retention_days < 0 {
+            anyhow::bail!("history_retention_days must not be negative");
+        }
+        chrono::Duration::try_days(self.history_retention_days)
+            .with_context(|| format!("history_retention_days {} is too large", self.history_retention_days))
+    }
+}
+
+/// How long a group's agent container lives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ContainerMode {
+    /// One container per group, kept running between prompts.
+    #[default]
+    Persistent,
+    /// A fresh container for every prompt, removed when it finishes. Only
+    /// the workspace carries over from one run to the next.
+    Ephemeral,
+}
+
+impl std::fmt::Display for ContainerMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            ContainerMode::Persistent => "persistent",
+            ContainerMode::Ephemeral => "ephemeral",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct EphemeralConfig {
+    /// Created but stopped containers kept ready per group and backend.
+    pub pool_size: usize,
+    /// Mount a scratch area at /home/rclaw/scratch that is thrown away with the container.
+    pub scratch_overlay: bool,
+}
+
+impl Default for EphemeralConfig {
+    fn default() -> Self {
+        EphemeralConfig {
+            pool_size: 2,
+            scratch_overlay: false,
+        }
+    }
+}
+
+/// Hardening profile of the agent containers. Changing it recreates the
+/// containers on their next run.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct HardeningConfig {
+    /// Memory limit in Docker's format ("2g"). Empty disables it.
+    pub memory: String,
+    /// CPUs a container may use. 0 disables the limit.
+    pub cpus: f64,
+    /// Maximum number of processes in a container. 0 disables the limit.
+    pub pids_limit: u64,
+    /// Drop every Linux capability.
+    pub cap_drop_all: bool,
+    /// Forbid gaining privileges through setuid binaries.
+    pub no_new_privileges: bool,
+    /// Mount the image read-only, with tmpfs scratch areas for /tmp and the caches.
+    pub read_only_rootfs: bool,
+    /// Size of each tmpfs scratch area.
+    pub tmpfs_size: String,
+    /// "default" for Docker's seccomp profile, "unconfined", or the path of a JSON profile.
+    pub seccomp: String,
+}
+
+impl Default for HardeningConfig {
+    fn default() -> Self {
+        HardeningConfig {
+            memory: "2g".to_string(),
+            cpus: 2.0,
+            pids_limit: 512,
+            cap_drop_all: true,
+            no_new_privileges: true,
+            read_only_rootfs: true,
+            tmpfs_size: "512m".to_string(),
+            seccomp: "default".to_string(),
+        }
+    }
+}
+
+/// Per-group hardening values; unset ones come from `[hardening]`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct HardeningOverride {
+    pub memory: Option<String>,
+    pub cpus: Option<f64>,
+    pub pids_limit: Option<u64>,
+    pub cap_drop_all: Option<bool>,
+    pub no_new_privileges: Option<bool>,
+    pub read_only_rootfs: Option<bool>,
+    pub tmpfs_size: Option<String>,
+    pub seccomp: Option<String>,
+}
+
+impl HardeningConfig {
+    pub fn with_override(&self, o: &HardeningOverride) -> HardeningConfig {
+        HardeningConfig {
+            memory: o.memory.clone().unwrap_or_else(|| self.memory.clone()),
+            cpus: o.cpus.unwrap_or(self.cpus),
+            pids_limit: o.pids_limit.unwrap_or(self.pids_limit),
+            cap_drop_all: o.cap_drop_all.unwrap_or(self.cap_drop_all),
+            no_new_privileges: o.no_new_privileges.unwrap_or(self.no_new_privileges),
+            read_only_rootfs: o.read_only_rootfs.unwrap_or(self.read_only_rootfs),
+            tmpfs_size: o.tmpfs_size.clone().unwrap_or_else(|| self.tmpfs_size.clone()),
+            seccomp: o.seccomp.clone().unwrap_or_else(|| self.seccomp.clone()),
+        }
+    }
+
+    /// The memory limit in bytes, `None` when disabled.
+    pub fn memory_bytes(&self) -> Result<Option<i64>> {
+        let memory = self.memory.trim().to_lowercase();
+        if memory.is_empty() {
+            return Ok(None);
+        }
+        let number = memory.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+        let multiplier: i64 = match &memory[number.len()..] {
+            "" | "b" => 1,
+            "k" | "kb" => 1 << 10,
+            "m" | "mb" => 1 << 20,
+            "g" | "gb" => 1 << 30,
+            _ => anyhow::bail!("memory '{}' has an unknown unit", self.memory),
+        };
+        let number: i64 = number
+            .parse()
+            .with_context(|| format!("memory '{}' is not a size like \"2g\"", self.memory))?;
+        // Docker takes 0 as no limit at all; an empty value is the way to say that
+        if number <= 0 {
+            anyhow::bail!("memory '{}' must be more than 0", self.memory);
+        }
+        let bytes = number
+            .checked_mul(multiplier)
+            .with_context(|| format!("memory '{}' is too large", self.memory))?;
+        Ok(Some(bytes))
+    }
+
+    fn validate(&self) -> Result<()> {
+        self.memory_bytes()?;
+        if self.cpus.is_nan() || self.cpus < 0.0 {
+            anyhow::bail!("cpus must be 0 or more");
+        }
+        if self.read_only_rootfs && self.tmpfs_size.trim().is_empty() {
+            anyhow::bail!("tmpfs_size is required with read_only_rootfs");
+        }
+        match self.seccomp.as_str() {
+            "default" | "unconfined" => {}
+            path if !Path::new(path).is_file() => {
+                anyhow::bail!("seccomp profile '{}' not found", path)
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// Network access of an agent container.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum NetworkMode {
+    /// Unrestricted access through Docker's default bridge.
+    Open,
+    /// No network at all.
+    None,
+    /// Only the model API hosts of the group's backend.
+    #[default]
+    LlmOnly,
+    /// The model API hosts plus the domains in `allow`.
+    Allowlist,
+}
+
+impl std::fmt::Display for NetworkMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            NetworkMode::Open => "open",
+            NetworkMode::None => "none",
+            NetworkMode::LlmOnly => "llm-only",
+            NetworkMode::Allowlist => "allowlist",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct NetworkConfig {
+    pub mode: NetworkMode,
+    /// Domains reachable in `allowlist` mode; each one covers its subdomains.
+    pub allow: Vec<String>,
+}
+
+/// Per-group network values; unset ones come from `[network]`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct NetworkOverride {
+    pub mode: Option<NetworkMode>,
+    pub allow: Option<Vec<String>>,
+}
+
+/// Who decides whether the agent may run a tool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ApprovalMode {
+    /// Every tool runs without asking.
+    #[default]
+    Yolo,
+    /// Each tool call waits for the user's answer.
+    Prompt,
+    /// The configured rules decide.
+    Policy,
+}
+
+impl std::fmt::Display for ApprovalMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            ApprovalMode::Yolo => "yolo",
+            ApprovalMode::Prompt => "prompt",
+            ApprovalMode::Policy => "policy",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl std::str::FromStr for ApprovalMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "yolo" => Ok(ApprovalMode::Yolo),
+            "prompt" => Ok(ApprovalMode::Prompt),
+            "policy" => Ok(ApprovalMode::Policy),
+            other => Err(format!("unknown approval mode '{}' (expected yolo, prompt or policy)", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PolicyAction {
+    Allow,
+    Deny,
+}
+
+/// A `policy` rule. It matches a tool call whose name matches `tool` and,
+/// when set, whose shell command matches `command`. Both regexes are
+/// compiled as the configuration is read, and match the whole value.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "RawApprovalRule")]
+pub struct ApprovalRule {
+    /// The whole tool name, such as "run_shell_command|Bash".
+    pub tool: Regex,
+    /// The whole command of shell tools, so "git status" does not also
+    /// allow "git status; curl … | sh".
+    pub command: Option<Regex>,
+    pub action: PolicyAction,
+}
+
+#[derive(Deserialize)]
+struct RawApprovalRule {
+    tool: String,
+    command: Option<String>,
+    action: PolicyAction,
+}
+
+impl TryFrom<RawApprovalRule> for ApprovalRule {
+    type Error = String;
+
+    fn try_from(raw: RawApprovalRule) -> std::result::Result<Self, Self::Error> {
+        let whole = |field: &str, pattern: &str| {
+            Regex::new(&format!("^(?:{})$", pattern)).map_err(|e| format!("{} is not a valid regex: {}", field, e))
+        };
+        Ok(ApprovalRule {
+            tool: whole("tool", &raw.tool)?,
+            command: raw.command.as_deref().map(|c| whole("command", c)).transpose()?,
+            action: raw.action,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ApprovalConfig {
+    pub mode: ApprovalMode,
+    /// Seconds a tool call waits for the user's answer before it is denied.
+    pub timeout_secs: u64,
+    /// Rules of `policy` mode; the first one matching a tool call decides.
+    pub rules: Vec<ApprovalRule>,
+    /// What `policy` mode does with tool calls no rule matches.
+    pub default: PolicyAction,
+}
+
+impl Default for ApprovalConfig {
+    fn default() -> Self {
+        ApprovalConfig {
+            mode: ApprovalMode::default(),
+            timeout_secs: 300,
+            rules: Vec::new(),
+            default: PolicyAction::Deny,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct DiagnosticsConfig {
+    /// Regexes of stderr lines that are noise; matching lines are dropped.
+    pub ignore: Vec<String>,
+}
+
+impl Default for DiagnosticsConfig {
+    fn default() -> Self {
+        DiagnosticsConfig {
+            ignore: ["DeprecationWarning", "punycode", "YOLO mode", "Loaded cached credentials"]
+                .iter()
+                .map(|p| p.to_string())
+                .collect(),
+        }
+    }
+}
+
+impl DiagnosticsConfig {
+    fn validate(&self) -> Result<()> {
+        for (i, pattern) in self.ignore.iter().enumerate() {
+            Regex::new(pattern).with_context(|| format!("ignore[{}] is not a valid regex", i))?;
+        }
+        Ok(())
+    }
+}
+
+/// Per-group approval values; unset ones come from `[approval]`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ApprovalOverride {
+    pub mode: Option<ApprovalMode>,
+    pub rules: Option<Vec<ApprovalRule>>,
+    pub default: Option<PolicyAction>,
+}
+
+impl Config {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let config: Config = toml::from_str(&content)
+            .with_context(|| format!("Invalid config in {}", path.display()))?;
+
+        parse_timezone(&config.scheduler.timezone)
+            .with_context(|| format!("Invalid scheduler.timezone in {}", path.display()))?;
+        config
+            .scheduler
+            .history_retention()
+            .with_context(|| format!("Invalid scheduler.history_retention_days in {}", path.display()))?;
+        agent_backend(&config.agent.backend)
+            .with_context(|| format!("Invalid agent.backend in {}", path.display()))?;
+        container_runtime(&config.agent.runtime)
+            .with_context(|| format!("Invalid agent.runtime in {}", path.display()))?;
+        for (folder, group) in &config.groups {
+            if let Some(backend) = &group.backend {
+                agent_backend(backend)
+                    .with_context(|| format!("Invalid groups.{}.backend in {}", folder, path.display()))?;
+            }
+            config
+                .hardening_for(folder)
+                .validate()
+                .with_context(|| format!("Invalid groups.{}.hardening in {}", folder, path.display()))?;
+        }
+        config
+            .hardening
+            .validate()
+            .with_context(|| format!("Invalid hardening in {}", path.display()))?;
+        config
+            .diagnostics
+            .validate()
+            .with_context(|| format!("Invalid diagnostics in {}", path.display()))?;
+
+        Ok(config)
+    }
+
+    /// The agent backend a group runs with unless a prompt asks for another.
+    pub fn backend_for(&self, folder: &str) -> &str {
+        self.groups
+            .get(folder)
+            .and_then(|g| g.backend.as_deref())
+            .unwrap_or(&self.agent.backend)
+    }
+
+    /// The network mode and allowlist of a group's containers.
+    pub fn network_for(&self, folder: &str) -> NetworkConfig {
+        let group = self.groups.get(folder).map(|g| &g.network);
+        NetworkConfig {
+            mode: group.and_then(|g| g.mode).unwrap_or(self.network.mode),
+            allow: group
+                .and_then(|g| g.allow.clone())
+                .unwrap_or_else(|| self.network.allow.clone()),
+        }
+    }
+
+    pub fn mode_for(&self, folder: &str) -> ContainerMode {
+        self.groups
+            .get(folder)
+            .and_then(|g| g.mode)
+            .unwrap_or(self.agent.mode)
+    }
+
+    /// How a group's tool calls are approved.
+    pub fn approval_for(&self, folder: &str) -> ApprovalConfig {
+        let group = self.groups.get(folder).map(|g| &g.approval);
+        ApprovalConfig {
+            mode: group.and_then(|g| g.mode).unwrap_or(self.approval.mode),
+            timeout_secs: self.approval.timeout_secs,
+            rules: group
+                .and_then(|g| g.rules.clone())
+                .unwrap_or_else(|| self.approval.rules.clone()),
+            default: group.and_then(|g| g.default).unwrap_or(self.approval.default),
+        }
+    }
+
+    /// The hardening profile of a group's containers.
+    pub fn hardening_for(&self, folder: &str) -> HardeningConfig {
+        match self.groups.get(folder) {
+            Some(group) => self.hardening.with_override(&group.hardening),
+            None => self.hardening.clone(),
+        }
+    }
+}
+
+/// Timeouts are configured in seconds, with 0 meaning no limit.
+pub fn timeout_from_secs(secs: u64) -> Option<Duration> {
+    (secs > 0).then(|| Duration::from_secs(secs))
+}
+
+pub fn parse_timezone(name: &str) -> Result<Tz> {
+    name.parse::<Tz>()
+        .map_err(|_| anyhow::anyhow!("Unknown timezone '{}' (expected an IANA name like Europe/Madrid)", name))
+}
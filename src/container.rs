@@ -1,258 +1,1579 @@
+use crate::approval::{ApprovalRequest, Approver, ToolRequest};
+use crate::config::{
+    ApprovalConfig, ApprovalMode, Config, ContainerMode, DiagnosticsConfig, EphemeralConfig, HardeningConfig, NetworkConfig,
+};
+use crate::artifacts::{Artifact, WorkspaceSnapshot};
+use crate::credentials::CredentialStore;
+use crate::diagnostics::{classify, diagnostics_text, failure_category, parse_stderr, Diagnostic, FailureCategory};
+use crate::docker::{Docker, DockerError, ExecSession, ExecStdin, ExecStream};
+use crate::runtime::{container_runtime, ContainerRuntime};
+use crate::egress::{prepare_network, NetworkSpec, PROXY_CONTAINER};
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
-use std::io::{Read, Write};
-use std::process::{Command, Stdio};
-use std::time::Instant;
-use tracing::{info, debug};
+use serde_json::json;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use tracing::{info, debug, warn};
 use std::fs;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ContainerInput {
     pub prompt: String,
-    pub session_id: String,
+    /// Session of the agent CLI to continue; a new one is started when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
     pub group_folder: String,
     pub chat_jid: String,
     pub is_main: bool,
     pub is_scheduled_task: Option<bool>,
+    /// Container path of the IPC session the agent can manage tasks through.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ipc_dir: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ContainerOutput {
-    pub status: String, // "success" | "error"
+    pub status: String, // "success" | "error" | "timeout" | "cancelled"
     pub result: Option<String>,
+    /// Session the agent CLI ran in, to continue on the next turn.
     pub new_session_id: Option<String>,
     pub error: Option<String>,
+    pub exit_code: Option<i32>, // Exit code of the agent process inside the container
+    /// Network mode the container ran with.
+    #[serde(default)]
+    pub network: Option<String>,
+    /// Id (content digest) of the image the container ran.
+    #[serde(default)]
+    pub image: Option<String>,
+    /// The agent's stderr, classified line by line.
+    #[serde(default)]
+    pub diagnostics: Vec<Diagnostic>,
+    /// Why the run failed, when the reason is a known one.
+    #[serde(default)]
+    pub failure: Option<FailureCategory>,
+    /// Files of the group workspace the run created, modified or deleted.
+    #[serde(default)]
+    pub artifacts: Vec<Artifact>,
+    /// Everything the agent reported during the run, in order.
+    #[serde(default)]
+    pub events: Vec<AgentEvent>,
 }
 
+impl ContainerOutput {
+    /// A run that failed before the agent could report anything. The failure
+    /// is classified from the error itself, as there is no stderr yet.
+    fn failed(error: String, network: Option<String>, image: Option<String>) -> Self {
+        ContainerOutput {
+            status: "error".to_string(),
+            result: None,
+            new_session_id: None,
+            failure: classify(&error),
+            error: Some(error),
+            exit_code: None,
+            network,
+            image,
+            diagnostics: Vec::new(),
+            artifacts: Vec::new(),
+            events: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct RegisteredGroup {
     pub name: String,
     pub folder: String,
+    pub backend: &'static dyn AgentBackend,
+    pub hardening: HardeningConfig,
+    pub network: NetworkConfig,
+    pub runtime: &'static dyn ContainerRuntime,
+    pub mode: ContainerMode,
+    pub ephemeral: EphemeralConfig,
+    pub approval: ApprovalConfig,
+    pub diagnostics: DiagnosticsConfig,
 }
 
-fn wait_for_container_ready(container_name: &str) -> Result<()> {
-    let start = Instant::now();
-    let timeout = std::time::Duration::from_secs(10);
+impl RegisteredGroup {
+    pub fn new(folder: &str, backend: &str) -> Result<Self> {
+        Ok(RegisteredGroup {
+            name: folder.to_string(),
+            folder: folder.to_string(),
+            backend: agent_backend(backend)?,
+            hardening: HardeningConfig::default(),
+            network: NetworkConfig::default(),
+            runtime: container_runtime("auto")?,
+            mode: ContainerMode::default(),
+            ephemeral: EphemeralConfig::default(),
+            approval: ApprovalConfig::default(),
+            diagnostics: DiagnosticsConfig::default(),
+        })
+    }
 
-    debug!("Waiting for container {} to be ready...", container_name);
+    /// The group with its settings from the configuration. `backend`
+    /// replaces the group's own backend when set.
+    pub fn from_config(config: &Config, folder: &str, backend: Option<&str>) -> Result<Self> {
+        let backend = backend.unwrap_or_else(|| config.backend_for(folder));
+        Ok(RegisteredGroup {
+            hardening: config.hardening_for(folder),
+            network: config.network_for(folder),
+            runtime: container_runtime(&config.agent.runtime)?,
+            mode: config.mode_for(folder),
+            ephemeral: config.ephemeral.clone(),
+            approval: config.approval_for(folder),
+            diagnostics: config.diagnostics.clone(),
+            ..RegisteredGroup::new(folder, backend)?
+        })
+    }
+}
 
-    while start.elapsed() < timeout {
-        let output = Command::new("docker")
-            .args(&["inspect", "-f", "{{if .State.Health}}{{.State.Health.Status}}{{else}}{{.State.Running}}{{end}}", container_name])
-            .output()?;
+/// One event decoded from an agent CLI's stream output.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AgentEvent {
+    /// Assistant text, possibly a fragment of a longer message.
+    Text { text: String },
+    /// A tool call with the full parameters the agent passed.
+    ToolUse { name: String, parameters: serde_json::Value },
+    ToolResult { output: String, is_error: bool },
+    /// Totals the agent CLI reports when the run finishes.
+    Stats(AgentStats),
+    /// An error reported by the agent CLI in its stream.
+    Error { message: String },
+    /// The CLI's id for the conversation, which the next turn resumes.
+    Session { id: String },
+    /// How a tool call was decided outside `yolo` mode.
+    Approval { tool: String, allowed: bool, reason: String },
+}
 
-        let status = String::from_utf8_lossy(&output.stdout).trim().to_string();
-        debug!("Current status for {}: {}", container_name, status);
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AgentStats {
+    pub input_tokens: Option<u64>,
+    pub output_tokens: Option<u64>,
+    pub duration_ms: Option<u64>,
+    pub tool_calls: Option<u64>,
+    pub cost_usd: Option<f64>,
+}
 
-        if status == "healthy" || status == "true" {
-            debug!("Container {} is ready.", container_name);
-            return Ok(());
+impl AgentStats {
+    /// One-line summary such as `12.3s · 1520 in / 230 out tokens · 3 tools`.
+    pub fn summary(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(ms) = self.duration_ms {
+            parts.push(format!("{:.1}s", ms as f64 / 1000.0));
+        }
+        match (self.input_tokens, self.output_tokens) {
+            (Some(input), Some(output)) => parts.push(format!("{} in / {} out tokens", input, output)),
+            (Some(input), None) => parts.push(format!("{} in tokens", input)),
+            (None, Some(output)) => parts.push(format!("{} out tokens", output)),
+            (None, None) => {}
+        }
+        if let Some(calls) = self.tool_calls {
+            parts.push(format!("{} tools", calls));
+        }
+        if let Some(cost) = self.cost_usd {
+            parts.push(format!("${:.4}", cost));
         }
+        parts.join(" · ")
+    }
+}
 
-        std::thread::sleep(std::time::Duration::from_millis(200));
+/// Short label for a tool call: the name plus the most telling parameter.
+pub fn tool_label(name: &str, parameters: &serde_json::Value) -> String {
+    let detail = ["command", "file_path", "absolute_path", "path", "pattern", "url", "query"]
+        .iter()
+        .find_map(|key| parameters[*key].as_str());
+    match detail {
+        Some(detail) => format!("{} ({})", name, detail),
+        None => name.to_string(),
     }
+}
 
-    anyhow::bail!("Timeout waiting for container {} to be ready", container_name);
+/// The assistant text of a run. Text separated by tool calls becomes
+/// separate paragraphs.
+pub fn assistant_text(events: &[AgentEvent]) -> String {
+    let mut text = String::new();
+    let mut after_other = false;
+    for event in events {
+        match event {
+            AgentEvent::Text { text: fragment } => {
+                if after_other && !text.is_empty() && !text.ends_with("\n\n") {
+                    text.push_str("\n\n");
+                }
+                text.push_str(fragment);
+                after_other = false;
+            }
+            _ => after_other = true,
+        }
+    }
+    text.trim().to_string()
 }
 
-pub fn run_container_agent(
-    _group: &RegisteredGroup,
-    input: &ContainerInput,
-) -> Result<ContainerOutput> {
-    let start_time = Instant::now();
+/// The last session id the agent reported.
+pub fn agent_session_id(events: &[AgentEvent]) -> Option<String> {
+    events.iter().rev().find_map(|event| match event {
+        AgentEvent::Session { id } => Some(id.clone()),
+        _ => None,
+    })
+}
+
+/// An agent CLI rclaw can run inside a group container.
+pub trait AgentBackend: Send + Sync {
+    /// Name used in the configuration and on the command line.
+    fn name(&self) -> &'static str;
+    /// Image the group containers of this backend are created from.
+    fn image(&self) -> &'static str;
+    /// Command exec'd in the container for each prompt. It reads the
+    /// `ContainerInput` on stdin and streams the CLI's output on stdout.
+    fn command(&self) -> Vec<String>;
+    /// Container directory the CLI keeps its settings and sessions in. Each
+    /// group has its own, so the host's credential files are never mounted.
+    fn state_dir(&self) -> &'static str;
+    /// Environment of a run: the access token the CLI authenticates with and
+    /// whatever it needs to find its state in `state_dir`.
+    fn run_env(&self, token: Option<&str>) -> Vec<String>;
+    /// Events carried by one line of the command's stdout.
+    fn decode_line(&self, line: &str) -> Vec<AgentEvent>;
+    /// Hosts the CLI talks to for the model and its credentials. They stay
+    /// reachable in the restricted network modes.
+    fn api_hosts(&self) -> &'static [&'static str];
+}
+
+pub struct GeminiBackend;
+
+impl AgentBackend for GeminiBackend {
+    fn name(&self) -> &'static str {
+        "gemini"
+    }
+
+    fn image(&self) -> &'static str {
+        "rclaw-agent-gemini:latest"
+    }
+
+    fn command(&self) -> Vec<String> {
+        vec!["node".to_string(), "/home/rclaw/entrypoint.js".to_string(), "gemini".to_string()]
+    }
+
+    fn state_dir(&self) -> &'static str {
+        "/home/rclaw/.gemini"
+    }
+
+    /// The CLI takes a bare access token for Google login from the environment.
+    fn run_env(&self, token: Option<&str>) -> Vec<String> {
+        match token {
+            Some(token) => vec![
+                "GOOGLE_GENAI_USE_GCA=true".to_string(),
+                format!("GOOGLE_CLOUD_ACCESS_TOKEN={}", token),
+            ],
+            None => Vec::new(),
+        }
+    }
+
+    fn api_hosts(&self) -> &'static [&'static str] {
+        &[
+            "generativelanguage.googleapis.com",
+            "cloudcode-pa.googleapis.com",
+            "oauth2.googleapis.com",
+            "www.googleapis.com",
+        ]
+    }
+
+    fn decode_line(&self, line: &str) -> Vec<AgentEvent> {
+        let Ok(val) = serde_json::from_str::<serde_json::Value>(line) else {
+            return Vec::new();
+        };
+        let event = match val["type"].as_str().unwrap_or("") {
+            "init" => val["session_id"].as_str().map(|id| AgentEvent::Session { id: id.to_string() }),
+            "message" if val["role"].as_str() == Some("assistant") => {
+                val["content"].as_str().map(|c| AgentEvent::Text { text: c.to_string() })
+            }
+            "tool_use" => Some(AgentEvent::ToolUse {
+                name: val["tool_name"].as_str().unwrap_or("unknown").to_string(),
+                parameters: val["parameters"].clone(),
+            }),
+            "tool_result" => {
+                let is_error = val["status"].as_str() == Some("error");
+                let output = val["output"]
+                    .as_str()
+                    .or(val["error"]["message"].as_str())
+                    .unwrap_or_default();
+                Some(AgentEvent::ToolResult { output: output.to_string(), is_error })
+            }
+            "error" => Some(AgentEvent::Error {
+                message: val["message"].as_str().unwrap_or("Unknown error").to_string(),
+            }),
+            "result" if val["stats"].is_object() => {
+                let stats = &val["stats"];
+                Some(AgentEvent::Stats(AgentStats {
+                    input_tokens: stats["input_tokens"].as_u64(),
+                    output_tokens: stats["output_tokens"].as_u64(),
+                    duration_ms: stats["duration_ms"].as_u64(),
+                    tool_calls: stats["tool_calls"].as_u64(),
+                    cost_usd: None,
+                }))
+            }
+            _ => None,
+        };
+        event.into_iter().collect()
+    }
+}
+
+pub struct ClaudeCodeBackend;
+
+impl AgentBackend for ClaudeCodeBackend {
+    fn name(&self) -> &'static str {
+        "claude"
+    }
+
+    fn image(&self) -> &'static str {
+        "rclaw-agent-claude:latest"
+    }
+
+    fn command(&self) -> Vec<String> {
+        vec!["node".to_string(), "/home/rclaw/entrypoint.js".to_string(), "claude".to_string()]
+    }
+
+    fn state_dir(&self) -> &'static str {
+        "/home/rclaw/.claude"
+    }
+
+    /// `CLAUDE_CONFIG_DIR` also moves `.claude.json`, which would otherwise
+    /// live in the read-only home directory.
+    fn run_env(&self, token: Option<&str>) -> Vec<String> {
+        let mut env = vec![format!("CLAUDE_CONFIG_DIR={}", self.state_dir())];
+        if let Some(token) = token {
+            env.push(format!("CLAUDE_CODE_OAUTH_TOKEN={}", token));
+        }
+        env
+    }
+
+    fn api_hosts(&self) -> &'static [&'static str] {
+        &["api.anthropic.com", "console.anthropic.com", "statsig.anthropic.com"]
+    }
+
+    fn decode_line(&self, line: &str) -> Vec<AgentEvent> {
+        let Ok(val) = serde_json::from_str::<serde_json::Value>(line) else {
+            return Vec::new();
+        };
+        let role = val["type"].as_str().unwrap_or("");
+        if role == "system" && val["subtype"].as_str() == Some("init") {
+            let session = val["session_id"].as_str().map(|id| AgentEvent::Session { id: id.to_string() });
+            return session.into_iter().collect();
+        }
+        if role == "result" {
+            let mut events = Vec::new();
+            if val["is_error"].as_bool() == Some(true) {
+                let message = val["result"].as_str().or(val["subtype"].as_str()).unwrap_or("Unknown error");
+                events.push(AgentEvent::Error { message: message.to_string() });
+            }
+            events.push(AgentEvent::Stats(AgentStats {
+                input_tokens: val["usage"]["input_tokens"].as_u64(),
+                output_tokens: val["usage"]["output_tokens"].as_u64(),
+                duration_ms: val["duration_ms"].as_u64(),
+                tool_calls: None,
+                cost_usd: val["total_cost_usd"].as_f64(),
+            }));
+            return events;
+        }
+        // Assistant and user messages carry a list of content blocks
+        if role != "assistant" && role != "user" {
+            return Vec::new();
+        }
+        let Some(blocks) = val["message"]["content"].as_array() else {
+            return Vec::new();
+        };
+
+        blocks
+            .iter()
+            .filter_map(|block| match block["type"].as_str().unwrap_or("") {
+                "text" if role == "assistant" => {
+                    block["text"].as_str().map(|t| AgentEvent::Text { text: t.to_string() })
+                }
+                "tool_use" => Some(AgentEvent::ToolUse {
+                    name: block["name"].as_str().unwrap_or("unknown").to_string(),
+                    parameters: block["input"].clone(),
+                }),
+                "tool_result" => Some(AgentEvent::ToolResult {
+                    output: claude_tool_output(&block["content"]),
+                    is_error: block["is_error"].as_bool().unwrap_or(false),
+                }),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// Tool results are either a string or a list of text blocks.
+fn claude_tool_output(content: &serde_json::Value) -> String {
+    match content {
+        serde_json::Value::String(s) => s.clone(),
+        serde_json::Value::Array(parts) => parts
+            .iter()
+            .filter_map(|p| p["text"].as_str())
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
+    }
+}
+
+static GEMINI: GeminiBackend = GeminiBackend;
+static CLAUDE_CODE: ClaudeCodeBackend = ClaudeCodeBackend;
+
+/// Looks up a backend by the name used in the configuration.
+pub fn agent_backend(name: &str) -> Result<&'static dyn AgentBackend> {
+    match name {
+        "gemini" => Ok(&GEMINI),
+        "claude" => Ok(&CLAUDE_CODE),
+        other => anyhow::bail!("Unknown agent backend '{}' (expected gemini or claude)", other),
+    }
+}
+
+/// Splits a leading `@<backend>` off a prompt, if it names a known backend.
+pub fn split_backend_prefix(prompt: &str) -> Option<(&'static dyn AgentBackend, &str)> {
+    let rest = prompt.strip_prefix('@')?;
+    let (name, rest) = rest.split_once(char::is_whitespace)?;
+    let backend = agent_backend(name).ok()?;
+    Some((backend, rest.trim_start()))
+}
+
+/// Folder of the group that administers rclaw (the TUI chat).
+pub const MAIN_GROUP: &str = "main";
+
+/// Label that marks the containers rclaw manages, with the group folder as value.
+pub const GROUP_LABEL: &str = "rclaw.group";
+
+/// Label holding a hash of the hardening settings a container was created with.
+pub const PROFILE_LABEL: &str = "rclaw.profile";
+
+/// Label holding the network mode of a container and a hash of its network settings.
+pub const NETWORK_LABEL: &str = "rclaw.network";
+
+/// Marks the stopped containers kept ready for a group's ephemeral runs.
+pub const POOL_LABEL: &str = "rclaw.pool";
+
+/// Id of the image a container was created from, so it is recreated once the
+/// image is rebuilt.
+pub const IMAGE_LABEL: &str = "rclaw.image";
+
+/// Marks containers whose agent home is the group's own directory, rather
+/// than the host's credential directories older versions mounted.
+pub const HOME_LABEL: &str = "rclaw.home";
+
+/// Where the scratch overlay of an ephemeral run is mounted.
+const SCRATCH_OVERLAY: &str = "/home/rclaw/scratch";
+
+/// Writable scratch areas of a container with a read-only root filesystem.
+const SCRATCH_DIRS: &[&str] = &["/tmp", "/home/rclaw/.cache", "/home/rclaw/.npm"];
+
+/// Group folders become directory and container names, so they are kept simple.
+pub fn is_valid_group_folder(folder: &str) -> bool {
+    !folder.is_empty()
+        && folder
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Host directory of a group's workspace (`workspace/<folder>`).
+pub fn group_workspace(folder: &str) -> Result<PathBuf> {
+    if !is_valid_group_folder(folder) {
+        anyhow::bail!("Invalid group folder '{}'", folder);
+    }
     let project_root = std::env::current_dir().context("Failed to get current dir")?;
-    let home_dir = dirs::home_dir().context("Failed to get home dir")?;
-    let container_name = "rclaw-agent-singleton";
+    Ok(project_root.join("workspace").join(folder))
+}
 
-    info!("Ensuring rclaw-agent container is ready: {}", container_name);
+/// Fails unless `folder` is the main group, has a `[groups.<folder>]` section
+/// or already has a workspace, so typos are caught before anything runs there.
+pub fn check_known_group(config: &Config, folder: &str) -> Result<()> {
+    let workspace = group_workspace(folder)?;
+    if folder != MAIN_GROUP && !config.groups.contains_key(folder) && !workspace.is_dir() {
+        anyhow::bail!(
+            "Unknown group '{}' (add [groups.{}] to the config or create {})",
+            folder,
+            folder,
+            workspace.display()
+        );
+    }
+    Ok(())
+}
 
-    // Prepare mounts
-    let group_dir = project_root.join("workspace");
+/// Creates the group's workspace if needed. The main group takes over the
+/// `workspace/memory` directory used when all groups shared one workspace.
+pub fn ensure_group_workspace(folder: &str) -> Result<PathBuf> {
+    let group_dir = group_workspace(folder)?;
     if !group_dir.exists() {
         fs::create_dir_all(&group_dir)?;
+        let legacy_memory = group_dir.with_file_name("memory");
+        if folder == MAIN_GROUP && legacy_memory.is_dir() {
+            info!("Moving {} into the main group workspace", legacy_memory.display());
+            fs::rename(&legacy_memory, group_dir.join("memory"))?;
+        }
+    }
+    Ok(group_dir)
+}
+
+/// Host directory backing the agent CLI's state directory in the group's
+/// containers, `homes/<folder>/<backend>`.
+pub fn ensure_group_home(group: &RegisteredGroup) -> Result<PathBuf> {
+    if !is_valid_group_folder(&group.folder) {
+        anyhow::bail!("Invalid group folder '{}'", group.folder);
+    }
+    let project_root = std::env::current_dir().context("Failed to get current dir")?;
+    let home = project_root.join("homes").join(&group.folder).join(group.backend.name());
+    fs::create_dir_all(&home).with_context(|| format!("Failed to create {}", home.display()))?;
+    Ok(home)
+}
+
+/// Name of the container that runs the agents of a group with its backend.
+pub fn group_container_name(group: &RegisteredGroup) -> Result<String> {
+    if !is_valid_group_folder(&group.folder) {
+        anyhow::bail!("Invalid group folder '{}'", group.folder);
+    }
+    Ok(format!("rclaw-agent-{}-{}", group.folder, group.backend.name()))
+}
+
+/// When each group container was last used, so idle ones can be stopped.
+struct ContainerUsage {
+    active_runs: usize,
+    last_used: Instant,
+}
+
+fn container_usage() -> &'static Mutex<HashMap<String, ContainerUsage>> {
+    static USAGE: OnceLock<Mutex<HashMap<String, ContainerUsage>>> = OnceLock::new();
+    USAGE.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Marks a container as busy for the duration of a run.
+struct ContainerLease(String);
+
+impl ContainerLease {
+    fn acquire(container_name: &str) -> Self {
+        let mut usage = container_usage().lock().unwrap();
+        let entry = usage.entry(container_name.to_string()).or_insert(ContainerUsage {
+            active_runs: 0,
+            last_used: Instant::now(),
+        });
+        entry.active_runs += 1;
+        ContainerLease(container_name.to_string())
+    }
+}
+
+impl Drop for ContainerLease {
+    fn drop(&mut self) {
+        let mut usage = container_usage().lock().unwrap();
+        if let Some(entry) = usage.get_mut(&self.0) {
+            entry.active_runs = entry.active_runs.saturating_sub(1);
+            entry.last_used = Instant::now();
+        }
     }
+}
+
+/// Stops group containers that have not run an agent for `idle`. Containers
+/// started by another rclaw process are picked up and timed from now.
+pub fn stop_idle_containers(runtime: &dyn ContainerRuntime, idle: Duration) -> Result<()> {
+    let docker = runtime.client();
+    let running = docker
+        .list_containers(false, GROUP_LABEL)
+        .context("Failed to list group containers")?;
 
-    // 1. Check container existence and status
-    let check_container = Command::new("docker")
-        .args(&["inspect", "-f", "{{.State.Status}}", container_name])
-        .output();
-
-    let mut needs_wait = false;
-
-    match check_container {
-        Ok(output) if output.status.success() => {
-            let status = String::from_utf8_lossy(&output.stdout).trim().to_string();
-            if status != "running" {
-                info!("Starting existing container: {}", container_name);
-                Command::new("docker")
-                    .args(&["start", container_name])
-                    .stdout(Stdio::null())
-                    .stderr(Stdio::null())
-                    .status()?;
-                needs_wait = true;
+    let mut usage = container_usage().lock().unwrap();
+    for container in running {
+        let name = container.name.as_str();
+        let entry = usage.entry(name.to_string()).or_insert(ContainerUsage {
+            active_runs: 0,
+            last_used: Instant::now(),
+        });
+        if entry.active_runs == 0 && entry.last_used.elapsed() >= idle {
+            if has_running_exec(&docker, name) {
+                // An agent started by another rclaw process is still working
+                entry.last_used = Instant::now();
+                continue;
+            }
+            info!("Stopping idle container {}", name);
+            match docker.stop_container(name) {
+                Ok(()) => {
+                    usage.remove(name);
+                }
+                Err(e) => warn!("Failed to stop idle container {}: {}", name, e),
             }
         }
-        _ => {
+    }
+    Ok(())
+}
+
+pub fn has_running_exec(docker: &Docker, container_name: &str) -> bool {
+    docker
+        .inspect_container(container_name)
+        .map(|c| c["ExecIDs"].as_array().is_some_and(|ids| !ids.is_empty()))
+        .unwrap_or(false)
+}
+
+/// Removes every group container (plus the egress proxy and the shared
+/// container of older versions) so they are recreated from a freshly built image.
+pub fn remove_agent_containers(runtime: &dyn ContainerRuntime) -> Result<()> {
+    let docker = runtime.client();
+    let listed = docker
+        .list_containers(true, GROUP_LABEL)
+        .context("Failed to list group containers")?;
+    let names = listed
+        .iter()
+        .map(|c| c.name.as_str())
+        .chain(["rclaw-agent-singleton", PROXY_CONTAINER]);
+
+    for name in names {
+        match docker.remove_container(name, true) {
+            Ok(()) | Err(DockerError::NotFound(_)) => {}
+            Err(e) => warn!("Failed to remove container {}: {}", name, e),
+        }
+    }
+    Ok(())
+}
+
+/// Brings the group's container to a running, healthy state: creates it if
+/// missing, starts it if stopped and restarts it if its health check fails.
+fn ensure_group_container(
+    docker: &Docker,
+    container_name: &str,
+    group: &RegisteredGroup,
+    group_dir: &Path,
+) -> Result<()> {
+    let spec = container_spec(docker, group)?;
+
+    let container = match docker.inspect_container(container_name) {
+        Ok(container) => container,
+        Err(DockerError::NotFound(_)) => {
             info!("Container {} not found. Creating...", container_name);
-            
-            let gemini_config_v1 = home_dir.join(".gemini");
-            let gemini_config_v2 = home_dir.join(".config").join("gemini");
-            
-            let mut args = vec![
-                "run".to_string(),
-                "-d".to_string(),
-                "--name".to_string(), container_name.to_string(),
-                "-v".to_string(), format!("{}:/home/rclaw/workspace", group_dir.display()),
-                "-w".to_string(), "/home/rclaw/workspace".to_string(),
-                "-u".to_string(), format!("{}:{}", unsafe { libc::getuid() }, unsafe { libc::getgid() }),
-                "-e".to_string(), "HOME=/home/rclaw".to_string(),
-            ];
-
-            if gemini_config_v1.exists() {
-                args.push("-v".to_string());
-                args.push(format!("{}:/home/rclaw/.gemini", gemini_config_v1.display()));
+            create_group_container(docker, container_name, group, group_dir, &spec)?;
+            start_container(docker, container_name)?;
+            return wait_for_container_ready(docker, container_name);
+        }
+        Err(e) => return Err(e).with_context(|| format!("Failed to inspect container {}", container_name)),
+    };
+
+    let outdated = !spec.matches(|label| container["Config"]["Labels"][label].as_str());
+    if outdated && !has_running_exec(docker, container_name) {
+        info!("Image or isolation profile of {} changed, recreating it", container_name);
+        docker
+            .remove_container(container_name, true)
+            .with_context(|| format!("Failed to remove container {}", container_name))?;
+        create_group_container(docker, container_name, group, group_dir, &spec)?;
+        start_container(docker, container_name)?;
+        return wait_for_container_ready(docker, container_name);
+    }
+
+    let status = container["State"]["Status"].as_str().unwrap_or("");
+    let health = container["State"]["Health"]["Status"].as_str().unwrap_or("");
+    match (status, health) {
+        ("running", "unhealthy") => {
+            warn!("Container {} is unhealthy, restarting it", container_name);
+            docker
+                .restart_container(container_name)
+                .with_context(|| format!("Failed to restart container {}", container_name))?;
+        }
+        ("running", _) => return Ok(()),
+        _ => {
+            info!("Starting existing container: {}", container_name);
+            start_container(docker, container_name)?;
+        }
+    }
+
+    wait_for_container_ready(docker, container_name)
+}
+
+/// How a group's containers are created, with the labels that tell whether
+/// an existing container still matches it.
+#[derive(Clone)]
+struct ContainerSpec {
+    host_config: serde_json::Value,
+    network: NetworkSpec,
+    labels: Vec<(&'static str, String)>,
+}
+
+impl ContainerSpec {
+    fn matches<'a>(&self, label: impl Fn(&str) -> Option<&'a str>) -> bool {
+        self.labels.iter().all(|(name, value)| label(name) == Some(value.as_str()))
+    }
+}
+
+fn container_spec(docker: &Docker, group: &RegisteredGroup) -> Result<ContainerSpec> {
+    let mut host_config = hardening_host_config(&group.hardening)?;
+    if group.mode == ContainerMode::Ephemeral && group.ephemeral.scratch_overlay {
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        let options = format!(
+            "rw,nosuid,nodev,size={},uid={},gid={}",
+            group.hardening.tmpfs_size.trim(),
+            uid,
+            gid
+        );
+        host_config["Tmpfs"][SCRATCH_OVERLAY] = json!(options);
+    }
+    let network = prepare_network(docker, group)?;
+    let image = match docker.inspect_image(group.backend.image()) {
+        Ok(image) => image["Id"].as_str().unwrap_or_default().to_string(),
+        Err(DockerError::NotFound(_)) => {
+            anyhow::bail!("The {} image is not built (run 'rclaw setup')", group.backend.image())
+        }
+        Err(e) => return Err(e).with_context(|| format!("Failed to inspect image {}", group.backend.image())),
+    };
+    let labels = vec![
+        (PROFILE_LABEL, profile_hash(host_config.to_string())),
+        (
+            NETWORK_LABEL,
+            format!("{}-{}", group.network.mode, profile_hash(serde_json::to_string(&network)?)),
+        ),
+        (HOME_LABEL, "group".to_string()),
+        (IMAGE_LABEL, image),
+    ];
+    Ok(ContainerSpec {
+        host_config,
+        network,
+        labels,
+    })
+}
+
+/// `HostConfig` fields that apply a hardening profile.
+pub fn hardening_host_config(profile: &HardeningConfig) -> Result<serde_json::Value> {
+    let mut host = serde_json::Map::new();
+    if let Some(bytes) = profile.memory_bytes()? {
+        host.insert("Memory".to_string(), json!(bytes));
+    }
+    if profile.cpus > 0.0 {
+        host.insert("NanoCpus".to_string(), json!((profile.cpus * 1e9) as i64));
+    }
+    if profile.pids_limit > 0 {
+        host.insert("PidsLimit".to_string(), json!(profile.pids_limit));
+    }
+    if profile.cap_drop_all {
+        host.insert("CapDrop".to_string(), json!(["ALL"]));
+    }
+    let mut security_opt = Vec::new();
+    if profile.no_new_privileges {
+        security_opt.push("no-new-privileges".to_string());
+    }
+    match profile.seccomp.as_str() {
+        "default" => {}
+        "unconfined" => security_opt.push("seccomp=unconfined".to_string()),
+        // The API takes the profile itself rather than its path
+        path => {
+            let content = fs::read_to_string(path)
+                .with_context(|| format!("Failed to read seccomp profile {}", path))?;
+            let parsed: serde_json::Value = serde_json::from_str(&content)
+                .with_context(|| format!("Invalid seccomp profile {}", path))?;
+            security_opt.push(format!("seccomp={}", parsed));
+        }
+    }
+    if !security_opt.is_empty() {
+        host.insert("SecurityOpt".to_string(), json!(security_opt));
+    }
+    if profile.read_only_rootfs {
+        host.insert("ReadonlyRootfs".to_string(), json!(true));
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        let tmpfs: serde_json::Map<String, serde_json::Value> = SCRATCH_DIRS
+            .iter()
+            .map(|dir| {
+                let owner = if *dir == "/tmp" {
+                    "mode=1777".to_string()
+                } else {
+                    format!("uid={},gid={}", uid, gid)
+                };
+                let options = format!("rw,nosuid,nodev,size={},{}", profile.tmpfs_size.trim(), owner);
+                (dir.to_string(), json!(options))
+            })
+            .collect();
+        host.insert("Tmpfs".to_string(), serde_json::Value::Object(tmpfs));
+    }
+    Ok(serde_json::Value::Object(host))
+}
+
+/// Short stable hash (FNV-1a) of part of a container's configuration, stored
+/// as a label so a change is noticed.
+pub fn profile_hash(config: impl AsRef<[u8]>) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for &byte in config.as_ref() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)
+}
+
+/// Creates, without starting, a container for the group.
+fn create_group_container(
+    docker: &Docker,
+    container_name: &str,
+    group: &RegisteredGroup,
+    group_dir: &Path,
+    spec: &ContainerSpec,
+) -> Result<()> {
+    // Only the group's own folders are visible inside its container
+    let home = ensure_group_home(group)?;
+    let binds = vec![
+        format!("{}:/home/rclaw/workspace", group_dir.display()),
+        format!("{}:{}", home.display(), group.backend.state_dir()),
+    ];
+
+    let mut host_config = spec.host_config.clone();
+    host_config["Binds"] = json!(binds);
+    if let Some(mode) = &spec.network.network_mode {
+        host_config["NetworkMode"] = json!(mode);
+    }
+
+    let mut env = vec!["HOME=/home/rclaw".to_string()];
+    env.extend(spec.network.env.iter().cloned());
+
+    let mut labels = serde_json::Map::new();
+    labels.insert(GROUP_LABEL.to_string(), json!(group.folder));
+    for (label, value) in &spec.labels {
+        labels.insert(label.to_string(), json!(value));
+    }
+
+    let mut body = json!({
+        "Image": group.backend.image(),
+        "Cmd": ["tail", "-f", "/dev/null"],
+        "WorkingDir": "/home/rclaw/workspace",
+        "Env": env,
+        "Labels": labels,
+        "HostConfig": host_config,
+    });
+    group.runtime.map_user(&mut body);
+
+    docker
+        .create_container(container_name, &body)
+        .with_context(|| format!("Failed to create container {}", container_name))?;
+    Ok(())
+}
+
+fn start_container(docker: &Docker, container_name: &str) -> Result<()> {
+    docker
+        .start_container(container_name)
+        .with_context(|| format!("Failed to start container {}", container_name))
+}
+
+/// Gives an ephemeral run a started container of its own: a stopped one
+/// from the group's pool when there is one, a new one otherwise.
+fn claim_run_container(docker: &Docker, group: &RegisteredGroup, group_dir: &Path) -> Result<String> {
+    let base = group_container_name(group)?;
+    let run_name = format!("{}-run-{}", base, &uuid::Uuid::new_v4().simple().to_string()[..12]);
+    let spec = container_spec(docker, group)?;
+
+    let pooled = docker
+        .list_containers(true, &format!("{}={}", POOL_LABEL, base))
+        .context("Failed to list pooled containers")?;
+    let mut claimed = false;
+    for container in pooled {
+        if !container.name.starts_with(&format!("{}-pool-", base))
+            || container.state != "created"
+            || !spec.matches(|label| container.labels.get(label).map(|v| v.as_str()))
+        {
+            continue;
+        }
+        // Renaming is atomic, so another process cannot take the same one
+        match docker.rename_container(&container.name, &run_name) {
+            Ok(()) => {
+                debug!("Claimed pooled container {} as {}", container.name, run_name);
+                claimed = true;
+                break;
             }
-            if gemini_config_v2.exists() {
-                args.push("-v".to_string());
-                args.push(format!("{}:/home/rclaw/.config/gemini", gemini_config_v2.display()));
+            Err(DockerError::NotFound(_)) | Err(DockerError::Conflict(_)) => continue,
+            Err(e) => return Err(e).context("Failed to claim a pooled container"),
+        }
+    }
+    if !claimed {
+        info!("No pooled container for {}, creating {}", base, run_name);
+        create_group_container(docker, &run_name, group, group_dir, &spec)?;
+    }
+
+    if let Err(e) = start_container(docker, &run_name).and_then(|_| wait_for_container_ready(docker, &run_name)) {
+        let _ = docker.remove_container(&run_name, true);
+        return Err(e);
+    }
+    Ok(run_name)
+}
+
+/// Tops up the group's pool of stopped containers to `ephemeral.pool_size`,
+/// replacing the ones created with an outdated profile.
+pub fn warm_pool(group: &RegisteredGroup) -> Result<()> {
+    static REFILL: Mutex<()> = Mutex::new(());
+    let _refilling = REFILL.lock().unwrap();
+
+    let docker = group.runtime.client();
+    let base = group_container_name(group)?;
+    let group_dir = ensure_group_workspace(&group.folder)?;
+    let spec = container_spec(&docker, group)?;
+
+    let pooled = docker
+        .list_containers(true, &format!("{}={}", POOL_LABEL, base))
+        .context("Failed to list pooled containers")?;
+    let mut ready = 0;
+    for container in pooled.iter().filter(|c| c.name.starts_with(&format!("{}-pool-", base))) {
+        if spec.matches(|label| container.labels.get(label).map(|v| v.as_str())) {
+            ready += 1;
+        } else {
+            debug!("Removing outdated pooled container {}", container.name);
+            let _ = docker.remove_container(&container.name, true);
+        }
+    }
+
+    for _ in ready..group.ephemeral.pool_size {
+        let name = format!("{}-pool-{}", base, &uuid::Uuid::new_v4().simple().to_string()[..12]);
+        let mut spec = spec.clone();
+        spec.labels.push((POOL_LABEL, base.clone()));
+        create_group_container(&docker, &name, group, &group_dir, &spec)?;
+    }
+    Ok(())
+}
+
+/// Removes an ephemeral run's container, scratch overlay included, however
+/// the run ends, and tops the group's pool up for the next one.
+struct RunContainer<'a> {
+    docker: &'a Docker,
+    name: String,
+    group: RegisteredGroup,
+}
+
+impl Drop for RunContainer<'_> {
+    fn drop(&mut self) {
+        if let Err(e) = self.docker.remove_container(&self.name, true) {
+            warn!("Failed to remove run container {}: {}", self.name, e);
+        }
+        container_usage().lock().unwrap().remove(&self.name);
+
+        let group = self.group.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = warm_pool(&group) {
+                warn!("Failed to refill the container pool of '{}': {:#}", group.folder, e);
             }
+        });
+    }
+}
 
-            args.push("rclaw-agent:latest".to_string());
-            args.push("tail".to_string());
-            args.push("-f".to_string());
-            args.push("/dev/null".to_string());
-
-            let status = Command::new("docker")
-                .args(&args)
-                .stdout(Stdio::null())
-                .stderr(Stdio::null())
-                .status()?;
-            
-            if !status.success() {
-                return Ok(ContainerOutput {
-                    status: "error".to_string(),
-                    result: None,
-                    new_session_id: None,
-                    error: Some(format!("Failed to create container {}", container_name)),
-                });
+/// Lets another thread stop a running agent.
+#[derive(Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// The files changed since `before`, empty without a snapshot.
+fn workspace_changes(before: Option<&WorkspaceSnapshot>) -> Vec<Artifact> {
+    let Some(before) = before else {
+        return Vec::new();
+    };
+    match before.changes() {
+        Ok(artifacts) => artifacts,
+        Err(e) => {
+            warn!("Could not list the files the run changed: {:#}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Answers the requests the entrypoint writes among the agent's output
+/// lines (tool approvals, token refreshes) through the exec's stdin.
+struct Bridge {
+    stdin: ExecStdin,
+    backend: &'static dyn AgentBackend,
+    approver: Approver,
+    credentials: Option<CredentialStore>,
+}
+
+impl Bridge {
+    /// Handles `line` if it is a request, returning the events it produced.
+    fn answer(&mut self, line: &str) -> Option<Vec<AgentEvent>> {
+        // The entrypoint writes the type first; the agent's lines never have it
+        if !line.starts_with("{\"type\":\"rclaw_") {
+            return None;
+        }
+        let (reply, events) = if let Some(request) = ToolRequest::parse(line) {
+            let decision = self.approver.decide(&request);
+            let verdict = if decision.allow { "allowed" } else { "denied" };
+            info!("Tool call {} {}: {}", request.label(), verdict, decision.reason);
+            let event = AgentEvent::Approval {
+                tool: request.label(),
+                allowed: decision.allow,
+                reason: decision.reason.clone(),
+            };
+            (decision.reply(&request), vec![event])
+        } else {
+            let request: serde_json::Value = serde_json::from_str(line).ok()?;
+            if request["type"] != "rclaw_credentials" {
+                return None;
             }
-            needs_wait = true;
+            info!("The agent asked for a new {} token", self.backend.name());
+            let refreshed = match &self.credentials {
+                Some(store) => store.refresh(self.backend),
+                None => Err(anyhow::anyhow!("No credentials to refresh")),
+            };
+            let answer = match refreshed {
+                Ok(credentials) => json!({
+                    "id": request["id"],
+                    "env": self.backend.run_env(Some(&credentials.token)),
+                    "expires_at": credentials.expires_at.map(|at| at.timestamp()),
+                }),
+                Err(e) => {
+                    warn!("Could not refresh the {} token: {:#}", self.backend.name(), e);
+                    json!({ "id": request["id"], "error": format!("{:#}", e) })
+                }
+            };
+            (format!("{}\n", answer), Vec::new())
+        };
+        if let Err(e) = self.stdin.write(reply.as_bytes()) {
+            warn!("Could not answer the entrypoint: {}", e);
         }
+        Some(events)
     }
+}
+
+/// Reads the exec's output, decoding stdout line by line and forwarding
+/// every event as it arrives. Requests from the entrypoint among the lines
+/// go to the bridge. Returns all the events and the stderr text once the
+/// streams end.
+fn spawn_output_reader(
+    mut session: ExecSession,
+    backend: &'static dyn AgentBackend,
+    events: Option<Sender<AgentEvent>>,
+    mut bridge: Bridge,
+) -> JoinHandle<(Vec<AgentEvent>, String)> {
+    std::thread::spawn(move || {
+        let mut decoded = Vec::new();
+        let mut pending = Vec::new();
+        let mut stderr = Vec::new();
+        let mut decode = |line: &[u8], decoded: &mut Vec<AgentEvent>| {
+            let line = String::from_utf8_lossy(line);
+            let line = line.trim_end();
+            let found = bridge.answer(line).unwrap_or_else(|| backend.decode_line(line));
+            for event in found {
+                if let Some(tx) = &events {
+                    let _ = tx.send(event.clone());
+                }
+                decoded.push(event);
+            }
+        };
+
+        let read = session.read_output(|stream, data| match stream {
+            ExecStream::Stdout => {
+                pending.extend_from_slice(data);
+                while let Some(end) = pending.iter().position(|b| *b == b'\n') {
+                    let line: Vec<u8> = pending.drain(..=end).collect();
+                    decode(&line, &mut decoded);
+                }
+            }
+            ExecStream::Stderr => stderr.extend_from_slice(data),
+        });
+        if let Err(e) = read {
+            debug!("Agent output stream ended: {}", e);
+        }
+        if !pending.is_empty() {
+            decode(&pending, &mut decoded);
+        }
+        (decoded, String::from_utf8_lossy(&stderr).into_owned())
+    })
+}
 
-    // 2. Wait for readiness
-    if needs_wait {
-        wait_for_container_ready(container_name)?;
+/// Closing the exec connection does not stop the command, so the agent is
+/// signalled through the pid file its entrypoint writes.
+fn kill_agent_process(docker: &Docker, container_name: &str, run_id: &str) {
+    let script = format!("kill -TERM \"$(cat /tmp/rclaw-run-{}.pid)\"", run_id);
+    let cmd = ["sh".to_string(), "-c".to_string(), script];
+    match docker.run_exec(container_name, &cmd) {
+        Ok(0) => {}
+        Ok(code) => warn!("Could not signal agent run {} in {} (exit code {})", run_id, container_name, code),
+        Err(e) => warn!("Could not signal agent run {} in {}: {}", run_id, container_name, e),
     }
+}
+
+/// Waits for the container's health check to pass (or for it to run, if the
+/// image has none), following the daemon's events instead of polling.
+fn wait_for_container_ready(docker: &Docker, container_name: &str) -> Result<()> {
+    let start = Instant::now();
+    let timeout = Duration::from_secs(10);
 
-    // 3. Interaction via docker exec
-    debug!("Executing prompt in container via docker exec");
-    let mut child = Command::new("docker")
-        .args(&["exec", "-i", container_name, "node", "/home/rclaw/entrypoint.js"])
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .context("Failed to execute docker exec")?;
+    debug!("Waiting for container {} to be ready...", container_name);
+    let filters = json!({ "type": ["container"], "container": [container_name] });
+    let mut events = docker.events(&filters).context("Failed to subscribe to Docker events")?;
+
+    // Subscribed first, so a change right after this check is not missed
+    let is_ready = |container: &serde_json::Value| match container["State"]["Health"]["Status"].as_str() {
+        Some(health) => health == "healthy",
+        None => container["State"]["Running"].as_bool() == Some(true),
+    };
+    if is_ready(&docker.inspect_container(container_name)?) {
+        debug!("Container {} is ready.", container_name);
+        return Ok(());
+    }
 
-    // Send input via stdin
-    if let Some(mut stdin) = child.stdin.take() {
-        let input_json = serde_json::to_string(input)?;
-        stdin.write_all(input_json.as_bytes())?;
+    while let Some(remaining) = timeout.checked_sub(start.elapsed()) {
+        let Some(event) = events.next_event(remaining)? else {
+            break;
+        };
+        let action = event["Action"].as_str().unwrap_or("");
+        debug!("Event for {}: {}", container_name, action);
+        if action == "die" {
+            anyhow::bail!("Container {} exited while starting", container_name);
+        }
+        if (action == "start" || action.starts_with("health_status"))
+            && is_ready(&docker.inspect_container(container_name)?)
+        {
+            debug!("Container {} is ready.", container_name);
+            return Ok(());
+        }
     }
 
-    let mut stdout = String::new();
-    if let Some(mut stdout_stream) = child.stdout.take() {
-        stdout_stream.read_to_string(&mut stdout)?;
+    anyhow::bail!("Timeout waiting for container {} to be ready", container_name);
+}
+
+/// How a single agent run is supervised.
+#[derive(Default)]
+pub struct RunOptions {
+    /// The run is stopped when it takes longer than this.
+    pub timeout: Option<Duration>,
+    pub cancel: CancelToken,
+    /// Receives each event as soon as the agent emits it.
+    pub events: Option<Sender<AgentEvent>>,
+    /// Where tool calls are asked about in `prompt` approval mode; they are
+    /// denied when unset.
+    pub approvals: Option<Sender<ApprovalRequest>>,
+    /// Issues the agent's access token. Without it the CLI gets none.
+    pub credentials: Option<CredentialStore>,
+}
+
+/// Runs the agent for one prompt. It is stopped, killing the process inside the
+/// container, when the timeout elapses or the run is cancelled; the output then
+/// has status `timeout` or `cancelled`.
+pub fn run_container_agent(
+    group: &RegisteredGroup,
+    input: &ContainerInput,
+    options: &RunOptions,
+) -> Result<ContainerOutput> {
+    let start_time = Instant::now();
+    let docker = group.runtime.client();
+    let network = Some(group.network.mode.to_string());
+    let group_dir = match ensure_group_workspace(&group.folder) {
+        Ok(dir) => dir,
+        Err(e) => return Ok(ContainerOutput::failed(format!("{:#}", e), network, None)),
+    };
+
+    // 1-2. Get a running container: the group's own one, created, started or
+    // recovered as needed, or a fresh one just for this run
+    let prepared = match group.mode {
+        ContainerMode::Persistent => {
+            let container_name = group_container_name(group)?;
+            info!("Ensuring container for group '{}' is ready: {}", group.name, container_name);
+            let lease = ContainerLease::acquire(&container_name);
+            ensure_group_container(&docker, &container_name, group, &group_dir).map(|_| (container_name, lease))
+        }
+        ContainerMode::Ephemeral => {
+            info!("Starting a fresh container for group '{}'", group.name);
+            claim_run_container(&docker, group, &group_dir).map(|name| {
+                let lease = ContainerLease::acquire(&name);
+                (name, lease)
+            })
+        }
+    };
+    let (container_name, _lease) = match prepared {
+        Ok(prepared) => prepared,
+        Err(e) => return Ok(ContainerOutput::failed(format!("{:#}", e), network, None)),
+    };
+    let _run_container = (group.mode == ContainerMode::Ephemeral).then(|| RunContainer {
+        docker: &docker,
+        name: container_name.clone(),
+        group: group.clone(),
+    });
+    let container_name = container_name.as_str();
+    let image = docker
+        .inspect_container(container_name)
+        .ok()
+        .and_then(|c| c["Image"].as_str().map(|id| id.to_string()));
+
+    // 3. Interaction through an exec attached to the agent's streams
+    debug!("Executing prompt in container via exec");
+    // Identifies the exec'd process inside the container so it can be killed
+    let run_id = uuid::Uuid::new_v4().simple().to_string();
+    let mut env = vec![format!("RCLAW_RUN_ID={}", run_id)];
+    if group.approval.mode != ApprovalMode::Yolo {
+        // The entrypoint then has the CLI ask before each tool call
+        env.push(format!("RCLAW_APPROVAL_TIMEOUT={}", group.approval.timeout_secs));
+    }
+    // A token for this run only, short-lived where the backend allows it;
+    // the entrypoint asks for a new one through the bridge if it expires
+    let credentials = match &options.credentials {
+        Some(store) => match store.issue(group.backend) {
+            Ok(credentials) => Some(credentials),
+            Err(e) => {
+                let error = format!("Failed to get {} credentials: {:#}", group.backend.name(), e);
+                return Ok(ContainerOutput {
+                    failure: Some(FailureCategory::Auth),
+                    ..ContainerOutput::failed(error, network, image)
+                });
+            }
+        },
+        None => {
+            warn!("No credential store for this run; the agent gets no token");
+            None
+        }
+    };
+    env.extend(group.backend.run_env(credentials.as_ref().map(|c| c.token.as_str())));
+    if let Some(expires_at) = credentials.as_ref().and_then(|c| c.expires_at) {
+        env.push(format!("RCLAW_TOKEN_EXPIRES_AT={}", expires_at.timestamp()));
     }
+    // What the workspace held before, to tell which files the run produced
+    let before = match WorkspaceSnapshot::take(&group_dir) {
+        Ok(snapshot) => Some(snapshot),
+        Err(e) => {
+            warn!("Could not snapshot the workspace of '{}': {:#}", group.name, e);
+            None
+        }
+    };
+    let started = (|| -> Result<_> {
+        let exec_id = docker
+            .create_exec(container_name, &group.backend.command(), &env, true)
+            .with_context(|| format!("Failed to create the agent exec in {}", container_name))?;
+        let session = docker
+            .start_exec(&exec_id)
+            .with_context(|| format!("Failed to start the agent exec in {}", container_name))?;
+        // The input is the first line; stdin stays open for the bridge's answers
+        let mut stdin = session.stdin()?;
+        stdin.write(format!("{}\n", serde_json::to_string(input)?).as_bytes())?;
+        let closer = session.closer()?;
+        Ok((exec_id, session, stdin, closer))
+    })();
+    let (exec_id, session, stdin, closer) = match started {
+        Ok(started) => started,
+        Err(e) => return Ok(ContainerOutput::failed(format!("{:#}", e), network, image)),
+    };
+    let stop = CancelToken::new();
+    let bridge = Bridge {
+        stdin,
+        backend: group.backend,
+        approver: Approver {
+            config: group.approval.clone(),
+            group: group.name.clone(),
+            prompter: options.approvals.clone(),
+            stop: stop.clone(),
+        },
+        credentials: options.credentials.clone(),
+    };
+
+    // Read the output in the background so the wait below can give up on a hung agent
+    let reader = spawn_output_reader(session, group.backend, options.events.clone(), bridge);
+
+    let stop_reason = loop {
+        if reader.is_finished() {
+            break None;
+        }
+        if options.cancel.is_cancelled() {
+            break Some(("cancelled", "Cancelled by the user".to_string()));
+        }
+        if let Some(limit) = options.timeout {
+            if start_time.elapsed() >= limit {
+                break Some(("timeout", format!("Agent timed out after {}s", limit.as_secs())));
+            }
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    };
 
-    let mut stderr = String::new();
-    if let Some(mut stderr_stream) = child.stderr.take() {
-        stderr_stream.read_to_string(&mut stderr)?;
+    if let Some((status, reason)) = stop_reason {
+        warn!("Stopping agent run {} in {}: {}", run_id, container_name, reason);
+        stop.cancel();
+        kill_agent_process(&docker, container_name, &run_id);
+        closer.close();
+        // Keep what the agent got through before it was stopped
+        let (events, stderr) = reader.join().unwrap_or_default();
+        return Ok(ContainerOutput {
+            status: status.to_string(),
+            result: None,
+            new_session_id: agent_session_id(&events),
+            error: Some(reason),
+            exit_code: None,
+            network: network.clone(),
+            image: image.clone(),
+            diagnostics: parse_stderr(&stderr, &group.diagnostics.ignore),
+            failure: None,
+            artifacts: workspace_changes(before.as_ref()),
+            events,
+        });
     }
 
-    let status = child.wait()?;
+    let (events, stderr) = reader.join().unwrap_or_default();
+    let exit_code = match docker.exec_exit_code(&exec_id).context("Failed to get the agent's exit code") {
+        Ok(code) => code as i32,
+        Err(e) => return Ok(ContainerOutput::failed(format!("{:#}", e), network, image)),
+    };
 
     let duration = start_time.elapsed();
     info!("Exec command finished in {:?}", duration);
 
-    // Filtrar ruidos de stderr
-    let filtered_stderr = stderr
-        .lines()
-        .filter(|line| {
-            !line.contains("DeprecationWarning")
-                && !line.contains("punycode")
-                && !line.contains("YOLO mode")
-                && !line.contains("Loaded cached credentials")
-        })
-        .collect::<Vec<_>>()
-        .join("\n");
+    // The noise the configuration filters out never reaches the diagnostics
+    let diagnostics = parse_stderr(&stderr, &group.diagnostics.ignore);
 
-    if !status.success() {
+    if exit_code != 0 {
+        let failure = failure_category(&diagnostics, &events);
+        let error = match failure {
+            Some(category) => format!(
+                "Agent failed, {}: {} (exit code: {})\n{}",
+                category,
+                category.hint(),
+                exit_code,
+                diagnostics_text(&diagnostics)
+            ),
+            None => format!("Container error (exit code: {}): {}", exit_code, diagnostics_text(&diagnostics)),
+        };
         return Ok(ContainerOutput {
             status: "error".to_string(),
             result: None,
-            new_session_id: None,
-            error: Some(format!("Container error (exit status: {}): {}", status, filtered_stderr)),
+            new_session_id: agent_session_id(&events),
+            error: Some(error),
+            exit_code: Some(exit_code),
+            network: network.clone(),
+            image: image.clone(),
+            diagnostics,
+            failure,
+            artifacts: workspace_changes(before.as_ref()),
+            events,
         });
     }
 
-    // --- Procesamiento robusto del stream-json ---
-    let mut final_result = String::new();
-    let mut last_type = String::new();
-
-    for line in stdout.lines() {
-        if let Ok(val) = serde_json::from_str::<serde_json::Value>(line) {
-            let msg_type = val["type"].as_str().unwrap_or("");
-            
-            match msg_type {
-                "message" => {
-                    if let Some(content) = val["content"].as_str() {
-                        if val["role"].as_str() == Some("assistant") {
-                            // Separar bloques con UN espacio horizontal (doble \n)
-                            if last_type == "tool_use" || last_type == "tool_result" {
-                                if !final_result.is_empty() && !final_result.ends_with("\n\n") {
-                                    final_result.push_str("\n\n");
-                                }
-                            }
-                            final_result.push_str(content);
-                            last_type = "message".to_string();
-                        }
-                    }
-                }
-                "tool_use" => {
-                    let tool_name = val["tool_name"].as_str().unwrap_or("unknown");
-                    let mut tool_desc = tool_name.to_string();
-                    if let Some(params) = val["parameters"].as_object() {
-                        if let Some(cmd) = params.get("command").and_then(|c| c.as_str()) {
-                            tool_desc = format!("{} ({})", tool_name, cmd);
-                        }
-                    }
-                    
-                    if !final_result.is_empty() && !final_result.ends_with("\n\n") {
-                        final_result.push_str("\n\n");
-                    }
-                    final_result.push_str(&format!("[RCLAW_USE_TOOL]{}", tool_desc));
-                    last_type = "tool_use".to_string();
-                }
-                "tool_result" => {
-                    if let Some(output) = val["output"].as_str() {
-                        if !final_result.is_empty() && !final_result.ends_with("\n\n") {
-                            final_result.push_str("\n\n");
-                        }
-                        // Usamos un marcador de fin explícito para que el parser no se trague el texto siguiente
-                        final_result.push_str(&format!("[RCLAW_TOOL_RESULT]{}[RCLAW_END_RESULT]", output));
-                        last_type = "tool_result".to_string();
-                    }
-                }
-                _ => {}
-            }
-        }
-    }
-
     Ok(ContainerOutput {
         status: "success".to_string(),
-        result: Some(final_result.trim().to_string()),
-        new_session_id: None,
+        result: Some(assistant_text(&events)),
+        new_session_id: agent_session_id(&events),
         error: None,
+        exit_code: Some(exit_code),
+        network,
+        image,
+        diagnostics,
+        failure: None,
+        artifacts: workspace_changes(before.as_ref()),
+        events,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(backend: &dyn AgentBackend, lines: &[&str]) -> Vec<AgentEvent> {
+        lines.iter().flat_map(|l| backend.decode_line(l)).collect()
+    }
+
+    #[test]
+    fn decodes_gemini_stream() {
+        let events = decode(
+            &GeminiBackend,
+            &[
+                r#"{"type":"init","session_id":"s1"}"#,
+                r#"{"type":"message","role":"user","content":"hi"}"#,
+                r#"{"type":"tool_use","tool_name":"run_shell_command","parameters":{"command":"ls"}}"#,
+                r#"{"type":"tool_result","status":"success","output":"notes.md"}"#,
+                r#"{"type":"message","role":"assistant","content":"One file."}"#,
+                r#"{"type":"result","status":"success","stats":{"input_tokens":120,"output_tokens":8,"duration_ms":2300,"tool_calls":1}}"#,
+                "not json",
+            ],
+        );
+        assert_eq!(
+            events,
+            vec![
+                AgentEvent::Session { id: "s1".to_string() },
+                AgentEvent::ToolUse {
+                    name: "run_shell_command".to_string(),
+                    parameters: serde_json::json!({"command": "ls"}),
+                },
+                AgentEvent::ToolResult {
+                    output: "notes.md".to_string(),
+                    is_error: false,
+                },
+                AgentEvent::Text {
+                    text: "One file.".to_string()
+                },
+                AgentEvent::Stats(AgentStats {
+                    input_tokens: Some(120),
+                    output_tokens: Some(8),
+                    duration_ms: Some(2300),
+                    tool_calls: Some(1),
+                    cost_usd: None,
+                }),
+            ]
+        );
+        assert_eq!(assistant_text(&events), "One file.");
+        assert_eq!(agent_session_id(&events).as_deref(), Some("s1"));
+    }
+
+    #[test]
+    fn decodes_claude_code_stream() {
+        let events = decode(
+            &ClaudeCodeBackend,
+            &[
+                r#"{"type":"system","subtype":"init","session_id":"s1"}"#,
+                r#"{"type":"assistant","message":{"content":[{"type":"text","text":"Checking."},{"type":"tool_use","id":"t1","name":"Bash","input":{"command":"ls"}}]}}"#,
+                r#"{"type":"user","message":{"content":[{"type":"tool_result","tool_use_id":"t1","content":[{"type":"text","text":"notes.md"}]}]}}"#,
+                r#"{"type":"result","subtype":"success","result":"One file.","duration_ms":900,"total_cost_usd":0.01,"usage":{"input_tokens":40,"output_tokens":12}}"#,
+            ],
+        );
+        assert_eq!(
+            events,
+            vec![
+                AgentEvent::Session { id: "s1".to_string() },
+                AgentEvent::Text {
+                    text: "Checking.".to_string()
+                },
+                AgentEvent::ToolUse {
+                    name: "Bash".to_string(),
+                    parameters: serde_json::json!({"command": "ls"}),
+                },
+                AgentEvent::ToolResult {
+                    output: "notes.md".to_string(),
+                    is_error: false,
+                },
+                AgentEvent::Stats(AgentStats {
+                    input_tokens: Some(40),
+                    output_tokens: Some(12),
+                    duration_ms: Some(900),
+                    tool_calls: None,
+                    cost_usd: Some(0.01),
+                }),
+            ]
+        );
+        assert_eq!(tool_label("Bash", &serde_json::json!({"command": "ls"})), "Bash (ls)");
+    }
+
+    #[test]
+    fn builds_hardening_host_config() {
+        let config: crate::config::Config = toml::from_str(
+            "[hardening]\npids_limit = 0\n[groups.family.hardening]\nmemory = \"\"\nread_only_rootfs = false\nseccomp = \"unconfined\"\n",
+        )
+        .unwrap();
+
+        let main = hardening_host_config(&config.hardening_for(MAIN_GROUP)).unwrap();
+        assert_eq!(main["Memory"], 2i64 << 30);
+        assert_eq!(main["ReadonlyRootfs"], true);
+        assert!(main["Tmpfs"]["/tmp"].as_str().unwrap().contains("size=512m"));
+        assert!(main.get("PidsLimit").is_none());
+
+        let family = hardening_host_config(&config.hardening_for("family")).unwrap();
+        assert_eq!(
+            family,
+            serde_json::json!({
+                "NanoCpus": 2_000_000_000i64,
+                "CapDrop": ["ALL"],
+                "SecurityOpt": ["no-new-privileges", "seccomp=unconfined"],
+            })
+        );
+        assert_ne!(profile_hash(main.to_string()), profile_hash(family.to_string()));
+    }
+
+    #[test]
+    fn rejects_memory_limits_out_of_range() {
+        let profile = |memory: &str| HardeningConfig {
+            memory: memory.to_string(),
+            ..HardeningConfig::default()
+        };
+        assert_eq!(profile("512m").memory_bytes().unwrap(), Some(512 << 20));
+        for memory in ["0", "-1g", "-512"] {
+            let err = profile(memory).memory_bytes().unwrap_err();
+            assert!(err.to_string().contains("must be more than 0"), "{}: {}", memory, err);
+        }
+        let err = profile("9999999999999g").memory_bytes().unwrap_err();
+        assert!(err.to_string().contains("is too large"), "{}", err);
+    }
+
+    #[test]
+    fn waits_for_health_events() {
+        use crate::docker::mock::{MockEngine, MockResponse};
+        use std::sync::atomic::AtomicUsize;
+
+        let inspections = AtomicUsize::new(0);
+        let engine = MockEngine::start(move |req| {
+            if req.path.starts_with("/events") {
+                return MockResponse::Events(vec![
+                    json!({"Action": "exec_start: node"}),
+                    json!({"Action": "health_status: healthy"}),
+                ]);
+            }
+            // Still starting when first looked at
+            let health = if inspections.fetch_add(1, Ordering::SeqCst) == 0 { "starting" } else { "healthy" };
+            MockResponse::Json(200, json!({"State": {"Running": true, "Health": {"Status": health}}}))
+        });
+
+        wait_for_container_ready(&engine.docker(), "box").unwrap();
+        let requests: Vec<String> = engine
+            .requests()
+            .iter()
+            .map(|r| format!("{} {}", r.method, r.path.split('?').next().unwrap()))
+            .collect();
+        assert_eq!(requests, ["GET /events", "GET /containers/box/json", "GET /containers/box/json"]);
+
+        let dead = MockEngine::start(|req| match req.path.starts_with("/events") {
+            true => MockResponse::Events(vec![json!({"Action": "die"})]),
+            false => MockResponse::Json(200, json!({"State": {"Running": false}})),
+        });
+        assert!(wait_for_container_ready(&dead.docker(), "box").is_err());
+    }
+
+    #[test]
+    fn claims_pooled_container() {
+        use crate::docker::mock::{MockEngine, MockResponse};
+
+        let mut group = RegisteredGroup::new(MAIN_GROUP, "gemini").unwrap();
+        group.mode = ContainerMode::Ephemeral;
+        group.network.mode = crate::config::NetworkMode::Open;
+        let image = |_: &_| MockResponse::Json(200, json!({"Id": "sha256:abc"}));
+        let spec = container_spec(&MockEngine::start(image).docker(), &group).unwrap();
+        let labels: serde_json::Map<String, serde_json::Value> =
+            spec.labels.iter().map(|(k, v)| (k.to_string(), json!(v))).collect();
+
+        let engine = MockEngine::start(move |req| match (req.method.as_str(), req.path.split('?').next().unwrap()) {
+            ("GET", "/containers/json") => MockResponse::Json(
+                200,
+                json!([
+                    // Already claimed by another run
+                    {"Names": ["/rclaw-agent-main-gemini-run-1"], "State": "created", "Labels": labels},
+                    {"Names": ["/rclaw-agent-main-gemini-pool-old"], "State": "created", "Labels": {}},
+                    {"Names": ["/rclaw-agent-main-gemini-pool-2"], "State": "created", "Labels": labels},
+                ]),
+            ),
+            ("GET", "/events") => MockResponse::Events(vec![json!({"Action": "start"})]),
+            ("GET", path) if path.starts_with("/images/") => image(req),
+            ("GET", _) => MockResponse::Json(200, json!({"State": {"Running": true}})),
+            _ => MockResponse::Empty(204),
+        });
+
+        let name = claim_run_container(&engine.docker(), &group, Path::new("/tmp")).unwrap();
+        assert!(name.starts_with("rclaw-agent-main-gemini-run-"));
+        let posts: Vec<String> = engine
+            .requests()
+            .into_iter()
+            .filter(|r| r.method == "POST")
+            .map(|r| r.path)
+            .collect();
+        assert_eq!(
+            posts,
+            [
+                format!("/containers/rclaw-agent-main-gemini-pool-2/rename?name={}", name),
+                format!("/containers/{}/start", name),
+            ]
+        );
+    }
+
+    #[test]
+    fn splits_backend_prefix() {
+        let (backend, rest) = split_backend_prefix("@claude  summarize my notes").unwrap();
+        assert_eq!(backend.name(), "claude");
+        assert_eq!(rest, "summarize my notes");
+        assert!(split_backend_prefix("@someone hello").is_none());
+        assert!(split_backend_prefix("hello").is_none());
+    }
+}
@@ -0,0 +1,199 @@
+use crate::auth::refresh_gemini_token;
+use crate::auth_discovery::try_discover_gemini_credentials;
+use crate::config::AgentConfig;
+use crate::container::AgentBackend;
+use crate::db::Db;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use std::sync::{Arc, Mutex};
+use tracing::warn;
+
+/// A token is refreshed before a run when it expires sooner than this, so
+/// it outlives most runs.
+const REFRESH_MARGIN_MINUTES: i64 = 10;
+
+/// Serializes refreshes across the process, so concurrent runs do not each
+/// ask Google; each run builds its own store.
+static REFRESHING: Mutex<()> = Mutex::new(());
+
+/// An access token for an agent CLI, handed to one run.
+#[derive(Debug, Clone)]
+pub struct AgentCredentials {
+    pub token: String,
+    /// `None` for tokens that do not expire.
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// Issues the agents' access tokens from `auth_store`. The agents never see
+/// the refresh tokens nor the host's credential files.
+#[derive(Clone)]
+pub struct CredentialStore {
+    db: Arc<Db>,
+    /// `agent.allow_long_lived_tokens`
+    allow_long_lived: bool,
+}
+
+impl CredentialStore {
+    pub fn new(db: Arc<Db>, agent: &AgentConfig) -> Self {
+        CredentialStore {
+            db,
+            allow_long_lived: agent.allow_long_lived_tokens,
+        }
+    }
+
+    /// A token for a run of the backend, refreshed first if it is about to expire.
+    pub fn issue(&self, backend: &dyn AgentBackend) -> Result<AgentCredentials> {
+        match backend.name() {
+            "gemini" => self.gemini(false),
+            "claude" => self.claude(),
+            other => anyhow::bail!("No credentials for backend '{}'", other),
+        }
+    }
+
+    /// A new token, for an agent whose token was rejected mid-run.
+    pub fn refresh(&self, backend: &dyn AgentBackend) -> Result<AgentCredentials> {
+        match backend.name() {
+            "gemini" => self.gemini(true),
+            "claude" => anyhow::bail!("Claude Code tokens cannot be refreshed; run 'rclaw auth claude' for a new one"),
+            other => anyhow::bail!("No credentials for backend '{}'", other),
+        }
+    }
+
+    fn gemini(&self, force: bool) -> Result<AgentCredentials> {
+        // The token is read once the lock is held, so a run that waited for
+        // another's refresh gets the new token instead of refreshing again
+        let _refreshing = REFRESHING.lock().unwrap_or_else(|e| e.into_inner());
+        let token = self
+            .key("gemini_access_token")?
+            .context("No Gemini credentials stored (run 'rclaw auth gemini')")?;
+        let expires_at = self
+            .key("gemini_token_expires_at")?
+            .and_then(|at| DateTime::parse_from_rfc3339(&at).ok())
+            .map(|at| at.with_timezone(&Utc));
+        // Tokens stored without an expiry are refreshed to learn it
+        let fresh = expires_at.is_some_and(|at| at - Utc::now() > Duration::minutes(REFRESH_MARGIN_MINUTES));
+        if fresh && !force {
+            return Ok(AgentCredentials { token, expires_at });
+        }
+
+        match self.refresh_gemini() {
+            Ok(credentials) => Ok(credentials),
+            // Still good for this run, even if not for long
+            Err(e) if !force && expires_at.is_some_and(|at| at > Utc::now()) => {
+                warn!("Could not refresh the Gemini token, using the current one: {:#}", e);
+                Ok(AgentCredentials { token, expires_at })
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn refresh_gemini(&self) -> Result<AgentCredentials> {
+        let refresh_token = self
+            .key("gemini_refresh_token")?
+            .filter(|t| !t.is_empty())
+            .context("The stored Gemini credentials cannot be refreshed (run 'rclaw auth gemini')")?;
+        let (client_id, client_secret) = match (self.key("gemini_client_id")?, self.key("gemini_client_secret")?) {
+            (Some(id), Some(secret)) => (id, secret),
+            // Stored by an older setup, which did not keep the client
+            _ => {
+                let discovered = try_discover_gemini_credentials()
+                    .context("Gemini OAuth client unknown (run 'rclaw auth gemini')")?;
+                (discovered.client_id, discovered.client_secret)
+            }
+        };
+
+        // Runs are sync code, possibly inside the tokio runtime, so the
+        // request gets a runtime of its own on a separate thread
+        let (token, expires_at) = std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+            runtime.block_on(refresh_gemini_token(&client_id, &client_secret, &refresh_token))
+        })
+        .join()
+        .map_err(|_| anyhow::anyhow!("The token refresh panicked"))??;
+
+        self.db.set_auth_key("gemini_access_token", &token)?;
+        match expires_at {
+            Some(at) => self.db.set_auth_key("gemini_token_expires_at", &at.to_rfc3339())?,
+            None => self.db.delete_auth_key("gemini_token_expires_at")?,
+        }
+        Ok(AgentCredentials { token, expires_at })
+    }
+
+    /// Claude Code only has the token from `claude setup-token`, valid for
+    /// months, and no way to derive short-lived ones from it. It is handed
+    /// to the agent only if the configuration accepts that.
+    fn claude(&self) -> Result<AgentCredentials> {
+        if !self.allow_long_lived {
+            anyhow::bail!(
+                "Claude Code has no short-lived tokens, and its long-lived one is not handed to agents \
+                 unless agent.allow_long_lived_tokens is set"
+            );
+        }
+        let token = self
+            .key("claude_oauth_token")?
+            .context("No Claude Code token stored (run 'rclaw auth claude')")?;
+        Ok(AgentCredentials { token, expires_at: None })
+    }
+
+    fn key(&self, key: &str) -> Result<Option<String>> {
+        self.db
+            .get_auth_key(key)
+            .with_context(|| format!("Failed to read {} from auth_store", key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::agent_backend;
+
+    #[test]
+    fn issues_stored_tokens_until_they_expire() {
+        let db = Arc::new(Db::new(":memory:").unwrap());
+        let mut store = CredentialStore::new(db.clone(), &AgentConfig::default());
+        let gemini = agent_backend("gemini").unwrap();
+        assert!(store.issue(gemini).is_err());
+
+        let expires_at = Utc::now() + Duration::hours(1);
+        db.set_auth_key("gemini_access_token", "ya29.fresh").unwrap();
+        db.set_auth_key("gemini_token_expires_at", &expires_at.to_rfc3339()).unwrap();
+        let issued = store.issue(gemini).unwrap();
+        assert_eq!(issued.token, "ya29.fresh");
+
+        // Expiring soon, with no refresh token to renew it
+        let soon = Utc::now() + Duration::minutes(1);
+        db.set_auth_key("gemini_token_expires_at", &soon.to_rfc3339()).unwrap();
+        assert_eq!(store.issue(gemini).unwrap().token, "ya29.fresh");
+        assert!(store.refresh(gemini).is_err());
+
+        // The long-lived Claude Code token only with the opt-in
+        db.set_auth_key("claude_oauth_token", "sk-ant-oat").unwrap();
+        let claude = agent_backend("claude").unwrap();
+        assert!(store.issue(claude).is_err());
+        store.allow_long_lived = true;
+        assert_eq!(store.issue(claude).unwrap().expires_at, None);
+        assert!(store.refresh(claude).is_err());
+    }
+
+    #[test]
+    fn waits_for_a_refresh_by_another_store() {
+        let db = Arc::new(Db::new(":memory:").unwrap());
+        db.set_auth_key("gemini_access_token", "ya29.expired").unwrap();
+        let expired = Utc::now() - Duration::minutes(1);
+        db.set_auth_key("gemini_token_expires_at", &expired.to_rfc3339()).unwrap();
+
+        // Another run's store is refreshing the token
+        let refreshing = REFRESHING.lock().unwrap_or_else(|e| e.into_inner());
+        let store = CredentialStore::new(db.clone(), &AgentConfig::default());
+        let waiting = std::thread::spawn(move || store.issue(agent_backend("gemini").unwrap()));
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        assert!(!waiting.is_finished());
+
+        // With no refresh token, only the other store's new token can satisfy it
+        let expires_at = Utc::now() + Duration::hours(1);
+        db.set_auth_key("gemini_access_token", "ya29.refreshed").unwrap();
+        db.set_auth_key("gemini_token_expires_at", &expires_at.to_rfc3339()).unwrap();
+        drop(refreshing);
+        assert_eq!(waiting.join().unwrap().unwrap().token, "ya29.refreshed");
+    }
+}
@@ -1,8 +1,11 @@
+use crate::container::AgentEvent;
+use crate::artifacts::Artifact;
+use crate::diagnostics::Diagnostic;
 use rusqlite::{params, Connection, Result, OptionalExtension};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use tracing::info;
 use serde::{Serialize, Deserialize};
-use std::sync::{Arc, Mutex};
+use std::sync::Mutex;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Task {
@@ -12,7 +15,65 @@ pub struct Task {
     pub schedule: String, // Cron expression or "every X"
     pub last_run: Option<String>,
     pub next_run: Option<String>,
-    pub status: String, // "active", "paused"
+    pub status: String, // "active", "paused", "completed" (one-shot tasks that ran)
+    pub catch_up: String, // "skip", "once", "all"
+    pub timezone: Option<String>, // IANA name; None uses the configured default
+    pub max_retries: u32,
+    pub backoff: String, // "fixed", "exponential"
+    pub consecutive_failures: u32,
+    pub last_error: Option<String>,
+    pub delivery: String, // "tui", "file[:path]", "channel:<jid>", "none"
+    pub timeout_secs: Option<u64>, // None uses scheduler.task_timeout_secs
+    pub backend: Option<String>, // None uses the group's backend
+    pub approval: Option<String>, // "yolo", "prompt", "policy"; None uses the group's mode
+}
+
+/// One execution of a scheduled task.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TaskRun {
+    pub id: i64,
+    pub task_id: String,
+    pub started_at: String,
+    pub finished_at: Option<String>,
+    pub duration_ms: Option<i64>,
+    pub status: String, // "running", then "success", "error", "timeout" or "cancelled"; each retry is a new run
+    pub result: Option<String>,
+    pub error: Option<String>,
+    pub exit_code: Option<i32>,
+    pub attempt: u32, // 1 for the first try, then one more per retry
+    /// What the agent did during the run, stored as JSON.
+    pub events: Vec<AgentEvent>,
+    /// Network mode of the container the run executed in.
+    pub network: Option<String>,
+    /// Id of the image that container was created from.
+    pub image: Option<String>,
+    /// The agent's stderr, classified, stored as JSON.
+    pub diagnostics: Vec<Diagnostic>,
+    /// Known reason the run failed, such as "quota exceeded".
+    pub failure: Option<String>,
+    /// Files of the workspace the run changed, stored as JSON.
+    pub artifacts: Vec<Artifact>,
+}
+
+/// A conversation with an agent CLI that later prompts continue.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub id: String,
+    pub group_folder: String,
+    pub backend: String,
+    pub agent_session_id: Option<String>, // The CLI's own id, known after the first turn
+    pub title: String, // Start of the first prompt
+    pub turns: u32,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+/// One prompt of a session and what the agent did for it.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionTurn {
+    pub prompt: String,
+    pub events: Vec<AgentEvent>,
+    pub created_at: String,
 }
 
 pub struct Db {
@@ -60,10 +121,92 @@ impl Db {
                 schedule TEXT NOT NULL,
                 last_run DATETIME,
                 next_run DATETIME,
-                status TEXT DEFAULT 'active'
+                status TEXT DEFAULT 'active',
+                catch_up TEXT DEFAULT 'once',
+                timezone TEXT,
+                max_retries INTEGER DEFAULT 0,
+                backoff TEXT DEFAULT 'exponential',
+                consecutive_failures INTEGER DEFAULT 0,
+                last_error TEXT,
+                delivery TEXT DEFAULT 'tui',
+                timeout_secs INTEGER,
+                backend TEXT,
+                approval TEXT
+            )",
+            [],
+        )?;
+
+        // Execution history of scheduled tasks
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS task_runs (
+                id INTEGER PRIMARY KEY,
+                task_id TEXT NOT NULL,
+                started_at DATETIME NOT NULL,
+                finished_at DATETIME,
+                duration_ms INTEGER,
+                status TEXT NOT NULL DEFAULT 'running', -- running, success, error, timeout, cancelled
+                result TEXT,
+                error TEXT,
+                exit_code INTEGER,
+                attempt INTEGER DEFAULT 1,
+                cancel_requested INTEGER DEFAULT 0
             )",
             [],
         )?;
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_task_runs_task ON task_runs (task_id, started_at)",
+            [],
+        )?;
+
+        // Agent conversations and their prompts
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS sessions (
+                id TEXT PRIMARY KEY,
+                group_folder TEXT NOT NULL,
+                backend TEXT NOT NULL,
+                agent_session_id TEXT,
+                title TEXT NOT NULL,
+                turns INTEGER DEFAULT 0,
+                created_at DATETIME NOT NULL,
+                updated_at DATETIME NOT NULL
+            )",
+            [],
+        )?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS session_turns (
+                id INTEGER PRIMARY KEY,
+                session_id TEXT NOT NULL,
+                prompt TEXT NOT NULL,
+                events TEXT,
+                created_at DATETIME NOT NULL
+            )",
+            [],
+        )?;
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_session_turns_session ON session_turns (session_id, id)",
+            [],
+        )?;
+
+        // Columns added after the first release, for databases created before them
+        add_column_if_missing(&conn, "tasks", "catch_up", "TEXT DEFAULT 'once'")?;
+        add_column_if_missing(&conn, "tasks", "timezone", "TEXT")?;
+        add_column_if_missing(&conn, "tasks", "max_retries", "INTEGER DEFAULT 0")?;
+        add_column_if_missing(&conn, "tasks", "backoff", "TEXT DEFAULT 'exponential'")?;
+        add_column_if_missing(&conn, "tasks", "consecutive_failures", "INTEGER DEFAULT 0")?;
+        add_column_if_missing(&conn, "tasks", "last_error", "TEXT")?;
+        add_column_if_missing(&conn, "tasks", "delivery", "TEXT DEFAULT 'tui'")?;
+        add_column_if_missing(&conn, "tasks", "timeout_secs", "INTEGER")?;
+        add_column_if_missing(&conn, "tasks", "backend", "TEXT")?;
+        add_column_if_missing(&conn, "tasks", "approval", "TEXT")?;
+        add_column_if_missing(&conn, "task_runs", "attempt", "INTEGER DEFAULT 1")?;
+        add_column_if_missing(&conn, "task_runs", "cancel_requested", "INTEGER DEFAULT 0")?;
+        add_column_if_missing(&conn, "task_runs", "events", "TEXT")?;
+        add_column_if_missing(&conn, "task_runs", "network", "TEXT")?;
+        add_column_if_missing(&conn, "task_runs", "image", "TEXT")?;
+        add_column_if_missing(&conn, "task_runs", "diagnostics", "TEXT")?;
+        add_column_if_missing(&conn, "task_runs", "failure", "TEXT")?;
+        add_column_if_missing(&conn, "task_runs", "artifacts", "TEXT")?;
+        add_column_if_missing(&conn, "message_queue", "attachments", "TEXT")?;
 
         info!("Database tables initialized.");
         Ok(())
@@ -98,77 +241,426 @@ impl Db {
     }
 
     // --- Message Queue Methods ---
-    pub fn queue_message(&self, jid: &str, content: &str) -> Result<i64> {
+    pub fn queue_message(&self, jid: &str, content: &str, attachments: &[PathBuf]) -> Result<i64> {
         let conn = self.conn.lock().unwrap();
         conn.execute(
-            "INSERT INTO message_queue (jid, content) VALUES (?1, ?2)",
-            params![jid, content],
+            "INSERT INTO message_queue (jid, content, attachments) VALUES (?1, ?2, ?3)",
+            params![jid, content, serde_json::to_string(attachments).unwrap_or_default()],
         )?;
         Ok(conn.last_insert_rowid())
     }
 
-    pub fn get_pending_messages(&self) -> Result<Vec<(i64, String, String)>> {
+    // --- Task Methods ---
+    pub fn add_task(&self, task: &Task) -> Result<()> {
         let conn = self.conn.lock().unwrap();
-        let mut stmt = conn.prepare(
-            "SELECT id, jid, content FROM message_queue WHERE status = 'pending' ORDER BY created_at ASC LIMIT 10"
+        conn.execute(
+            "INSERT OR REPLACE INTO tasks (id, group_folder, prompt, schedule, last_run, next_run, status, catch_up, timezone,
+                                           max_retries, backoff, consecutive_failures, last_error, delivery, timeout_secs, backend, approval)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17)",
+            params![
+                task.id, 
+                task.group_folder, 
+                task.prompt, 
+                task.schedule, 
+                task.last_run, 
+                task.next_run, 
+                task.status,
+                task.catch_up,
+                task.timezone,
+                task.max_retries,
+                task.backoff,
+                task.consecutive_failures,
+                task.last_error,
+                task.delivery,
+                task.timeout_secs,
+                task.backend,
+                task.approval
+            ],
         )?;
-        
-        let msgs = stmt.query_map([], |row| {
-            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
-        })?
-        .collect::<Result<Vec<_>>>()?;
-        
-        Ok(msgs)
+        Ok(())
+    }
+    
+    pub fn get_active_tasks(&self) -> Result<Vec<Task>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM tasks WHERE status = 'active'",
+            TASK_COLUMNS
+        ))?;
+
+        let tasks = stmt
+            .query_map([], task_from_row)?
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(tasks)
+    }
+
+    pub fn list_tasks(&self) -> Result<Vec<Task>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM tasks ORDER BY group_folder, id",
+            TASK_COLUMNS
+        ))?;
+
+        let tasks = stmt
+            .query_map([], task_from_row)?
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(tasks)
+    }
+
+    pub fn get_task(&self, id: &str) -> Result<Option<Task>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            &format!("SELECT {} FROM tasks WHERE id = ?1", TASK_COLUMNS),
+            params![id],
+            task_from_row,
+        )
+        .optional()
+    }
+
+    pub fn set_task_last_run(&self, id: &str, last_run: &str) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE tasks SET last_run = ?2 WHERE id = ?1",
+            params![id, last_run],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_task_next_run(&self, id: &str, next_run: Option<&str>) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE tasks SET next_run = ?2 WHERE id = ?1",
+            params![id, next_run],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_task_failures(&self, id: &str, consecutive_failures: u32, last_error: Option<&str>) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE tasks SET consecutive_failures = ?2, last_error = ?3 WHERE id = ?1",
+            params![id, consecutive_failures, last_error],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_task_status(&self, id: &str, status: &str) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE tasks SET status = ?2 WHERE id = ?1",
+            params![id, status],
+        )?;
+        Ok(())
     }
 
-    pub fn mark_message_sent(&self, id: i64) -> Result<()> {
+    /// Returns whether a task with that id existed.
+    pub fn remove_task(&self, id: &str) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        let removed = conn.execute("DELETE FROM tasks WHERE id = ?1", params![id])?;
+        conn.execute("DELETE FROM task_runs WHERE task_id = ?1", params![id])?;
+        Ok(removed > 0)
+    }
+
+    // --- Task Run Methods ---
+    pub fn insert_task_run(&self, run: &TaskRun) -> Result<i64> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO task_runs (task_id, started_at, finished_at, duration_ms, status, result, error, exit_code, attempt)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![
+                run.task_id,
+                run.started_at,
+                run.finished_at,
+                run.duration_ms,
+                run.status,
+                run.result,
+                run.error,
+                run.exit_code,
+                run.attempt
+            ],
+        )?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    pub fn update_task_run(&self, run: &TaskRun) -> Result<()> {
         let conn = self.conn.lock().unwrap();
         conn.execute(
-            "UPDATE message_queue SET status = 'sent' WHERE id = ?1",
+            "UPDATE task_runs SET finished_at = ?2, duration_ms = ?3, status = ?4, result = ?5, error = ?6, exit_code = ?7,
+             events = ?8, network = ?9, image = ?10, diagnostics = ?11, failure = ?12, artifacts = ?13
+             WHERE id = ?1",
+            params![
+                run.id,
+                run.finished_at,
+                run.duration_ms,
+                run.status,
+                run.result,
+                run.error,
+                run.exit_code,
+                serde_json::to_string(&run.events).unwrap_or_default(),
+                run.network,
+                run.image,
+                serde_json::to_string(&run.diagnostics).unwrap_or_default(),
+                run.failure,
+                serde_json::to_string(&run.artifacts).unwrap_or_default()
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Most recent runs of a task first.
+    pub fn get_task_runs(&self, task_id: &str, limit: usize) -> Result<Vec<TaskRun>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM task_runs WHERE task_id = ?1 ORDER BY started_at DESC, id DESC LIMIT ?2",
+            TASK_RUN_COLUMNS
+        ))?;
+
+        let runs = stmt
+            .query_map(params![task_id, limit as i64], task_run_from_row)?
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(runs)
+    }
+
+    pub fn get_task_run(&self, id: i64) -> Result<Option<TaskRun>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            &format!("SELECT {} FROM task_runs WHERE id = ?1", TASK_RUN_COLUMNS),
             params![id],
+            task_run_from_row,
+        )
+        .optional()
+    }
+
+    /// Asks the process executing the task's running runs to stop them.
+    /// Returns the number of runs flagged.
+    pub fn request_run_cancel(&self, task_id: &str) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE task_runs SET cancel_requested = 1 WHERE task_id = ?1 AND status = 'running'",
+            params![task_id],
+        )
+    }
+
+    /// Whether a run of the task is in progress, in this or another process.
+    pub fn has_running_run(&self, task_id: &str) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM task_runs WHERE task_id = ?1 AND status = 'running')",
+            params![task_id],
+            |row| row.get(0),
+        )
+    }
+
+    /// Marks the runs left `running` by a process that exited mid-run as
+    /// errors, so they don't hold their task back. Returns how many there were.
+    pub fn fail_interrupted_runs(&self, finished_at: &str) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE task_runs SET status = 'error', finished_at = ?1, error = 'Interrupted: rclaw exited during the run'
+             WHERE status = 'running'",
+            params![finished_at],
+        )
+    }
+
+    pub fn is_run_cancel_requested(&self, id: i64) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        let flag: Option<bool> = conn
+            .query_row(
+                "SELECT cancel_requested FROM task_runs WHERE id = ?1",
+                params![id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(flag.unwrap_or(false))
+    }
+
+    // --- Session Methods ---
+    pub fn create_session(&self, session: &Session) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO sessions (id, group_folder, backend, agent_session_id, title, turns, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                session.id,
+                session.group_folder,
+                session.backend,
+                session.agent_session_id,
+                session.title,
+                session.turns,
+                session.created_at,
+                session.updated_at
+            ],
         )?;
         Ok(())
     }
 
-    // --- Task Methods ---
-    pub fn add_task(&self, task: &Task) -> Result<()> {
+    pub fn get_session(&self, id: &str) -> Result<Option<Session>> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            &format!("SELECT {} FROM sessions WHERE id = ?1", SESSION_COLUMNS),
+            params![id],
+            session_from_row,
+        )
+        .optional()
+    }
+
+    /// Most recently used sessions of a group first.
+    pub fn list_sessions(&self, group_folder: &str, limit: usize) -> Result<Vec<Session>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM sessions WHERE group_folder = ?1 ORDER BY updated_at DESC LIMIT ?2",
+            SESSION_COLUMNS
+        ))?;
+
+        let sessions = stmt
+            .query_map(params![group_folder, limit as i64], session_from_row)?
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(sessions)
+    }
+
+    /// Records a finished prompt. `agent_session_id` replaces the stored one
+    /// when the CLI reported it.
+    pub fn add_session_turn(&self, session_id: &str, agent_session_id: Option<&str>, turn: &SessionTurn) -> Result<()> {
         let conn = self.conn.lock().unwrap();
         conn.execute(
-            "INSERT OR REPLACE INTO tasks (id, group_folder, prompt, schedule, last_run, next_run, status)
-             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            "INSERT INTO session_turns (session_id, prompt, events, created_at) VALUES (?1, ?2, ?3, ?4)",
             params![
-                task.id, 
-                task.group_folder, 
-                task.prompt, 
-                task.schedule, 
-                task.last_run, 
-                task.next_run, 
-                task.status
+                session_id,
+                turn.prompt,
+                serde_json::to_string(&turn.events).unwrap_or_default(),
+                turn.created_at
             ],
         )?;
+        conn.execute(
+            "UPDATE sessions SET agent_session_id = COALESCE(?2, agent_session_id), turns = turns + 1, updated_at = ?3
+             WHERE id = ?1",
+            params![session_id, agent_session_id, turn.created_at],
+        )?;
         Ok(())
     }
-    
-    pub fn get_active_tasks(&self) -> Result<Vec<Task>> {
+
+    pub fn get_session_turns(&self, session_id: &str) -> Result<Vec<SessionTurn>> {
         let conn = self.conn.lock().unwrap();
         let mut stmt = conn.prepare(
-            "SELECT id, group_folder, prompt, schedule, last_run, next_run, status 
-             FROM tasks WHERE status = 'active'"
-        )?;
-
-        let tasks = stmt.query_map([], |row| {
-            Ok(Task {
-                id: row.get(0)?,
-                group_folder: row.get(1)?,
-                prompt: row.get(2)?,
-                schedule: row.get(3)?,
-                last_run: row.get(4)?,
-                next_run: row.get(5)?,
-                status: row.get(6)?,
-            })
-        })?
+            "SELECT prompt, events, created_at FROM session_turns WHERE session_id = ?1 ORDER BY id",
+        )?;
+
+        let turns = stmt
+            .query_map(params![session_id], |row| {
+                Ok(SessionTurn {
+                    prompt: row.get(0)?,
+                    events: row
+                        .get::<_, Option<String>>(1)?
+                        .and_then(|json| serde_json::from_str(&json).ok())
+                        .unwrap_or_default(),
+                    created_at: row.get(2)?,
+                })
+            })?
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(turns)
+    }
+
+    /// Deletes finished runs that started before `before` (RFC 3339).
+    /// Returns the number of rows removed.
+    pub fn prune_task_runs(&self, before: &str) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "DELETE FROM task_runs WHERE started_at < ?1 AND status != 'running'",
+            params![before],
+        )
+    }
+}
+
+const TASK_COLUMNS: &str =
+    "id, group_folder, prompt, schedule, last_run, next_run, status, catch_up, timezone, \
+     max_retries, backoff, consecutive_failures, last_error, delivery, timeout_secs, backend, approval";
+
+fn task_from_row(row: &rusqlite::Row) -> Result<Task> {
+    Ok(Task {
+        id: row.get(0)?,
+        group_folder: row.get(1)?,
+        prompt: row.get(2)?,
+        schedule: row.get(3)?,
+        last_run: row.get(4)?,
+        next_run: row.get(5)?,
+        status: row.get(6)?,
+        catch_up: row.get(7)?,
+        timezone: row.get(8)?,
+        max_retries: row.get(9)?,
+        backoff: row.get(10)?,
+        consecutive_failures: row.get(11)?,
+        last_error: row.get(12)?,
+        delivery: row.get(13)?,
+        timeout_secs: row.get(14)?,
+        backend: row.get(15)?,
+        approval: row.get(16)?,
+    })
+}
+
+const TASK_RUN_COLUMNS: &str =
+    "id, task_id, started_at, finished_at, duration_ms, status, result, error, exit_code, attempt, events, network, image, diagnostics, failure, artifacts";
+
+fn task_run_from_row(row: &rusqlite::Row) -> Result<TaskRun> {
+    Ok(TaskRun {
+        id: row.get(0)?,
+        task_id: row.get(1)?,
+        started_at: row.get(2)?,
+        finished_at: row.get(3)?,
+        duration_ms: row.get(4)?,
+        status: row.get(5)?,
+        result: row.get(6)?,
+        error: row.get(7)?,
+        exit_code: row.get(8)?,
+        attempt: row.get(9)?,
+        events: row
+            .get::<_, Option<String>>(10)?
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default(),
+        network: row.get(11)?,
+        image: row.get(12)?,
+        diagnostics: row
+            .get::<_, Option<String>>(13)?
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default(),
+        failure: row.get(14)?,
+        artifacts: row
+            .get::<_, Option<String>>(15)?
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default(),
+    })
+}
+
+const SESSION_COLUMNS: &str =
+    "id, group_folder, backend, agent_session_id, title, turns, created_at, updated_at";
+
+fn session_from_row(row: &rusqlite::Row) -> Result<Session> {
+    Ok(Session {
+        id: row.get(0)?,
+        group_folder: row.get(1)?,
+        backend: row.get(2)?,
+        agent_session_id: row.get(3)?,
+        title: row.get(4)?,
+        turns: row.get(5)?,
+        created_at: row.get(6)?,
+        updated_at: row.get(7)?,
+    })
+}
+
+fn add_column_if_missing(conn: &Connection, table: &str, column: &str, definition: &str) -> Result<()> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let columns = stmt
+        .query_map([], |row| row.get::<_, String>(1))?
         .collect::<Result<Vec<_>>>()?;
 
-        Ok(tasks)
+    if !columns.iter().any(|c| c == column) {
+        conn.execute(
+            &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition),
+            [],
+        )?;
     }
-}
\ No newline at end of file
+    Ok(())
+}
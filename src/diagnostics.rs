@@ -0,0 +1,166 @@
+use crate::container::AgentEvent;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiagnosticLevel {
+    Info,
+    Warning,
+    Error,
+}
+
+/// Known reasons for an agent run to fail, which the user can act on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureCategory {
+    /// The model's quota or rate limit ran out.
+    Quota,
+    /// The access token was rejected or has expired.
+    Auth,
+    /// The agent could not reach its API.
+    Network,
+    /// The task or its group is misconfigured, so the agent never started.
+    Config,
+}
+
+impl FailureCategory {
+    /// What the user can do about it.
+    pub fn hint(&self) -> &'static str {
+        match self {
+            FailureCategory::Quota => "wait for the quota to reset or use another backend",
+            FailureCategory::Auth => "run 'rclaw auth <backend>' to log in again",
+            FailureCategory::Network => "check the connection and the group's network mode",
+            FailureCategory::Config => "fix the task or its group's configuration",
+        }
+    }
+}
+
+impl std::fmt::Display for FailureCategory {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            FailureCategory::Quota => "quota exceeded",
+            FailureCategory::Auth => "auth expired",
+            FailureCategory::Network => "network failure",
+            FailureCategory::Config => "invalid config",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A line the agent wrote to stderr, classified.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Diagnostic {
+    pub level: DiagnosticLevel,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub category: Option<FailureCategory>,
+    pub message: String,
+}
+
+fn patterns() -> &'static [(FailureCategory, Regex)] {
+    static PATTERNS: OnceLock<Vec<(FailureCategory, Regex)>> = OnceLock::new();
+    PATTERNS.get_or_init(|| {
+        [
+            (
+                FailureCategory::Quota,
+                r"(?i)quota|rate.?limit|RESOURCE_EXHAUSTED|\b429\b|usage limit|too many requests",
+            ),
+            (
+                FailureCategory::Auth,
+                r"(?i)\b401\b|UNAUTHENTICATED|authentication_error|invalid.(api.key|token|credentials)|token (has )?expired|expired token|not logged in|please log ?in",
+            ),
+            (
+                FailureCategory::Network,
+                r"(?i)ECONNREFUSED|ECONNRESET|ENOTFOUND|ETIMEDOUT|EAI_AGAIN|getaddrinfo|socket hang up|fetch failed|network (error|is unreachable)",
+            ),
+        ]
+        .into_iter()
+        .map(|(category, pattern)| (category, Regex::new(pattern).expect("valid failure pattern")))
+        .collect()
+    })
+}
+
+/// The failure category a message matches, if any.
+pub fn classify(message: &str) -> Option<FailureCategory> {
+    patterns()
+        .iter()
+        .find(|(_, pattern)| pattern.is_match(message))
+        .map(|(category, _)| *category)
+}
+
+fn level(message: &str) -> DiagnosticLevel {
+    static ERROR: OnceLock<Regex> = OnceLock::new();
+    static WARNING: OnceLock<Regex> = OnceLock::new();
+    let error = ERROR.get_or_init(|| Regex::new(r"(?i)\berror\b|\bfatal\b|exception|\bfailed\b").unwrap());
+    let warning = WARNING.get_or_init(|| Regex::new(r"(?i)\bwarn(ing)?\b|deprecat").unwrap());
+    if error.is_match(message) {
+        DiagnosticLevel::Error
+    } else if warning.is_match(message) {
+        DiagnosticLevel::Warning
+    } else {
+        DiagnosticLevel::Info
+    }
+}
+
+/// The diagnostics in a run's stderr, leaving out blank lines and those
+/// matching one of the `ignore` patterns (validated in `Config::load`).
+pub fn parse_stderr(stderr: &str, ignore: &[String]) -> Vec<Diagnostic> {
+    let ignore: Vec<Regex> = ignore.iter().filter_map(|p| Regex::new(p).ok()).collect();
+    stderr
+        .lines()
+        .map(str::trim_end)
+        .filter(|line| !line.trim().is_empty() && !ignore.iter().any(|p| p.is_match(line)))
+        .map(|line| {
+            let category = classify(line);
+            Diagnostic {
+                // A known failure is an error whatever its wording
+                level: if category.is_some() { DiagnosticLevel::Error } else { level(line) },
+                category,
+                message: line.to_string(),
+            }
+        })
+        .collect()
+}
+
+/// Why a run failed: the first categorized diagnostic, or else the first
+/// error the agent reported in its stream that matches a category.
+pub fn failure_category(diagnostics: &[Diagnostic], events: &[AgentEvent]) -> Option<FailureCategory> {
+    diagnostics.iter().find_map(|d| d.category).or_else(|| {
+        events.iter().find_map(|e| match e {
+            AgentEvent::Error { message } => classify(message),
+            _ => None,
+        })
+    })
+}
+
+/// The stderr lines kept as diagnostics, for error messages.
+pub fn diagnostics_text(diagnostics: &[Diagnostic]) -> String {
+    diagnostics.iter().map(|d| d.message.as_str()).collect::<Vec<_>>().join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DiagnosticsConfig;
+
+    #[test]
+    fn classifies_stderr_lines() {
+        let stderr = "(node:12) [DEP0040] DeprecationWarning: The `punycode` module is deprecated\n\
+                      Warning: slow response from the model\n\
+                      \n\
+                      Error: [API Error: 429 RESOURCE_EXHAUSTED] You exceeded your current quota\n\
+                      Retrying in 5s\n";
+        let diagnostics = parse_stderr(stderr, &DiagnosticsConfig::default().ignore);
+        let levels: Vec<_> = diagnostics.iter().map(|d| d.level).collect();
+        assert_eq!(levels, [DiagnosticLevel::Warning, DiagnosticLevel::Error, DiagnosticLevel::Info]);
+        assert_eq!(failure_category(&diagnostics, &[]), Some(FailureCategory::Quota));
+
+        // Without a categorized line, errors from the stream count too
+        let events = [AgentEvent::Error {
+            message: "request to https://cloudcode-pa.googleapis.com failed, reason: getaddrinfo EAI_AGAIN".to_string(),
+        }];
+        assert_eq!(failure_category(&diagnostics[..1], &events), Some(FailureCategory::Network));
+        assert_eq!(classify("API Error: 401 UNAUTHENTICATED"), Some(FailureCategory::Auth));
+    }
+}
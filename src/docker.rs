@@ -0,0 +1,672 @@
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::Shutdown;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Failures of a Docker Engine API call.
+#[derive(Debug, thiserror::Error)]
+pub enum DockerError {
+    #[error("Cannot connect to the Docker daemon at {path}: {source}")]
+    Connect {
+        path: String,
+        #[source]
+        source: io::Error,
+    },
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    Conflict(String),
+    #[error("Docker API error {status}: {message}")]
+    Api { status: u16, message: String },
+    #[error("Docker API connection failed: {0}")]
+    Io(#[from] io::Error),
+    #[error("Unexpected Docker API response: {0}")]
+    Protocol(String),
+}
+
+pub type DockerResult<T> = std::result::Result<T, DockerError>;
+
+/// Client of the Docker Engine API (or Podman's compatible one) over its Unix socket. Every call opens
+/// its own connection, so a client can be shared freely between threads.
+#[derive(Debug, Clone)]
+pub struct Docker {
+    socket: PathBuf,
+}
+
+/// A container as listed by `GET /containers/json`.
+#[derive(Debug, Clone)]
+pub struct ContainerSummary {
+    pub name: String,
+    pub state: String,
+    pub labels: HashMap<String, String>,
+}
+
+/// Which stream a chunk of exec output belongs to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExecStream {
+    Stdout,
+    Stderr,
+}
+
+impl Docker {
+    pub fn new(socket: impl Into<PathBuf>) -> Self {
+        Docker { socket: socket.into() }
+    }
+
+    pub fn version(&self) -> DockerResult<Value> {
+        self.call("GET", "/version", None)
+    }
+
+    pub fn info(&self) -> DockerResult<Value> {
+        self.call("GET", "/info", None)
+    }
+
+    pub fn inspect_image(&self, image: &str) -> DockerResult<Value> {
+        self.call("GET", &format!("/images/{}/json", image), None)
+    }
+
+    pub fn inspect_container(&self, name: &str) -> DockerResult<Value> {
+        self.call("GET", &format!("/containers/{}/json", name), None)
+    }
+
+    /// Containers carrying `label`, stopped ones included when `all` is set.
+    pub fn list_containers(&self, all: bool, label: &str) -> DockerResult<Vec<ContainerSummary>> {
+        let filters = encode(&json!({ "label": [label] }).to_string());
+        let listed = self.call(
+            "GET",
+            &format!("/containers/json?all={}&filters={}", all, filters),
+            None,
+        )?;
+        let containers = listed.as_array().cloned().unwrap_or_default();
+        Ok(containers
+            .iter()
+            .map(|c| ContainerSummary {
+                name: c["Names"][0].as_str().unwrap_or("").trim_start_matches('/').to_string(),
+                state: c["State"].as_str().unwrap_or("").to_string(),
+                labels: serde_json::from_value(c["Labels"].clone()).unwrap_or_default(),
+            })
+            .collect())
+    }
+
+    /// Creates a container from a `POST /containers/create` body and returns its id.
+    pub fn create_container(&self, name: &str, spec: &Value) -> DockerResult<String> {
+        let created = self.call("POST", &format!("/containers/create?name={}", encode(name)), Some(spec))?;
+        id_of(&created)
+    }
+
+    pub fn start_container(&self, name: &str) -> DockerResult<()> {
+        self.call("POST", &format!("/containers/{}/start", name), None).map(|_| ())
+    }
+
+    pub fn stop_container(&self, name: &str) -> DockerResult<()> {
+        self.call("POST", &format!("/containers/{}/stop", name), None).map(|_| ())
+    }
+
+    pub fn restart_container(&self, name: &str) -> DockerResult<()> {
+        self.call("POST", &format!("/containers/{}/restart", name), None).map(|_| ())
+    }
+
+    pub fn rename_container(&self, name: &str, new_name: &str) -> DockerResult<()> {
+        self.call("POST", &format!("/containers/{}/rename?name={}", name, encode(new_name)), None)
+            .map(|_| ())
+    }
+
+    pub fn remove_container(&self, name: &str, force: bool) -> DockerResult<()> {
+        self.call("DELETE", &format!("/containers/{}?force={}", name, force), None)
+            .map(|_| ())
+    }
+
+    pub fn inspect_network(&self, name: &str) -> DockerResult<Value> {
+        self.call("GET", &format!("/networks/{}", name), None)
+    }
+
+    pub fn create_network(&self, name: &str, internal: bool) -> DockerResult<()> {
+        let spec = json!({ "Name": name, "Internal": internal });
+        self.call("POST", "/networks/create", Some(&spec)).map(|_| ())
+    }
+
+    pub fn connect_network(&self, network: &str, container: &str) -> DockerResult<()> {
+        let spec = json!({ "Container": container });
+        self.call("POST", &format!("/networks/{}/connect", network), Some(&spec))
+            .map(|_| ())
+    }
+
+    /// Prepares a command to run in a container and returns the exec id.
+    pub fn create_exec(&self, container: &str, cmd: &[String], env: &[String], stdin: bool) -> DockerResult<String> {
+        let spec = json!({
+            "Cmd": cmd,
+            "Env": env,
+            "AttachStdin": stdin,
+            "AttachStdout": true,
+            "AttachStderr": true,
+            "Tty": false,
+        });
+        let created = self.call("POST", &format!("/containers/{}/exec", container), Some(&spec))?;
+        id_of(&created)
+    }
+
+    /// Starts an exec and returns the connection attached to its streams.
+    pub fn start_exec(&self, id: &str) -> DockerResult<ExecSession> {
+        let body = json!({ "Detach": false, "Tty": false });
+        let mut stream = self.connect()?;
+        write_request(
+            &mut stream,
+            "POST",
+            &format!("/exec/{}/start", id),
+            Some(&body),
+            &[("Connection", "Upgrade"), ("Upgrade", "tcp")],
+        )?;
+        let control = stream.try_clone()?;
+        let mut reader = BufReader::new(stream);
+        let (status, headers) = read_head(&mut reader)?;
+        if status >= 400 {
+            let message = read_to_string(Body::new(reader, &headers))?;
+            return Err(api_error(status, &message));
+        }
+        Ok(ExecSession { reader, control })
+    }
+
+    pub fn inspect_exec(&self, id: &str) -> DockerResult<Value> {
+        self.call("GET", &format!("/exec/{}/json", id), None)
+    }
+
+    /// Runs a command to completion and returns its exit code.
+    pub fn run_exec(&self, container: &str, cmd: &[String]) -> DockerResult<i64> {
+        let id = self.create_exec(container, cmd, &[], false)?;
+        let mut session = self.start_exec(&id)?;
+        session.read_output(|_, _| {})?;
+        self.exec_exit_code(&id)
+    }
+
+    /// Exit code of a finished exec. Docker may report it a moment after the
+    /// output stream closes, so it is polled briefly.
+    pub fn exec_exit_code(&self, id: &str) -> DockerResult<i64> {
+        for _ in 0..50 {
+            let exec = self.inspect_exec(id)?;
+            if exec["Running"].as_bool() != Some(true) {
+                return exec["ExitCode"]
+                    .as_i64()
+                    .ok_or_else(|| DockerError::Protocol("exec has no exit code".to_string()));
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        Err(DockerError::Protocol(format!("exec {} is still running", id)))
+    }
+
+    /// Subscribes to daemon events matching `filters` (`GET /events`).
+    pub fn events(&self, filters: &Value) -> DockerResult<EventStream> {
+        let mut stream = self.connect()?;
+        let path = format!("/events?filters={}", encode(&filters.to_string()));
+        write_request(&mut stream, "GET", &path, None, &[])?;
+        let control = stream.try_clone()?;
+        let mut reader = BufReader::new(stream);
+        let (status, headers) = read_head(&mut reader)?;
+        if status >= 400 {
+            let message = read_to_string(Body::new(reader, &headers))?;
+            return Err(api_error(status, &message));
+        }
+        Ok(EventStream {
+            lines: BufReader::new(Body::new(reader, &headers)),
+            control,
+        })
+    }
+
+    fn connect(&self) -> DockerResult<UnixStream> {
+        UnixStream::connect(&self.socket).map_err(|source| DockerError::Connect {
+            path: self.socket.display().to_string(),
+            source,
+        })
+    }
+
+    /// Sends a request and returns the JSON body of the response (`Null`
+    /// when it has none), mapping error statuses to `DockerError`.
+    fn call(&self, method: &str, path: &str, body: Option<&Value>) -> DockerResult<Value> {
+        let mut stream = self.connect()?;
+        write_request(&mut stream, method, path, body, &[("Connection", "close")])?;
+        let mut reader = BufReader::new(stream);
+        let (status, headers) = read_head(&mut reader)?;
+        let text = read_to_string(Body::new(reader, &headers))?;
+        if status >= 400 {
+            return Err(api_error(status, &text));
+        }
+        if text.trim().is_empty() {
+            return Ok(Value::Null);
+        }
+        // Some endpoints (like /_ping) answer with plain text
+        Ok(serde_json::from_str(&text).unwrap_or(Value::String(text)))
+    }
+}
+
+/// The attached streams of a running exec.
+pub struct ExecSession {
+    reader: BufReader<UnixStream>,
+    control: UnixStream,
+}
+
+impl ExecSession {
+    /// A handle that writes to the command's stdin from another thread
+    /// while the output is read, leaving it open.
+    pub fn stdin(&self) -> DockerResult<ExecStdin> {
+        Ok(ExecStdin(self.control.try_clone()?))
+    }
+
+    /// A handle that can close the connection from another thread, which
+    /// ends `read_output`.
+    pub fn closer(&self) -> DockerResult<ExecCloser> {
+        Ok(ExecCloser(self.control.try_clone()?))
+    }
+
+    /// Reads the multiplexed output until the command ends, passing each
+    /// chunk to `on_output` as it arrives.
+    pub fn read_output(&mut self, mut on_output: impl FnMut(ExecStream, &[u8])) -> DockerResult<()> {
+        let mut header = [0u8; 8];
+        loop {
+            match self.reader.read_exact(&mut header) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(e) => return Err(e.into()),
+            }
+            let size = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+            let mut payload = vec![0u8; size];
+            self.reader.read_exact(&mut payload)?;
+            let stream = if header[0] == 2 { ExecStream::Stderr } else { ExecStream::Stdout };
+            on_output(stream, &payload);
+        }
+    }
+}
+
+pub struct ExecStdin(UnixStream);
+
+impl ExecStdin {
+    pub fn write(&mut self, data: &[u8]) -> DockerResult<()> {
+        self.0.write_all(data)?;
+        Ok(())
+    }
+}
+
+pub struct ExecCloser(UnixStream);
+
+impl ExecCloser {
+    pub fn close(&self) {
+        let _ = self.0.shutdown(Shutdown::Both);
+    }
+}
+
+/// Daemon events, one JSON object per line.
+pub struct EventStream {
+    lines: BufReader<Body>,
+    control: UnixStream,
+}
+
+impl EventStream {
+    /// The next event, or `None` if none arrives within `timeout`.
+    pub fn next_event(&mut self, timeout: Duration) -> DockerResult<Option<Value>> {
+        self.control.set_read_timeout(Some(timeout.max(Duration::from_millis(1))))?;
+        let mut line = String::new();
+        loop {
+            line.clear();
+            match self.lines.read_line(&mut line) {
+                Ok(0) => return Err(DockerError::Protocol("event stream closed".to_string())),
+                Ok(_) if line.trim().is_empty() => continue,
+                Ok(_) => {
+                    return serde_json::from_str(line.trim())
+                        .map(Some)
+                        .map_err(|e| DockerError::Protocol(e.to_string()))
+                }
+                Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                    return Ok(None)
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+fn write_request(
+    stream: &mut UnixStream,
+    method: &str,
+    path: &str,
+    body: Option<&Value>,
+    headers: &[(&str, &str)],
+) -> io::Result<()> {
+    let body = body.map(|b| b.to_string()).unwrap_or_default();
+    let mut request = format!("{} {} HTTP/1.1\r\nHost: docker\r\nUser-Agent: rclaw\r\n", method, path);
+    for (name, value) in headers {
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    if !body.is_empty() {
+        request.push_str("Content-Type: application/json\r\n");
+    }
+    request.push_str(&format!("Content-Length: {}\r\n\r\n", body.len()));
+    request.push_str(&body);
+    stream.write_all(request.as_bytes())?;
+    stream.flush()
+}
+
+/// Reads the status line and headers (names lowercased) of a response.
+fn read_head<R: BufRead>(reader: &mut R) -> DockerResult<(u16, HashMap<String, String>)> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let status = line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| DockerError::Protocol(format!("bad status line '{}'", line.trim())))?;
+    let mut headers = HashMap::new();
+    loop {
+        line.clear();
+        reader.read_line(&mut line)?;
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+        }
+    }
+    Ok((status, headers))
+}
+
+fn read_to_string(mut body: Body) -> DockerResult<String> {
+    let mut text = String::new();
+    body.read_to_string(&mut text)?;
+    Ok(text)
+}
+
+fn api_error(status: u16, body: &str) -> DockerError {
+    let message = serde_json::from_str::<Value>(body)
+        .ok()
+        .and_then(|v| v["message"].as_str().map(str::to_string))
+        .unwrap_or_else(|| body.trim().to_string());
+    match status {
+        404 => DockerError::NotFound(message),
+        409 => DockerError::Conflict(message),
+        _ => DockerError::Api { status, message },
+    }
+}
+
+fn id_of(created: &Value) -> DockerResult<String> {
+    created["Id"]
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| DockerError::Protocol("response has no Id".to_string()))
+}
+
+fn encode(value: &str) -> String {
+    url::form_urlencoded::byte_serialize(value.as_bytes()).collect()
+}
+
+/// A response body, framed by `Content-Length`, chunked encoding or the end
+/// of the connection.
+enum Body {
+    Length(io::Take<BufReader<UnixStream>>),
+    Chunked { reader: BufReader<UnixStream>, remaining: usize, done: bool },
+    Eof(BufReader<UnixStream>),
+}
+
+impl Body {
+    fn new(reader: BufReader<UnixStream>, headers: &HashMap<String, String>) -> Self {
+        if headers
+            .get("transfer-encoding")
+            .is_some_and(|te| te.eq_ignore_ascii_case("chunked"))
+        {
+            Body::Chunked { reader, remaining: 0, done: false }
+        } else if let Some(length) = headers.get("content-length").and_then(|l| l.parse().ok()) {
+            Body::Length(reader.take(length))
+        } else {
+            Body::Eof(reader)
+        }
+    }
+}
+
+impl Read for Body {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Body::Length(reader) => reader.read(buf),
+            Body::Eof(reader) => reader.read(buf),
+            Body::Chunked { reader, remaining, done } => {
+                if *done || buf.is_empty() {
+                    return Ok(0);
+                }
+                if *remaining == 0 {
+                    let mut size_line = String::new();
+                    reader.read_line(&mut size_line)?;
+                    let size = size_line.trim().split(';').next().unwrap_or("");
+                    let size = usize::from_str_radix(size, 16)
+                        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "bad chunk size"))?;
+                    if size == 0 {
+                        *done = true;
+                        return Ok(0);
+                    }
+                    *remaining = size;
+                }
+                let limit = buf.len().min(*remaining);
+                let n = reader.read(&mut buf[..limit])?;
+                if n == 0 {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                *remaining -= n;
+                if *remaining == 0 {
+                    let mut crlf = [0u8; 2];
+                    reader.read_exact(&mut crlf)?;
+                }
+                Ok(n)
+            }
+        }
+    }
+}
+
+/// A fake Docker daemon on a Unix socket for tests. Each request is
+/// recorded and answered by the handler.
+#[cfg(test)]
+pub mod mock {
+    use super::*;
+    use std::os::unix::net::UnixListener;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Debug, Clone)]
+    pub struct MockRequest {
+        pub method: String,
+        pub path: String,
+        pub body: Value,
+        /// What the client wrote to an exec's stdin.
+        pub stdin: Vec<u8>,
+    }
+
+    pub enum MockResponse {
+        Json(u16, Value),
+        Empty(u16),
+        /// Chunked newline-delimited JSON, like `/events`.
+        Events(Vec<Value>),
+        /// An upgraded exec connection: stdin is read to its end, then the
+        /// frames are written.
+        Exec(Vec<(ExecStream, Vec<u8>)>),
+    }
+
+    type Handler = dyn Fn(&MockRequest) -> MockResponse + Send + Sync;
+
+    pub struct MockEngine {
+        socket: PathBuf,
+        requests: Arc<Mutex<Vec<MockRequest>>>,
+    }
+
+    impl MockEngine {
+        pub fn start(handler: impl Fn(&MockRequest) -> MockResponse + Send + Sync + 'static) -> Self {
+            let socket = std::env::temp_dir().join(format!("rclaw-docker-{}.sock", uuid::Uuid::new_v4().simple()));
+            let listener = UnixListener::bind(&socket).expect("bind mock socket");
+            let requests = Arc::new(Mutex::new(Vec::new()));
+            let handler: Arc<Handler> = Arc::new(handler);
+            let recorded = requests.clone();
+            std::thread::spawn(move || {
+                for conn in listener.incoming().flatten() {
+                    let handler = handler.clone();
+                    let recorded = recorded.clone();
+                    std::thread::spawn(move || {
+                        let _ = serve(conn, &*handler, &recorded);
+                    });
+                }
+            });
+            MockEngine { socket, requests }
+        }
+
+        pub fn docker(&self) -> Docker {
+            Docker::new(&self.socket)
+        }
+
+        pub fn requests(&self) -> Vec<MockRequest> {
+            self.requests.lock().unwrap().clone()
+        }
+    }
+
+    impl Drop for MockEngine {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.socket);
+        }
+    }
+
+    fn serve(conn: UnixStream, handler: &Handler, recorded: &Mutex<Vec<MockRequest>>) -> io::Result<()> {
+        let mut writer = conn.try_clone()?;
+        let mut reader = BufReader::new(conn);
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let mut parts = line.split_whitespace();
+        let method = parts.next().unwrap_or("").to_string();
+        let path = parts.next().unwrap_or("").to_string();
+        let mut length = 0;
+        let mut upgrade = false;
+        loop {
+            line.clear();
+            reader.read_line(&mut line)?;
+            let header = line.trim_end().to_ascii_lowercase();
+            if header.is_empty() {
+                break;
+            }
+            if let Some(value) = header.strip_prefix("content-length:") {
+                length = value.trim().parse().unwrap_or(0);
+            }
+            upgrade |= header.starts_with("upgrade:");
+        }
+        let mut body = vec![0u8; length];
+        reader.read_exact(&mut body)?;
+        let mut request = MockRequest {
+            method,
+            path,
+            body: serde_json::from_slice(&body).unwrap_or(Value::Null),
+            stdin: Vec::new(),
+        };
+
+        let response = handler(&request);
+        if let MockResponse::Exec(_) = response {
+            assert!(upgrade, "exec start must ask for a connection upgrade");
+            write!(writer, "HTTP/1.1 101 UPGRADED\r\nConnection: Upgrade\r\nUpgrade: tcp\r\n\r\n")?;
+            reader.read_to_end(&mut request.stdin)?;
+        }
+        recorded.lock().unwrap().push(request);
+
+        match response {
+            MockResponse::Json(status, value) => {
+                let text = value.to_string();
+                write!(writer, "HTTP/1.1 {} X\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}", status, text.len(), text)?;
+            }
+            MockResponse::Empty(status) => write!(writer, "HTTP/1.1 {} X\r\nContent-Length: 0\r\n\r\n", status)?,
+            MockResponse::Events(events) => {
+                write!(writer, "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n")?;
+                for event in events {
+                    let line = format!("{}\n", event);
+                    write!(writer, "{:x}\r\n{}\r\n", line.len(), line)?;
+                    writer.flush()?;
+                }
+                write!(writer, "0\r\n\r\n")?;
+            }
+            MockResponse::Exec(frames) => {
+                for (stream, data) in frames {
+                    let kind = if stream == ExecStream::Stderr { 2 } else { 1 };
+                    writer.write_all(&[kind, 0, 0, 0])?;
+                    writer.write_all(&(data.len() as u32).to_be_bytes())?;
+                    writer.write_all(&data)?;
+                }
+                writer.shutdown(Shutdown::Both)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mock::{MockEngine, MockResponse};
+    use super::*;
+
+    #[test]
+    fn maps_error_statuses() {
+        let engine = MockEngine::start(|req| match req.path.as_str() {
+            "/containers/missing/json" => MockResponse::Json(404, json!({"message": "No such container: missing"})),
+            "/containers/create?name=taken" => MockResponse::Json(409, json!({"message": "name already in use"})),
+            _ => MockResponse::Json(500, json!({"message": "boom"})),
+        });
+        let docker = engine.docker();
+
+        assert!(matches!(docker.inspect_container("missing"), Err(DockerError::NotFound(m)) if m.contains("missing")));
+        assert!(matches!(docker.create_container("taken", &json!({})), Err(DockerError::Conflict(_))));
+        assert!(matches!(docker.info(), Err(DockerError::Api { status: 500, .. })));
+        assert!(matches!(Docker::new("/nonexistent.sock").version(), Err(DockerError::Connect { .. })));
+    }
+
+    #[test]
+    fn streams_exec_output() {
+        let engine = MockEngine::start(|req| match req.path.as_str() {
+            "/containers/box/exec" => MockResponse::Json(201, json!({"Id": "e1"})),
+            "/exec/e1/start" => MockResponse::Exec(vec![
+                (ExecStream::Stdout, b"hello ".to_vec()),
+                (ExecStream::Stderr, b"warning".to_vec()),
+                (ExecStream::Stdout, b"world\n".to_vec()),
+            ]),
+            "/exec/e1/json" => MockResponse::Json(200, json!({"Running": false, "ExitCode": 3})),
+            _ => MockResponse::Empty(404),
+        });
+        let docker = engine.docker();
+
+        let id = docker
+            .create_exec("box", &["cat".to_string()], &["A=1".to_string()], true)
+            .unwrap();
+        let mut session = docker.start_exec(&id).unwrap();
+        // Sends the input and closes stdin, as `cat` waits for the end of it
+        let mut stdin = session.stdin().unwrap();
+        stdin.write(b"input").unwrap();
+        stdin.0.shutdown(Shutdown::Write).unwrap();
+        let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+        session
+            .read_output(|stream, data| match stream {
+                ExecStream::Stdout => stdout.extend_from_slice(data),
+                ExecStream::Stderr => stderr.extend_from_slice(data),
+            })
+            .unwrap();
+
+        assert_eq!(stdout, b"hello world\n");
+        assert_eq!(stderr, b"warning");
+        assert_eq!(docker.exec_exit_code(&id).unwrap(), 3);
+        let requests = engine.requests();
+        assert_eq!(requests[0].body["Env"], json!(["A=1"]));
+        assert_eq!(requests[1].stdin, b"input");
+    }
+
+    #[test]
+    fn reads_event_stream() {
+        let engine = MockEngine::start(|req| {
+            assert!(req.path.starts_with("/events?filters="));
+            MockResponse::Events(vec![
+                json!({"Type": "container", "Action": "start"}),
+                json!({"Type": "container", "Action": "health_status: healthy"}),
+            ])
+        });
+
+        let mut events = engine.docker().events(&json!({"container": ["box"]})).unwrap();
+        let first = events.next_event(Duration::from_secs(1)).unwrap().unwrap();
+        let second = events.next_event(Duration::from_secs(1)).unwrap().unwrap();
+        assert_eq!(first["Action"], "start");
+        assert_eq!(second["Action"], "health_status: healthy");
+    }
+}
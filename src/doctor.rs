@@ -0,0 +1,247 @@
+use crate::config::{Config, HardeningConfig};
+use crate::config::NetworkMode;
+use crate::container::{
+    group_container_name, hardening_host_config, profile_hash, RegisteredGroup, GROUP_LABEL, MAIN_GROUP,
+    NETWORK_LABEL, PROFILE_LABEL,
+};
+use crate::egress::{allowed_hosts, proxy_status, PROXY_CONTAINER};
+use crate::images::{image_state, rclaw_images, source_hash, ImageState, SOURCE_DIR};
+use crate::runtime::container_runtime;
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// A protection and the value it is active with, `None` when it is off.
+type Protections = Vec<(&'static str, Option<String>)>;
+
+/// Prints the state of Docker, the agent images and the protections of
+/// every group's container, as configured and as actually running.
+pub fn run_doctor(config: &Config) -> Result<()> {
+    let runtime = container_runtime(&config.agent.runtime)?;
+    let docker = runtime.client();
+    match docker.version() {
+        Ok(version) => println!(
+            "Runtime: {} {} ✅ ({})",
+            runtime.name(),
+            version["Version"].as_str().unwrap_or("unknown"),
+            runtime.socket().display()
+        ),
+        Err(e) => {
+            println!("Runtime: {} not reachable ❌ ({})", runtime.name(), e);
+            return Ok(());
+        }
+    }
+    // The runtime applies its own seccomp profile unless it lacks support
+    let seccomp_supported = docker
+        .info()
+        .map(|info| info["SecurityOptions"].to_string().contains("seccomp"))
+        .unwrap_or(false);
+
+    // Compared with the sources when run from the repository
+    let sources = source_hash(Path::new(SOURCE_DIR)).ok();
+    for image in rclaw_images() {
+        let state = match &sources {
+            Some(hash) => image_state(&docker, image, hash)?,
+            None if docker.inspect_image(image).is_ok() => ImageState::Current,
+            None => ImageState::Missing,
+        };
+        let shown = match state {
+            ImageState::Current => "✅",
+            ImageState::Outdated if config.agent.auto_rebuild => "built from older sources ⚠️ (rebuilt at the next start)",
+            ImageState::Outdated => "built from older sources ⚠️ (run 'rclaw setup')",
+            ImageState::Missing => "not built ❌",
+        };
+        println!("Image:   {} {}", image, shown);
+    }
+
+    // Groups with settings or containers, the main group always
+    let mut groups: BTreeMap<String, Vec<(String, String)>> = BTreeMap::new();
+    groups.entry(MAIN_GROUP.to_string()).or_default();
+    for folder in config.groups.keys() {
+        groups.entry(folder.clone()).or_default();
+    }
+    for container in docker.list_containers(true, GROUP_LABEL).unwrap_or_default() {
+        if let Some(folder) = container.labels.get(GROUP_LABEL) {
+            groups
+                .entry(folder.clone())
+                .or_default()
+                .push((container.name, container.state));
+        }
+    }
+
+    for (folder, containers) in &groups {
+        let backend = config.backend_for(folder);
+        let hardening = config.hardening_for(folder);
+        let configured = configured_protections(&hardening, seccomp_supported);
+        println!("\nGroup '{}' ({})", folder, backend);
+
+        let registered = RegisteredGroup::from_config(config, folder, None)?;
+        let expected = group_container_name(&registered).ok();
+        let active = containers
+            .iter()
+            .find(|(name, _)| Some(name) == expected.as_ref())
+            .or(containers.first());
+        let actual = match active {
+            Some((name, state)) => {
+                println!("  Container: {} ({})", name, state);
+                let inspected = docker.inspect_container(name).ok();
+                let label = |key: &str| {
+                    inspected
+                        .as_ref()
+                        .and_then(|c| c["Config"]["Labels"][key].as_str())
+                        .map(|l| l.to_string())
+                };
+                let expected_profile = hardening_host_config(&hardening).map(|h| profile_hash(h.to_string()));
+                if label(PROFILE_LABEL) != expected_profile.ok() {
+                    println!("  The configured profile changed; the container is recreated on its next run");
+                }
+                let running_mode = label(NETWORK_LABEL)
+                    .as_deref()
+                    .and_then(|label| label.rsplit_once('-'))
+                    .map(|(mode, _)| mode.to_string());
+                if running_mode.as_deref() != Some(registered.network.mode.to_string().as_str()) {
+                    println!(
+                        "  Network:   running with {}, recreated with {} on its next run",
+                        running_mode.as_deref().unwrap_or("open"),
+                        registered.network.mode
+                    );
+                }
+                inspected.map(|c| active_protections(&c["HostConfig"], seccomp_supported))
+            }
+            None => {
+                println!("  Container: not created yet, the configured profile applies on the first run");
+                None
+            }
+        };
+
+        match registered.network.mode {
+            NetworkMode::Open => println!("  Network:   open (unrestricted) ❌"),
+            NetworkMode::None => println!("  Network:   none ✅"),
+            mode => println!(
+                "  Network:   {} via {} ({}) -> {}",
+                mode,
+                PROXY_CONTAINER,
+                proxy_status(&docker).unwrap_or_else(|| "not created yet".to_string()),
+                allowed_hosts(&registered).join(", ")
+            ),
+        }
+
+        match backend {
+            "claude" if config.agent.allow_long_lived_tokens => {
+                println!("  Token:     long-lived (claude setup-token), usable for months if leaked ⚠️")
+            }
+            "claude" => println!("  Token:     refused, Claude Code only has a long-lived one (agent.allow_long_lived_tokens) ❌"),
+            _ => println!("  Token:     short-lived, refreshed by the host ✅"),
+        }
+
+        println!("  {:<20} {:<28} ACTIVE", "PROTECTION", "CONFIGURED");
+        for (i, (label, value)) in configured.iter().enumerate() {
+            let active = match &actual {
+                Some(actual) => match &actual[i].1 {
+                    Some(value) => format!("{} ✅", value),
+                    None => "off ❌".to_string(),
+                },
+                None => "-".to_string(),
+            };
+            println!(
+                "  {:<20} {:<28} {}",
+                label,
+                value.as_deref().unwrap_or("off"),
+                active
+            );
+        }
+    }
+    Ok(())
+}
+
+fn configured_protections(profile: &HardeningConfig, seccomp_supported: bool) -> Protections {
+    let tmpfs = format!("on (tmpfs {})", profile.tmpfs_size.trim());
+    vec![
+        ("memory limit", Some(profile.memory.trim().to_string()).filter(|m| !m.is_empty())),
+        ("cpu limit", (profile.cpus > 0.0).then(|| profile.cpus.to_string())),
+        ("pids limit", (profile.pids_limit > 0).then(|| profile.pids_limit.to_string())),
+        ("capabilities", profile.cap_drop_all.then(|| "all dropped".to_string())),
+        ("no-new-privileges", profile.no_new_privileges.then(|| "on".to_string())),
+        ("read-only rootfs", profile.read_only_rootfs.then_some(tmpfs)),
+        (
+            "seccomp",
+            match profile.seccomp.as_str() {
+                "unconfined" => None,
+                "default" => seccomp_supported.then(|| "docker default".to_string()),
+                path => Some(path.to_string()),
+            },
+        ),
+    ]
+}
+
+/// The same protections read from a container's `HostConfig`.
+fn active_protections(host: &serde_json::Value, seccomp_supported: bool) -> Protections {
+    let security_opts: Vec<&str> = host["SecurityOpt"]
+        .as_array()
+        .map(|opts| opts.iter().filter_map(|o| o.as_str()).collect())
+        .unwrap_or_default();
+    let cap_drop: Vec<&str> = host["CapDrop"]
+        .as_array()
+        .map(|caps| caps.iter().filter_map(|c| c.as_str()).collect())
+        .unwrap_or_default();
+    let seccomp = security_opts.iter().find_map(|o| o.strip_prefix("seccomp="));
+
+    vec![
+        (
+            "memory limit",
+            host["Memory"].as_u64().filter(|m| *m > 0).map(format_bytes),
+        ),
+        (
+            "cpu limit",
+            host["NanoCpus"]
+                .as_u64()
+                .filter(|n| *n > 0)
+                .map(|n| (n as f64 / 1e9).to_string()),
+        ),
+        (
+            "pids limit",
+            host["PidsLimit"].as_i64().filter(|p| *p > 0).map(|p| p.to_string()),
+        ),
+        (
+            "capabilities",
+            if cap_drop.iter().any(|c| c.eq_ignore_ascii_case("ALL")) {
+                Some("all dropped".to_string())
+            } else if !cap_drop.is_empty() {
+                Some(format!("{} dropped", cap_drop.join(",")))
+            } else {
+                None
+            },
+        ),
+        (
+            "no-new-privileges",
+            security_opts
+                .iter()
+                .any(|o| o.starts_with("no-new-privileges") && !o.ends_with("false"))
+                .then(|| "on".to_string()),
+        ),
+        (
+            "read-only rootfs",
+            (host["ReadonlyRootfs"].as_bool() == Some(true)).then(|| {
+                let mounts = host["Tmpfs"].as_object().map(|t| t.len()).unwrap_or(0);
+                format!("on ({} tmpfs)", mounts)
+            }),
+        ),
+        (
+            "seccomp",
+            match seccomp {
+                Some("unconfined") => None,
+                Some(_) => Some("custom".to_string()),
+                None => seccomp_supported.then(|| "docker default".to_string()),
+            },
+        ),
+    ]
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const MIB: u64 = 1024 * 1024;
+    if bytes.is_multiple_of(1024 * MIB) {
+        format!("{}g", bytes / (1024 * MIB))
+    } else {
+        format!("{}m", bytes / MIB)
+    }
+}
@@ -0,0 +1,276 @@
+use crate::config::NetworkMode;
+use crate::container::{has_running_exec, RegisteredGroup};
+use crate::docker::{Docker, DockerError};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::os::fd::AsRawFd;
+use std::path::PathBuf;
+use tracing::info;
+
+/// Internal Docker network of the restricted containers. It has no route out;
+/// the proxy, also attached to the default bridge, is the only way through.
+pub const EGRESS_NETWORK: &str = "rclaw-egress";
+pub const PROXY_CONTAINER: &str = "rclaw-egress-proxy";
+pub const PROXY_IMAGE: &str = "rclaw-egress-proxy:latest";
+const PROXY_PORT: u16 = 3128;
+
+/// Rules the proxy enforces: each group authenticates with its token and may
+/// reach its hosts only.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct EgressRules {
+    groups: BTreeMap<String, GroupRules>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct GroupRules {
+    token: String,
+    hosts: Vec<String>,
+}
+
+/// Directory shared read-only with the proxy container.
+fn rules_dir() -> Result<PathBuf> {
+    let project_root = std::env::current_dir().context("Failed to get current dir")?;
+    Ok(project_root.join("egress"))
+}
+
+/// Hosts a group may reach in its network mode.
+pub fn allowed_hosts(group: &RegisteredGroup) -> Vec<String> {
+    let mut hosts: Vec<String> = group.backend.api_hosts().iter().map(|h| h.to_string()).collect();
+    if group.network.mode == NetworkMode::Allowlist {
+        hosts.extend(group.network.allow.iter().map(|h| h.trim().to_lowercase()));
+    }
+    hosts.sort();
+    hosts.dedup();
+    hosts
+}
+
+/// What puts a container in its group's network mode.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct NetworkSpec {
+    /// `HostConfig.NetworkMode`, the default bridge when unset.
+    pub network_mode: Option<String>,
+    pub env: Vec<String>,
+}
+
+/// Gets the group's network mode ready and returns what its container needs
+/// to be in it. For the restricted modes this updates the group's rules and
+/// starts the proxy if needed.
+pub fn prepare_network(docker: &Docker, group: &RegisteredGroup) -> Result<NetworkSpec> {
+    match group.network.mode {
+        NetworkMode::Open => Ok(NetworkSpec::default()),
+        NetworkMode::None => Ok(NetworkSpec {
+            network_mode: Some("none".to_string()),
+            env: Vec::new(),
+        }),
+        NetworkMode::LlmOnly | NetworkMode::Allowlist => {
+            let token = update_rules(&group.folder, allowed_hosts(group))?;
+            ensure_proxy(docker)?;
+            let proxy = format!("http://{}:{}@{}:{}", group.folder, token, PROXY_CONTAINER, PROXY_PORT);
+            let mut env: Vec<String> = ["HTTPS_PROXY", "https_proxy", "HTTP_PROXY", "http_proxy"]
+                .iter()
+                .map(|var| format!("{}={}", var, proxy))
+                .collect();
+            env.extend([
+                "NO_PROXY=localhost,127.0.0.1".to_string(),
+                // Node's fetch ignores the proxy variables unless asked
+                "NODE_USE_ENV_PROXY=1".to_string(),
+            ]);
+            Ok(NetworkSpec {
+                network_mode: Some(EGRESS_NETWORK.to_string()),
+                env,
+            })
+        }
+    }
+}
+
+/// Writes the group's hosts to the rules file and returns its token, which
+/// is kept across runs so the container does not have to be recreated.
+fn update_rules(folder: &str, hosts: Vec<String>) -> Result<String> {
+    let dir = rules_dir()?;
+    std::fs::create_dir_all(&dir)?;
+    // Runs of different groups, also from other rclaw processes, update the
+    // file at once; without the lock one's write would drop the other's group.
+    // It is released when the file closes.
+    let lock = std::fs::File::create(dir.join("rules.lock"))?;
+    if unsafe { libc::flock(lock.as_raw_fd(), libc::LOCK_EX) } != 0 {
+        return Err(std::io::Error::last_os_error()).context("Failed to lock the egress rules");
+    }
+    let path = dir.join("rules.json");
+    let mut rules: EgressRules = std::fs::read_to_string(&path)
+        .ok()
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default();
+
+    let token = rules
+        .groups
+        .get(folder)
+        .map(|g| g.token.clone())
+        .unwrap_or_else(|| uuid::Uuid::new_v4().simple().to_string());
+    let changed = rules.groups.get(folder).map(|g| &g.hosts) != Some(&hosts);
+    if changed {
+        rules.groups.insert(folder.to_string(), GroupRules { token: token.clone(), hosts });
+        // The proxy may read the file at any time, so it is replaced at once
+        let tmp = dir.join(format!("rules.json.{}.tmp", uuid::Uuid::new_v4().simple()));
+        std::fs::write(&tmp, serde_json::to_string_pretty(&rules)?)?;
+        std::fs::rename(&tmp, &path)?;
+    }
+    Ok(token)
+}
+
+/// Creates the internal network and starts the proxy container if needed.
+/// A proxy left from an older image is recreated once no agent uses it.
+fn ensure_proxy(docker: &Docker) -> Result<()> {
+    let network = match docker.inspect_network(EGRESS_NETWORK) {
+        Ok(network) => network,
+        Err(DockerError::NotFound(_)) => {
+            info!("Creating network {}", EGRESS_NETWORK);
+            docker
+                .create_network(EGRESS_NETWORK, true)
+                .with_context(|| format!("Failed to create network {}", EGRESS_NETWORK))?;
+            Value::Null
+        }
+        Err(e) => return Err(e).with_context(|| format!("Failed to inspect network {}", EGRESS_NETWORK)),
+    };
+
+    match docker.inspect_container(PROXY_CONTAINER) {
+        Ok(proxy) if proxy_outdated(docker, &proxy) && !proxy_in_use(docker, &network) => {
+            info!("Recreating {} with the rebuilt image", PROXY_CONTAINER);
+            match docker.remove_container(PROXY_CONTAINER, true) {
+                Ok(()) | Err(DockerError::NotFound(_)) => {}
+                Err(e) => return Err(e).with_context(|| format!("Failed to remove {}", PROXY_CONTAINER)),
+            }
+        }
+        Ok(proxy) => {
+            if proxy["State"]["Status"].as_str() != Some("running") {
+                info!("Starting {}", PROXY_CONTAINER);
+                docker
+                    .start_container(PROXY_CONTAINER)
+                    .with_context(|| format!("Failed to start {}", PROXY_CONTAINER))?;
+            }
+            return Ok(());
+        }
+        Err(DockerError::NotFound(_)) => {}
+        Err(e) => return Err(e).with_context(|| format!("Failed to inspect {}", PROXY_CONTAINER)),
+    }
+
+    info!("Creating {}", PROXY_CONTAINER);
+    let spec = json!({
+        "Image": PROXY_IMAGE,
+        "HostConfig": {
+            "Binds": [format!("{}:/etc/rclaw-egress:ro", rules_dir()?.display())],
+            "RestartPolicy": { "Name": "unless-stopped" },
+            "ReadonlyRootfs": true,
+            "CapDrop": ["ALL"],
+            "SecurityOpt": ["no-new-privileges"],
+            "Memory": 128 * 1024 * 1024,
+            "PidsLimit": 128,
+        },
+    });
+    match docker.create_container(PROXY_CONTAINER, &spec) {
+        Ok(_) => {}
+        Err(DockerError::NotFound(_)) => anyhow::bail!(
+            "Failed to create {}: the {} image is not built (run 'rclaw setup')",
+            PROXY_CONTAINER,
+            PROXY_IMAGE
+        ),
+        Err(e) => return Err(e).with_context(|| format!("Failed to create {}", PROXY_CONTAINER)),
+    }
+    docker
+        .start_container(PROXY_CONTAINER)
+        .with_context(|| format!("Failed to start {}", PROXY_CONTAINER))?;
+    docker
+        .connect_network(EGRESS_NETWORK, PROXY_CONTAINER)
+        .with_context(|| format!("Failed to connect {} to {}", PROXY_CONTAINER, EGRESS_NETWORK))?;
+    Ok(())
+}
+
+/// Whether the proxy runs an older image than the current `PROXY_IMAGE`,
+/// as after `ensure_images_current` rebuilt it.
+fn proxy_outdated(docker: &Docker, proxy: &Value) -> bool {
+    docker
+        .inspect_image(PROXY_IMAGE)
+        .is_ok_and(|image| image["Id"].as_str().is_some_and(|id| proxy["Image"].as_str() != Some(id)))
+}
+
+/// Whether an agent, of this or another rclaw process, is running in a
+/// container behind the proxy.
+fn proxy_in_use(docker: &Docker, network: &Value) -> bool {
+    let Some(containers) = network["Containers"].as_object() else {
+        return false;
+    };
+    containers
+        .values()
+        .filter_map(|c| c["Name"].as_str())
+        .filter(|name| *name != PROXY_CONTAINER)
+        .any(|name| has_running_exec(docker, name))
+}
+
+/// State of the proxy container, for `rclaw doctor`.
+pub fn proxy_status(docker: &Docker) -> Option<String> {
+    let proxy = docker.inspect_container(PROXY_CONTAINER).ok()?;
+    proxy["State"]["Status"].as_str().map(|s| s.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn allows_model_hosts_and_group_domains() {
+        let config: Config = toml::from_str(
+            "[network]\nallow = [\"github.com\"]\n[groups.family.network]\nmode = \"allowlist\"\n",
+        )
+        .unwrap();
+
+        let main = RegisteredGroup::from_config(&config, "main", Some("claude")).unwrap();
+        assert_eq!(main.network.mode, NetworkMode::LlmOnly);
+        assert!(!allowed_hosts(&main).contains(&"github.com".to_string()));
+
+        let family = RegisteredGroup::from_config(&config, "family", Some("claude")).unwrap();
+        let hosts = allowed_hosts(&family);
+        assert!(hosts.contains(&"api.anthropic.com".to_string()));
+        assert!(hosts.contains(&"github.com".to_string()));
+    }
+
+    #[test]
+    fn recreates_an_outdated_proxy_once_idle() {
+        use crate::docker::mock::{MockEngine, MockResponse};
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+
+        let busy = Arc::new(AtomicBool::new(true));
+        let agent_busy = busy.clone();
+        let engine = MockEngine::start(move |req| match (req.method.as_str(), req.path.as_str()) {
+            ("GET", "/networks/rclaw-egress") => MockResponse::Json(
+                200,
+                json!({"Containers": {
+                    "a1": {"Name": PROXY_CONTAINER},
+                    "b2": {"Name": "rclaw-agent-main-gemini"},
+                }}),
+            ),
+            ("GET", "/images/rclaw-egress-proxy:latest/json") => MockResponse::Json(200, json!({"Id": "sha256:new"})),
+            ("GET", "/containers/rclaw-egress-proxy/json") => {
+                MockResponse::Json(200, json!({"Image": "sha256:old", "State": {"Status": "running"}}))
+            }
+            ("GET", "/containers/rclaw-agent-main-gemini/json") => {
+                let execs = if agent_busy.load(Ordering::SeqCst) { json!(["e1"]) } else { json!([]) };
+                MockResponse::Json(200, json!({"ExecIDs": execs}))
+            }
+            ("POST", "/containers/create?name=rclaw-egress-proxy") => MockResponse::Json(201, json!({"Id": "c3"})),
+            _ => MockResponse::Empty(204),
+        });
+        let removed = || engine.requests().iter().any(|r| r.method == "DELETE");
+
+        // An agent still goes through the old proxy
+        ensure_proxy(&engine.docker()).unwrap();
+        assert!(!removed());
+
+        busy.store(false, Ordering::SeqCst);
+        ensure_proxy(&engine.docker()).unwrap();
+        assert!(removed());
+        assert!(engine.requests().iter().any(|r| r.path == "/containers/create?name=rclaw-egress-proxy"));
+    }
+}
@@ -0,0 +1,156 @@
+use crate::container::{agent_backend, profile_hash};
+use crate::docker::{Docker, DockerError};
+use crate::egress::PROXY_IMAGE;
+use crate::runtime::ContainerRuntime;
+use anyhow::{Context, Result};
+use std::fs;
+use std::os::unix::ffi::OsStringExt;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// Label holding the hash of the `container/` sources an image was built from.
+pub const SOURCE_LABEL: &str = "rclaw.source";
+
+/// Directory with the Dockerfiles and the scripts copied into the images.
+pub const SOURCE_DIR: &str = "container";
+
+/// Hash of every file under `dir` and its path, so editing, adding or
+/// removing any of them changes it.
+pub fn source_hash(dir: &Path) -> Result<String> {
+    let mut files = Vec::new();
+    collect_files(dir, &mut files)?;
+    files.sort();
+    let mut content = Vec::new();
+    for file in files {
+        let relative = file.strip_prefix(dir).unwrap_or(&file);
+        content.extend_from_slice(relative.to_string_lossy().as_bytes());
+        content.push(0);
+        // Docker copies a symlink as such, so its target is what counts
+        let data = match fs::read_link(&file) {
+            Ok(target) => target.into_os_string().into_vec(),
+            Err(_) => fs::read(&file).with_context(|| format!("Failed to read {}", file.display()))?,
+        };
+        content.extend(data);
+        content.push(0);
+    }
+    Ok(profile_hash(content))
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))? {
+        let entry = entry?;
+        let path = entry.path();
+        // A symlinked directory is a link like any other, not walked
+        if entry.file_type()?.is_dir() {
+            collect_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// The images rclaw runs, as built by `container/build.sh`.
+pub fn rclaw_images() -> Vec<&'static str> {
+    ["gemini", "claude"]
+        .iter()
+        .filter_map(|name| agent_backend(name).ok().map(|b| b.image()))
+        .chain([PROXY_IMAGE])
+        .collect()
+}
+
+/// Whether an image matches the current sources.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ImageState {
+    Current,
+    /// Built from other sources, or before images were labelled.
+    Outdated,
+    Missing,
+}
+
+pub fn image_state(docker: &Docker, image: &str, hash: &str) -> Result<ImageState> {
+    match docker.inspect_image(image) {
+        Ok(inspected) if inspected["Config"]["Labels"][SOURCE_LABEL].as_str() == Some(hash) => Ok(ImageState::Current),
+        Ok(_) => Ok(ImageState::Outdated),
+        Err(DockerError::NotFound(_)) => Ok(ImageState::Missing),
+        Err(e) => Err(e).with_context(|| format!("Failed to inspect image {}", image)),
+    }
+}
+
+/// Builds every image with `container/build.sh`, labelled with the hash of
+/// the sources.
+pub fn build_images(runtime: &dyn ContainerRuntime) -> Result<()> {
+    let hash = source_hash(Path::new(SOURCE_DIR))?;
+    info!("Building agent images with {} (sources {})...", runtime.name(), hash);
+    let status = std::process::Command::new("bash")
+        .arg(Path::new(SOURCE_DIR).join("build.sh"))
+        .env("CONTAINER_RUNTIME", runtime.cli())
+        .env("RCLAW_SOURCE_HASH", &hash)
+        .status()
+        .context("Failed to execute build script")?;
+    if !status.success() {
+        anyhow::bail!("Container build failed with exit code: {}", status);
+    }
+    Ok(())
+}
+
+/// Rebuilds the images when `container/` changed since they were built, as
+/// after a `git pull`. Group containers notice the new image through their
+/// `rclaw.image` label and are recreated before their next run; the egress
+/// proxy is recreated once no agent uses it. Returns whether it rebuilt.
+pub fn ensure_images_current(runtime: &dyn ContainerRuntime, auto_rebuild: bool) -> Result<bool> {
+    let dir = Path::new(SOURCE_DIR);
+    if !dir.is_dir() {
+        // Running away from the sources, nothing to compare with
+        return Ok(false);
+    }
+    let hash = source_hash(dir)?;
+    let docker = runtime.client();
+    let mut stale = Vec::new();
+    for image in rclaw_images() {
+        if image_state(&docker, image, &hash)? == ImageState::Outdated {
+            stale.push(image);
+        }
+    }
+    if stale.is_empty() {
+        return Ok(false);
+    }
+    if !auto_rebuild {
+        warn!("Images built from older sources: {} (run 'rclaw setup' to rebuild them)", stale.join(", "));
+        return Ok(false);
+    }
+
+    info!("{} changed since {} were built, rebuilding", SOURCE_DIR, stale.join(", "));
+    build_images(runtime)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn source_hash_follows_the_files() {
+        let dir = std::env::temp_dir().join(format!("rclaw-sources-{}", uuid::Uuid::new_v4().simple()));
+        fs::create_dir_all(dir.join("gemini")).unwrap();
+        fs::write(dir.join("entrypoint.js"), "console.log(1)").unwrap();
+        fs::write(dir.join("gemini/Dockerfile"), "FROM rclaw-base").unwrap();
+        let first = source_hash(&dir).unwrap();
+        assert_eq!(source_hash(&dir).unwrap(), first);
+
+        fs::write(dir.join("entrypoint.js"), "console.log(2)").unwrap();
+        let edited = source_hash(&dir).unwrap();
+        assert_ne!(edited, first);
+
+        // A file moving to another directory counts as a change
+        fs::create_dir_all(dir.join("claude")).unwrap();
+        fs::rename(dir.join("gemini/Dockerfile"), dir.join("claude/Dockerfile")).unwrap();
+        let moved = source_hash(&dir).unwrap();
+        assert_ne!(moved, edited);
+
+        // A symlink back up the tree is not walked into
+        std::os::unix::fs::symlink("..", dir.join("claude/loop")).unwrap();
+        assert_ne!(source_hash(&dir).unwrap(), moved);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,488 @@
+use crate::config::Config;
+use crate::container::{ensure_group_workspace, is_valid_group_folder, ContainerInput};
+use crate::db::{Db, Task};
+use crate::task_scheduler::{CatchUpPolicy, Delivery, TaskScheduler};
+use crate::workspace_fs::WorkspaceDir;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Where session directories live, relative to the group workspace. Only the
+/// group's own container can see them.
+const IPC_ROOT: &str = ".rclaw/ipc";
+
+/// How often an open session looks for new request files.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Tasks a non-main group may have scheduled (not counting completed ones), so
+/// a looping agent cannot flood the scheduler.
+const MAX_TASKS_PER_GROUP: usize = 20;
+
+/// A request dropped by the agent in `<session>/requests/<id>.json`. The answer
+/// is written to `<session>/responses/<id>.json`.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum IpcRequest {
+    CreateTask {
+        schedule: String,
+        prompt: String,
+        /// Only the main group may create tasks for other groups.
+        group_folder: Option<String>,
+        timezone: Option<String>,
+        catch_up: Option<String>,
+        /// Only the main group may deliver to channels.
+        deliver: Option<String>,
+    },
+    ListTasks,
+    PauseTask { task_id: String },
+    ResumeTask { task_id: String },
+    CancelTask { task_id: String },
+}
+
+#[derive(Debug, Serialize)]
+pub struct IpcResponse {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub tasks: Vec<TaskSummary>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TaskSummary {
+    pub id: String,
+    pub group_folder: String,
+    pub status: String,
+    pub schedule: String,
+    pub next_run: Option<String>,
+    pub prompt: String,
+}
+
+impl From<&Task> for TaskSummary {
+    fn from(task: &Task) -> Self {
+        TaskSummary {
+            id: task.id.clone(),
+            group_folder: task.group_folder.clone(),
+            status: task.status.clone(),
+            schedule: task.schedule.clone(),
+            next_run: task.next_run.clone(),
+            prompt: task.prompt.clone(),
+        }
+    }
+}
+
+/// The group an agent run belongs to. Taken from the `ContainerInput` the host
+/// built, never from the request itself.
+#[derive(Debug, Clone)]
+pub struct IpcCaller {
+    pub group_folder: String,
+    pub is_main: bool,
+}
+
+impl IpcCaller {
+    /// The main group manages every task; other groups only their own.
+    fn can_manage(&self, group_folder: &str) -> bool {
+        self.is_main || self.group_folder == group_folder
+    }
+}
+
+/// Applies agent requests to the task table after checking them against the
+/// caller's permissions.
+pub struct IpcHandler {
+    db: Arc<Db>,
+    scheduler: TaskScheduler,
+}
+
+impl IpcHandler {
+    pub fn new(db: Arc<Db>, config: Config) -> Self {
+        let scheduler = TaskScheduler::new(db.clone(), config);
+        IpcHandler { db, scheduler }
+    }
+
+    pub fn handle(&self, caller: &IpcCaller, request: IpcRequest) -> IpcResponse {
+        match self.apply(caller, request) {
+            Ok(tasks) => IpcResponse { ok: true, error: None, tasks },
+            Err(e) => IpcResponse {
+                ok: false,
+                error: Some(format!("{:#}", e)),
+                tasks: Vec::new(),
+            },
+        }
+    }
+
+    fn apply(&self, caller: &IpcCaller, request: IpcRequest) -> Result<Vec<TaskSummary>> {
+        match request {
+            IpcRequest::CreateTask {
+                schedule,
+                prompt,
+                group_folder,
+                timezone,
+                catch_up,
+                deliver,
+            } => {
+                let group = group_folder.unwrap_or_else(|| caller.group_folder.clone());
+                if !caller.can_manage(&group) {
+                    anyhow::bail!(
+                        "Group '{}' is not allowed to create tasks for group '{}'",
+                        caller.group_folder,
+                        group
+                    );
+                }
+                if !is_valid_group_folder(&group) {
+                    anyhow::bail!("Invalid group folder '{}'", group);
+                }
+                if prompt.trim().is_empty() {
+                    anyhow::bail!("The task prompt is empty");
+                }
+                if !caller.is_main {
+                    let scheduled = self
+                        .db
+                        .list_tasks()?
+                        .iter()
+                        .filter(|t| t.group_folder == group && t.status != "completed")
+                        .count();
+                    if scheduled >= MAX_TASKS_PER_GROUP {
+                        anyhow::bail!(
+                            "Group '{}' already has {} scheduled tasks (limit {})",
+                            group,
+                            scheduled,
+                            MAX_TASKS_PER_GROUP
+                        );
+                    }
+                }
+                let policy = match catch_up {
+                    Some(value) => CatchUpPolicy::from_str(&value)?,
+                    None => CatchUpPolicy::Once,
+                };
+                let delivery = match deliver {
+                    Some(value) => Delivery::from_str(&value)?,
+                    None => Delivery::Tui,
+                };
+                if matches!(delivery, Delivery::Channel(_)) && !caller.is_main {
+                    anyhow::bail!("Only the main group can deliver task results to a channel");
+                }
+
+                let mut task = Task {
+                    id: uuid::Uuid::new_v4().simple().to_string()[..8].to_string(),
+                    group_folder: group,
+                    prompt,
+                    schedule,
+                    last_run: None,
+                    next_run: None,
+                    status: "active".to_string(),
+                    catch_up: policy.as_str().to_string(),
+                    timezone,
+                    max_retries: 0,
+                    backoff: "exponential".to_string(),
+                    consecutive_failures: 0,
+                    last_error: None,
+                    delivery: delivery.to_string(),
+                    timeout_secs: None,
+                    backend: None,
+                    approval: None,
+                };
+                self.scheduler.schedule_new_task(&mut task, 1)?;
+                info!(
+                    "Group '{}' scheduled task {} for '{}': {}",
+                    caller.group_folder, task.id, task.group_folder, task.schedule
+                );
+                Ok(vec![TaskSummary::from(&task)])
+            }
+            IpcRequest::ListTasks => Ok(self
+                .db
+                .list_tasks()?
+                .iter()
+                .filter(|t| caller.can_manage(&t.group_folder))
+                .map(TaskSummary::from)
+                .collect()),
+            IpcRequest::PauseTask { task_id } => {
+                let mut task = self.find_task(caller, &task_id)?;
+                task.status = "paused".to_string();
+                self.db.set_task_status(&task.id, "paused")?;
+                info!("Group '{}' paused task {}", caller.group_folder, task.id);
+                Ok(vec![TaskSummary::from(&task)])
+            }
+            IpcRequest::ResumeTask { task_id } => {
+                let mut task = self.find_task(caller, &task_id)?;
+                self.scheduler.resume_task(&mut task)?;
+                info!("Group '{}' resumed task {}", caller.group_folder, task.id);
+                Ok(vec![TaskSummary::from(&task)])
+            }
+            IpcRequest::CancelTask { task_id } => {
+                let task = self.find_task(caller, &task_id)?;
+                self.db.remove_task(&task.id)?;
+                info!("Group '{}' cancelled task {}", caller.group_folder, task.id);
+                Ok(vec![TaskSummary::from(&task)])
+            }
+        }
+    }
+
+    /// Tasks of groups the caller cannot manage are reported as missing.
+    fn find_task(&self, caller: &IpcCaller, id: &str) -> Result<Task> {
+        self.db
+            .get_task(id)?
+            .filter(|t| caller.can_manage(&t.group_folder))
+            .with_context(|| format!("Task '{}' not found", id))
+    }
+
+    /// Answers every complete request file in `requests`. Both directories
+    /// are writable by the agent, so nothing in them is followed if it is a
+    /// symlink: the host would read or write its target with its own rights.
+    fn process_pending(&self, caller: &IpcCaller, requests: &WorkspaceDir, responses: &WorkspaceDir) -> Result<()> {
+        for name in requests.entries()? {
+            let path = Path::new(&name);
+            // The agent writes `<id>.json.tmp` and renames it when complete.
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            let Some(id) = path.file_stem().and_then(|s| s.to_str()).map(str::to_string) else {
+                continue;
+            };
+
+            let content = requests.read_to_string(&name);
+            requests.remove(&name)?;
+
+            let response = match content
+                .context("Failed to read request")
+                .and_then(|c| serde_json::from_str::<IpcRequest>(&c).context("Invalid request"))
+            {
+                Ok(request) => self.handle(caller, request),
+                Err(e) => IpcResponse {
+                    ok: false,
+                    error: Some(format!("{:#}", e)),
+                    tasks: Vec::new(),
+                },
+            };
+            if let Some(err) = &response.error {
+                warn!("IPC request {} from group '{}' rejected: {}", id, caller.group_folder, err);
+            }
+
+            let tmp = format!("{}.json.tmp", id);
+            responses.write_new(&tmp, serde_json::to_string(&response)?.as_bytes())?;
+            responses.rename(&tmp, format!("{}.json", id))?;
+        }
+        Ok(())
+    }
+}
+
+/// A directory the agent can drop requests into while it runs. Requests are
+/// answered from a background thread until the session is dropped, which
+/// handles whatever is left and removes the directory.
+pub struct IpcSession {
+    /// `IPC_ROOT` in the workspace, and the session's name in it.
+    root: WorkspaceDir,
+    name: String,
+    stop: Arc<AtomicBool>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl IpcSession {
+    /// Creates a session for the run described by `input` and points
+    /// `input.ipc_dir` at it.
+    pub fn open(db: Arc<Db>, config: Config, input: &mut ContainerInput) -> Result<Self> {
+        let name = uuid::Uuid::new_v4().simple().to_string();
+        let workspace = WorkspaceDir::open(&ensure_group_workspace(&input.group_folder)?)?;
+        let root = workspace.create_dir_all(Path::new(IPC_ROOT))?;
+        let session = root.create_dir(&name)?;
+        let requests = session.create_dir("requests")?;
+        let responses = session.create_dir("responses")?;
+
+        let caller = IpcCaller {
+            group_folder: input.group_folder.clone(),
+            is_main: input.is_main,
+        };
+        input.ipc_dir = Some(format!("/home/rclaw/workspace/{}/{}", IPC_ROOT, name));
+
+        let handler = IpcHandler::new(db, config);
+        let stop = Arc::new(AtomicBool::new(false));
+        let worker = {
+            let stop = stop.clone();
+            thread::spawn(move || loop {
+                // Read the flag first so requests written just before the run
+                // ended still get a pass.
+                let stopping = stop.load(Ordering::SeqCst);
+                if let Err(e) = handler.process_pending(&caller, &requests, &responses) {
+                    warn!("Failed to process IPC requests: {:#}", e);
+                }
+                if stopping {
+                    break;
+                }
+                thread::sleep(POLL_INTERVAL);
+            })
+        };
+
+        Ok(IpcSession {
+            root,
+            name,
+            stop,
+            worker: Some(worker),
+        })
+    }
+}
+
+impl Drop for IpcSession {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+        // Whatever the agent replaced with a symlink is left behind
+        if let Ok(session) = self.root.open_dir(&self.name) {
+            for subdir in ["requests", "responses"] {
+                if let Ok(dir) = session.open_dir(subdir) {
+                    for name in dir.entries().unwrap_or_default() {
+                        let _ = dir.remove(name);
+                    }
+                }
+                let _ = session.remove_dir(subdir);
+            }
+            let _ = self.root.remove_dir(&self.name);
+        }
+    }
+}
+
+/// Opens an IPC session for an agent run. The run goes ahead without task
+/// management if the directory cannot be created.
+pub fn start_session(db: Arc<Db>, config: Config, input: &mut ContainerInput) -> Option<IpcSession> {
+    match IpcSession::open(db, config, input) {
+        Ok(session) => Some(session),
+        Err(e) => {
+            warn!("Task management IPC unavailable for this run: {:#}", e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn handler() -> IpcHandler {
+        let db = Arc::new(Db::new(":memory:").unwrap());
+        IpcHandler::new(db, Config::default())
+    }
+
+    fn caller(group: &str, is_main: bool) -> IpcCaller {
+        IpcCaller {
+            group_folder: group.to_string(),
+            is_main,
+        }
+    }
+
+    fn create(group_folder: Option<&str>) -> IpcRequest {
+        IpcRequest::CreateTask {
+            schedule: "every 1h".to_string(),
+            prompt: "check the inbox".to_string(),
+            group_folder: group_folder.map(str::to_string),
+            timezone: None,
+            catch_up: None,
+            deliver: None,
+        }
+    }
+
+    #[test]
+    fn group_creates_tasks_for_itself_only() {
+        let handler = handler();
+        let family = caller("family", false);
+
+        let own = handler.handle(&family, create(None));
+        assert!(own.ok, "{:?}", own.error);
+        assert_eq!(own.tasks[0].group_folder, "family");
+        assert!(own.tasks[0].next_run.is_some());
+
+        let other = handler.handle(&family, create(Some("work")));
+        assert!(!other.ok);
+        assert_eq!(handler.db.list_tasks().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn main_group_manages_every_task() {
+        let handler = handler();
+        let main = caller("main", true);
+        let family = caller("family", false);
+
+        let created = handler.handle(&main, create(Some("work")));
+        assert!(created.ok, "{:?}", created.error);
+        handler.handle(&family, create(None));
+
+        assert_eq!(handler.handle(&main, IpcRequest::ListTasks).tasks.len(), 2);
+        assert_eq!(handler.handle(&family, IpcRequest::ListTasks).tasks.len(), 1);
+
+        let work_task = created.tasks[0].id.clone();
+        let denied = handler.handle(&family, IpcRequest::CancelTask { task_id: work_task.clone() });
+        assert!(!denied.ok);
+        let cancelled = handler.handle(&main, IpcRequest::CancelTask { task_id: work_task });
+        assert!(cancelled.ok);
+        assert_eq!(handler.db.list_tasks().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn planted_symlinks_are_not_followed() {
+        use std::fs;
+        use std::os::unix::fs::symlink;
+
+        let handler = handler();
+        let root = std::env::temp_dir().join(format!("rclaw-ipc-{}", uuid::Uuid::new_v4().simple()));
+        fs::create_dir_all(root.join("session/requests")).unwrap();
+        fs::create_dir_all(root.join("session/responses")).unwrap();
+        fs::write(root.join("host-file"), "host").unwrap();
+        symlink(root.join("host-file"), root.join("session/requests/leak.json")).unwrap();
+        symlink(root.join("host-file"), root.join("session/responses/list.json.tmp")).unwrap();
+        fs::write(root.join("session/requests/list.json"), r#"{"action":"list_tasks"}"#).unwrap();
+
+        let session = WorkspaceDir::open(&root.join("session")).unwrap();
+        let (requests, responses) = (session.open_dir("requests").unwrap(), session.open_dir("responses").unwrap());
+        handler.process_pending(&caller("main", true), &requests, &responses).unwrap();
+
+        assert_eq!(fs::read_to_string(root.join("host-file")).unwrap(), "host");
+        let leak = fs::read_to_string(root.join("session/responses/leak.json")).unwrap();
+        assert!(leak.contains(r#""ok":false"#) && !leak.contains("host"), "{}", leak);
+        let list = fs::read_to_string(root.join("session/responses/list.json")).unwrap();
+        assert!(list.contains(r#""ok":true"#), "{}", list);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn answers_requests_dropped_during_the_run() {
+        use std::fs;
+
+        let handler = handler();
+        let main = caller("main", true);
+        let root = std::env::temp_dir().join(format!("rclaw-ipc-{}", uuid::Uuid::new_v4().simple()));
+        fs::create_dir_all(root.join("requests")).unwrap();
+        fs::create_dir_all(root.join("responses")).unwrap();
+        let session = WorkspaceDir::open(&root).unwrap();
+        let (requests, responses) = (session.open_dir("requests").unwrap(), session.open_dir("responses").unwrap());
+
+        // The session keeps polling the same directories
+        for id in ["first", "second"] {
+            fs::write(root.join(format!("requests/{}.json", id)), r#"{"action":"list_tasks"}"#).unwrap();
+            handler.process_pending(&main, &requests, &responses).unwrap();
+            assert!(root.join(format!("responses/{}.json", id)).exists(), "{} not answered", id);
+        }
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn invalid_requests_are_rejected() {
+        let handler = handler();
+        let main = caller("main", true);
+
+        let bad_schedule = IpcRequest::CreateTask {
+            schedule: "whenever".to_string(),
+            prompt: "x".to_string(),
+            group_folder: None,
+            timezone: None,
+            catch_up: None,
+            deliver: None,
+        };
+        assert!(!handler.handle(&main, bad_schedule).ok);
+        assert!(!handler.handle(&main, create(Some("../etc"))).ok);
+        assert!(handler.db.list_tasks().unwrap().is_empty());
+    }
+}
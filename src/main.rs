@@ -1,16 +1,45 @@
+mod approval;
+mod artifacts;
 mod auth;
 mod auth_discovery;
+mod config;
 mod container;
+mod credentials;
 mod db;
+mod diagnostics;
+mod docker;
+mod doctor;
+mod images;
+mod egress;
+mod ipc;
+mod runtime;
+mod sessions;
+mod task_cli;
 mod task_scheduler;
 mod ui;
-
-use crate::auth::setup_gemini_auth;
-use crate::container::{run_container_agent, ContainerInput, RegisteredGroup};
+mod workspace_fs;
+
+use crate::approval::terminal_prompter;
+use crate::auth::{setup_claude_auth, setup_gemini_auth, store_gemini_tokens};
+use crate::config::{Config, ContainerMode};
+use crate::container::{
+    agent_backend, ensure_group_workspace, remove_agent_containers, run_container_agent,
+    split_backend_prefix, stop_idle_containers, AgentEvent, CancelToken, ContainerInput,
+    warm_pool, RegisteredGroup, RunOptions, MAIN_GROUP,
+};
+use crate::credentials::CredentialStore;
 use crate::db::Db;
+use crate::docker::DockerError;
+use crate::doctor::run_doctor;
+use crate::images::{build_images, ensure_images_current};
+use crate::ipc::start_session;
+use crate::runtime::container_runtime;
+use crate::sessions::{describe_sessions, find_session, open_session, record_turn};
+use crate::task_cli::{handle_task_command, TaskCommand};
 use crate::task_scheduler::TaskScheduler;
-use crate::ui::{run_tui, App, AppEvent, TuiLogger, WorkerEvent};
+use crate::ui::{approval_prompter, run_tui, App, AppEvent, TuiLogger, WorkerEvent};
 use clap::{Parser, Subcommand};
+use std::collections::VecDeque;
 use std::path::PathBuf;
 use std::sync::mpsc;
 use std::sync::Arc;
@@ -37,9 +66,24 @@ enum Commands {
         prompt: String,
         #[arg(short, long, default_value = "main")]
         group: String,
+        /// Agent backend for this prompt: gemini or claude (defaults to the group's)
+        #[arg(short, long)]
+        backend: Option<String>,
+        /// Continue this session (the id printed after a run) instead of starting a new one
+        #[arg(short, long)]
+        session: Option<String>,
     },
+    /// Store the credentials rclaw hands to an agent backend: gemini or claude
+    Auth { backend: String },
     /// Initialize or check DB
     DbCheck,
+    /// Check Docker, the agent images and the protections of each group's container
+    Doctor,
+    /// Manage scheduled tasks
+    Task {
+        #[command(subcommand)]
+        command: TaskCommand,
+    },
 }
 
 #[tokio::main]
@@ -63,15 +107,34 @@ async fn main() {
         tracing::subscriber::set_global_default(subscriber)
             .expect("setting default subscriber failed");
     } else {
-        // Modo headless estándar
+        // Modo headless estándar. Task management commands print their own output,
+        // except run-now which shows the agent run as it happens.
+        let level = match &cli.command {
+            Some(Commands::Task { command }) if !matches!(command, TaskCommand::RunNow { .. }) => {
+                Level::WARN
+            }
+            Some(Commands::Doctor) => Level::WARN,
+            _ => Level::INFO,
+        };
         let subscriber = FmtSubscriber::builder()
-            .with_max_level(Level::INFO)
+            .with_max_level(level)
             .finish();
         tracing::subscriber::set_global_default(subscriber)
             .expect("setting default subscriber failed");
     }
 
     let db_path = PathBuf::from("rclaw.db");
+    let config = match Config::load("rclaw.toml") {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // Validated in Config::load
+    let default_backend = agent_backend(&config.agent.backend).expect("invalid agent.backend");
+    let runtime = container_runtime(&config.agent.runtime).expect("invalid agent.runtime");
 
     match &cli.command {
         Some(Commands::Setup) => {
@@ -79,11 +142,8 @@ async fn main() {
             let db = Db::new(&db_path).expect("Failed to open DB");
             
             let has_auth = db.get_auth_key("gemini_access_token").unwrap_or(None).is_some();
-            let has_image = std::process::Command::new("docker")
-                .args(&["inspect", "--type=image", "rclaw-agent:latest"])
-                .output()
-                .map(|o| o.status.success())
-                .unwrap_or(false);
+            let default_image = default_backend.image();
+            let has_image = runtime.client().inspect_image(default_image).is_ok();
 
             let mut run_auth = true;
             let mut run_build = true;
@@ -125,9 +185,8 @@ async fn main() {
 
             let mut auth_success = !run_auth;
             if run_auth {
-                if let Some((access, refresh)) = setup_gemini_auth().await {
-                    db.set_auth_key("gemini_access_token", &access).unwrap();
-                    db.set_auth_key("gemini_refresh_token", &refresh).unwrap();
+                if let Some(tokens) = setup_gemini_auth().await {
+                    store_gemini_tokens(&db, &tokens).unwrap();
                     info!("Credentials saved to database.");
                     auth_success = true;
                 } else {
@@ -136,71 +195,70 @@ async fn main() {
             }
 
             if auth_success && run_build {
-                info!("Stopping and removing existing rclaw-agent-singleton container...");
-                let _ = std::process::Command::new("docker")
-                    .args(&["stop", "rclaw-agent-singleton"])
-                    .stdout(std::process::Stdio::null())
-                    .stderr(std::process::Stdio::null())
-                    .status();
-                let _ = std::process::Command::new("docker")
-                    .args(&["rm", "rclaw-agent-singleton"])
-                    .stdout(std::process::Stdio::null())
-                    .stderr(std::process::Stdio::null())
-                    .status();
-
-                info!("Building agent containers...");
-                let status = std::process::Command::new("bash")
-                    .arg("container/build.sh")
-                    .status()
-                    .expect("Failed to execute build script");
-
-                if status.success() {
-                    info!("Containers built successfully.");
-
-                    // Sync initial memory to workspace preserving existing files
-                    let memory_src_path = std::path::Path::new("container/setup/memory");
-
-                    if memory_src_path.exists() {
-                        std::fs::create_dir_all("workspace/memory").ok();
-
-                        // Use rsync recursively (-a) and do not overwrite existing files (--ignore-existing)
-                        let status = std::process::Command::new("rsync")
-                            .args([
-                                "-a",
-                                "--ignore-existing",
-                                "container/setup/memory/",
-                                "workspace/memory/",
-                            ])
-                            .status();
-
-                        match status {
-                            Ok(s) if s.success() => info!("Initial memory synced to workspace."),
-                            _ => error!("Failed to sync initial memory to workspace."),
+                info!("Removing existing agent containers...");
+                if let Err(e) = remove_agent_containers(runtime) {
+                    error!("Failed to remove agent containers: {:#}", e);
+                }
+
+                match build_images(runtime) {
+                    Ok(()) => {
+                        info!("Containers built successfully.");
+
+                        // Sync initial memory to the main group workspace preserving existing files
+                        let memory_src_path = std::path::Path::new("container/setup/memory");
+
+                        if memory_src_path.exists() {
+                            if let Err(e) = ensure_group_workspace(MAIN_GROUP) {
+                                error!("Failed to prepare the main group workspace: {:#}", e);
+                            }
+                            std::fs::create_dir_all("workspace/main/memory").ok();
+
+                            // Use rsync recursively (-a) and do not overwrite existing files (--ignore-existing)
+                            let status = std::process::Command::new("rsync")
+                                .args([
+                                    "-a",
+                                    "--ignore-existing",
+                                    "container/setup/memory/",
+                                    "workspace/main/memory/",
+                                ])
+                                .status();
+
+                            match status {
+                                Ok(s) if s.success() => info!("Initial memory synced to workspace."),
+                                _ => error!("Failed to sync initial memory to workspace."),
+                            }
                         }
                     }
-                } else {
-                    error!("Container build failed with exit code: {}", status);
+                    Err(e) => error!("{:#}", e),
                 }
             }
         }
         Some(Commands::Start) => {
             // 0. Pre-flight check: Ensure docker image exists
-            let check_image = std::process::Command::new("docker")
-                .args(&["inspect", "--type=image", "rclaw-agent:latest"])
-                .output();
-
-            match check_image {
-                Ok(output) if output.status.success() => {
+            let default_image = default_backend.image();
+            match runtime.client().inspect_image(default_image) {
+                Ok(_) => {
                     // Solo procedemos si la imagen existe
                 }
-                _ => {
+                Err(e @ DockerError::Connect { .. }) => {
+                    eprintln!("\n❌ ERROR CRÍTICO: {}", e);
+                    return;
+                }
+                Err(_) => {
                     // Imprimir directamente a stderr para asegurar visibilidad antes de que el logger se apropie de todo
-                    eprintln!("\n❌ ERROR CRÍTICO: No se ha encontrado la imagen de Docker 'rclaw-agent:latest'.");
+                    eprintln!("\n❌ ERROR CRÍTICO: No se ha encontrado la imagen de Docker '{}'.", default_image);
                     eprintln!("👉 Por favor, ejecuta 'cargo run -- setup' primero para construir las imágenes necesarias.\n");
                     return;
                 }
             }
 
+            // Reconstruir las imágenes si container/ cambió desde que se construyeron
+            match ensure_images_current(runtime, config.agent.auto_rebuild) {
+                Ok(true) => eprintln!("🔄 Images rebuilt: container/ changed since the last build."),
+                Ok(false) => {}
+                Err(e) => eprintln!("⚠️  Could not rebuild the images, using the current ones: {:#}", e),
+            }
+
             info!("Initializing Rclaw...");
             // Inicializar DB
             let db: Db = match Db::new(&db_path) {
@@ -214,62 +272,205 @@ async fn main() {
 
             info!("Database ready.");
 
+            // Canales para comunicación TUI <-> Worker
+            let (tx_app, rx_worker) = mpsc::channel();
+            let tx_self = tx_app.clone();
+            let (tx_worker, rx_app) = mpsc::channel::<WorkerEvent>();
+
             // Iniciar el planificador de tareas
-            let task_scheduler = TaskScheduler::new(db.clone());
+            let task_scheduler = Arc::new(
+                TaskScheduler::new(db.clone(), config.clone())
+                    .with_notifier(tx_worker.clone()),
+            );
             tokio::spawn(async move {
                 task_scheduler.run().await;
             });
             info!("Task scheduler initialized.");
 
-            // Canales para comunicación TUI <-> Worker
-            let (tx_app, rx_worker) = mpsc::channel();
-            let (tx_worker, rx_app) = mpsc::channel();
+            // Parar los contenedores de grupos inactivos
+            if let Some(idle) = config.agent.idle_stop() {
+                tokio::spawn(async move {
+                    let mut interval = tokio::time::interval(std::time::Duration::from_secs(60));
+                    loop {
+                        interval.tick().await;
+                        match tokio::task::spawn_blocking(move || stop_idle_containers(runtime, idle)).await {
+                            Ok(Err(e)) => error!("Idle container check failed: {:#}", e),
+                            Err(e) => error!("Idle container check join error: {:?}", e),
+                            Ok(Ok(())) => {}
+                        }
+                    }
+                });
+            }
+
+            // Preparar los contenedores de los grupos en modo efímero
+            let ephemeral_groups = std::iter::once(MAIN_GROUP)
+                .chain(config.groups.keys().map(|folder| folder.as_str()))
+                .filter(|folder| config.mode_for(folder) == ContainerMode::Ephemeral);
+            for folder in ephemeral_groups {
+                match RegisteredGroup::from_config(&config, folder, None) {
+                    Ok(group) => {
+                        tokio::task::spawn_blocking(move || {
+                            if let Err(e) = warm_pool(&group) {
+                                error!("Failed to warm the container pool of '{}': {:#}", group.folder, e);
+                            }
+                        });
+                    }
+                    Err(e) => error!("Invalid group '{}': {:#}", folder, e),
+                }
+            }
 
             // Background worker para procesar inputs
-            tokio::spawn(async move {
+            let worker_config = config.clone();
+            let agent_timeout = config.agent.timeout();
+            // A thread of its own, since it blocks on the channel: on a tokio
+            // worker it could starve the task that reports a finished run
+            let runtime_handle = tokio::runtime::Handle::current();
+            std::thread::spawn(move || {
                 info!("Worker thread started.");
+                // Prompts run one at a time, since they share the group's
+                // container and session; the others wait here in order
+                let mut queued: VecDeque<String> = VecDeque::new();
+                let mut running = false;
+                // Stops the prompt in progress when the TUI asks to cancel it
+                let mut current_run = CancelToken::new();
+                // Session the next prompt continues; a new one starts when unset
+                let mut active_session: Option<String> = None;
                 while let Ok(event) = rx_worker.recv() {
                     match event {
-                        AppEvent::Input(prompt) => {
-                            info!("Processing input: {}", prompt);
-
-                            let group_config = RegisteredGroup {
-                                name: "main".to_string(),
-                                folder: "main".to_string(),
+                        AppEvent::Cancel => {
+                            info!("Cancelling the current prompt and {} queued.", queued.len());
+                            current_run.cancel();
+                            for _ in queued.drain(..) {
+                                let _ = tx_worker.send(WorkerEvent::Failed("Cancelled before it started".to_string()));
+                            }
+                        }
+                        AppEvent::RunFinished => running = false,
+                        AppEvent::NewSession => {
+                            active_session = None;
+                            let _ = tx_worker.send(WorkerEvent::SessionSwitched { title: None, turns: Vec::new() });
+                        }
+                        AppEvent::ListSessions => {
+                            let notice = match db.list_sessions(MAIN_GROUP, 10) {
+                                Ok(sessions) => describe_sessions(&sessions, active_session.as_deref()),
+                                Err(e) => format!("Failed to list sessions: {}", e),
                             };
+                            let _ = tx_worker.send(WorkerEvent::Notice(notice));
+                        }
+                        AppEvent::SwitchSession(key) => {
+                            let switched = find_session(&db, MAIN_GROUP, &key)
+                                .and_then(|session| Ok((db.get_session_turns(&session.id)?, session)));
+                            match switched {
+                                Ok((turns, session)) => {
+                                    active_session = Some(session.id);
+                                    let _ = tx_worker.send(WorkerEvent::SessionSwitched {
+                                        title: Some(session.title),
+                                        turns,
+                                    });
+                                }
+                                Err(e) => {
+                                    let _ = tx_worker.send(WorkerEvent::Notice(format!("{:#}", e)));
+                                }
+                            }
+                        }
+                        AppEvent::Input(prompt) => queued.push_back(prompt),
+                    }
 
-                            let input = ContainerInput {
-                                prompt,
-                                session_id: "interactive".to_string(),
-                                group_folder: "main".to_string(),
-                                chat_jid: "tui-user".to_string(),
-                                is_main: true,
-                                is_scheduled_task: None,
-                            };
+                    while !running {
+                        let Some(prompt) = queued.pop_front() else {
+                            break;
+                        };
+                        info!("Processing input: {}", prompt);
+
+                        // "@claude ..." runs a single prompt with another backend
+                        let (backend, prompt) = match split_backend_prefix(&prompt) {
+                            Some((backend, rest)) => (backend.name(), rest.to_string()),
+                            None => (worker_config.backend_for(MAIN_GROUP), prompt),
+                        };
+                        let group_config = match RegisteredGroup::from_config(&worker_config, MAIN_GROUP, Some(backend)) {
+                            Ok(group) => group,
+                            Err(e) => {
+                                let _ = tx_worker.send(WorkerEvent::Failed(format!("{:#}", e)));
+                                continue;
+                            }
+                        };
+
+                        let session = match open_session(&db, active_session.as_deref(), MAIN_GROUP, backend, &prompt) {
+                            Ok(session) => session,
+                            Err(e) => {
+                                let _ = tx_worker.send(WorkerEvent::Failed(format!("{:#}", e)));
+                                continue;
+                            }
+                        };
+                        if active_session.as_deref() != Some(session.id.as_str()) {
+                            active_session = Some(session.id.clone());
+                            let _ = tx_worker.send(WorkerEvent::SessionStarted { title: session.title.clone() });
+                        }
 
-                            // Ejecutar agente en un hilo bloqueante pero sin mover el input permanentemente
-                            let worker_tx = tx_worker.clone();
-                            tokio::task::spawn_blocking(move || {
-                                match run_container_agent(&group_config, &input) {
-                                    Ok(output) => {
-                                        if let Some(res) = output.result {
-                                            let _ = worker_tx.send(WorkerEvent::Response(res));
-                                        } else if let Some(err) = output.error {
-                                            let _ = worker_tx.send(WorkerEvent::Response(format!(
-                                                "Error: {}",
-                                                err
-                                            )));
-                                        }
-                                    }
-                                    Err(e) => {
-                                        let _ = worker_tx.send(WorkerEvent::Response(format!(
-                                            "Container Error: {}",
-                                            e
-                                        )));
-                                    }
+                        let mut input = ContainerInput {
+                            prompt: prompt.clone(),
+                            session_id: session.agent_session_id.clone(),
+                            group_folder: MAIN_GROUP.to_string(),
+                            chat_jid: "tui-user".to_string(),
+                            is_main: true,
+                            is_scheduled_task: None,
+                            ipc_dir: None,
+                        };
+                        let ipc = start_session(db.clone(), worker_config.clone(), &mut input);
+
+                        // Ejecutar agente en un hilo bloqueante pero sin mover el input permanentemente
+                        current_run = CancelToken::new();
+                        running = true;
+                        let cancel = current_run.clone();
+                        let worker_tx = tx_worker.clone();
+                        let db = db.clone();
+                        let credentials = CredentialStore::new(db.clone(), &worker_config.agent);
+                        let run = runtime_handle.spawn_blocking(move || {
+                            let _ipc = ipc;
+
+                            // Reenviar los eventos a la TUI según llegan
+                            let (events_tx, events_rx) = mpsc::channel::<AgentEvent>();
+                            let forward_tx = worker_tx.clone();
+                            let forwarder = std::thread::spawn(move || {
+                                for event in events_rx {
+                                    let _ = forward_tx.send(WorkerEvent::Agent(event));
                                 }
                             });
-                        }
+
+                            let options = RunOptions {
+                                timeout: agent_timeout,
+                                cancel,
+                                events: Some(events_tx),
+                                approvals: Some(approval_prompter(worker_tx.clone())),
+                                credentials: Some(credentials),
+                            };
+                            let outcome = run_container_agent(&group_config, &input, &options);
+                            // The final event must not overtake the streamed ones
+                            drop(options);
+                            let _ = forwarder.join();
+
+                            if let Ok(output) = &outcome {
+                                if let Err(e) = record_turn(&db, &session, &prompt, output) {
+                                    error!("Failed to record the session turn: {:#}", e);
+                                }
+                            }
+                            let final_event = match outcome {
+                                Ok(output) => match output.error {
+                                    Some(err) if output.result.is_none() => WorkerEvent::Failed(err),
+                                    _ => WorkerEvent::Response {
+                                        events: output.events,
+                                        artifacts: output.artifacts,
+                                    },
+                                },
+                                Err(e) => WorkerEvent::Failed(format!("Container Error: {:#}", e)),
+                            };
+                            let _ = worker_tx.send(final_event);
+                        });
+                        // Also if the run panics, or the queue would stall
+                        let finished_tx = tx_self.clone();
+                        runtime_handle.spawn(async move {
+                            let _ = run.await;
+                            let _ = finished_tx.send(AppEvent::RunFinished);
+                        });
                     }
                 }
             });
@@ -281,31 +482,85 @@ async fn main() {
                 }
             }
         }
-        Some(Commands::Run { prompt, group }) => {
+        Some(Commands::Run { prompt, group, backend, session }) => {
             info!(
                 "Running agent for group '{}' with prompt: {}",
                 group, prompt
             );
 
-            let group_config = RegisteredGroup {
-                name: group.clone(),
-                folder: group.clone(),
+            let group_config = match RegisteredGroup::from_config(&config, group, backend.as_deref()) {
+                Ok(group) => group,
+                Err(e) => {
+                    eprintln!("Error: {:#}", e);
+                    std::process::exit(1);
+                }
             };
 
-            let input = ContainerInput {
+            let db = match Db::new(&db_path) {
+                Ok(db) => Some(Arc::new(db)),
+                Err(e) => {
+                    error!("Failed to init DB, sessions and task management disabled: {}", e);
+                    None
+                }
+            };
+            let agent_session = match &db {
+                Some(db) => {
+                    match open_session(db, session.as_deref(), group, group_config.backend.name(), prompt) {
+                        Ok(session) => Some(session),
+                        Err(e) => {
+                            eprintln!("Error: {:#}", e);
+                            std::process::exit(1);
+                        }
+                    }
+                }
+                None => None,
+            };
+
+            let mut input = ContainerInput {
                 prompt: prompt.clone(),
-                session_id: "cli-run".to_string(),
+                session_id: agent_session.as_ref().and_then(|s| s.agent_session_id.clone()),
                 group_folder: group.clone(),
                 chat_jid: "test-user@s.whatsapp.net".to_string(),
-                is_main: group == "main",
+                is_main: group == MAIN_GROUP,
                 is_scheduled_task: None,
+                ipc_dir: None,
             };
+            let ipc = db
+                .clone()
+                .and_then(|db| start_session(db, config.clone(), &mut input));
+            let credentials = db.clone().map(|db| CredentialStore::new(db, &config.agent));
+
+            // Ctrl+C stops the agent inside the container too, not just this client
+            let cancel = CancelToken::new();
+            let on_interrupt = cancel.clone();
+            tokio::spawn(async move {
+                if tokio::signal::ctrl_c().await.is_ok() {
+                    on_interrupt.cancel();
+                }
+            });
 
-            match tokio::task::spawn_blocking(move || run_container_agent(&group_config, &input))
-                .await
+            let timeout = config.agent.timeout();
+            match tokio::task::spawn_blocking(move || {
+                let _ipc = ipc;
+                let options = RunOptions {
+                    timeout,
+                    cancel,
+                    events: None,
+                    approvals: Some(terminal_prompter()),
+                    credentials,
+                };
+                run_container_agent(&group_config, &input, &options)
+            })
+            .await
             {
                 Ok(Ok(output)) => {
                     info!("Agent finished: {:?}", output);
+                    if let (Some(db), Some(session)) = (&db, &agent_session) {
+                        match record_turn(db, session, prompt, &output) {
+                            Ok(()) => info!("Session {} (continue it with --session {})", session.id, session.id),
+                            Err(e) => error!("Failed to record the session turn: {:#}", e),
+                        }
+                    }
                 }
                 Ok(Err(e)) => {
                     info!("Agent failed: {:?}", e);
@@ -315,6 +570,53 @@ async fn main() {
                 }
             }
         }
+        Some(Commands::Task { command }) => {
+            let db = match Db::new(&db_path) {
+                Ok(db) => Arc::new(db),
+                Err(e) => {
+                    error!("Failed to init DB: {}", e);
+                    return;
+                }
+            };
+            if let Err(e) = handle_task_command(db, &config, command).await {
+                eprintln!("Error: {:#}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Doctor) => {
+            if let Err(e) = run_doctor(&config) {
+                eprintln!("Error: {:#}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Auth { backend }) => {
+            let db = Db::new(&db_path).expect("Failed to open DB");
+            let stored = match backend.as_str() {
+                "gemini" => match setup_gemini_auth().await {
+                    Some(tokens) => store_gemini_tokens(&db, &tokens).map(|_| true),
+                    None => Ok(false),
+                },
+                "claude" => match setup_claude_auth() {
+                    Some(token) => db.set_auth_key("claude_oauth_token", &token).map(|_| true).map_err(Into::into),
+                    None => Ok(false),
+                },
+                other => {
+                    eprintln!("Error: unknown backend '{}' (expected gemini or claude)", other);
+                    std::process::exit(1);
+                }
+            };
+            match stored {
+                Ok(true) if backend == "claude" => println!(
+                    "✅ claude token saved. It is long-lived: agents only get it with agent.allow_long_lived_tokens set."
+                ),
+                Ok(true) => println!("✅ {} credentials saved. Agents get short-lived tokens from them.", backend),
+                Ok(false) => std::process::exit(1),
+                Err(e) => {
+                    eprintln!("Error: failed to save the credentials: {:#}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
         Some(Commands::DbCheck) => match Db::new(&db_path) {
             Ok(_) => info!("Database initialized successfully at {:?}", db_path),
             Err(e) => error!("Database init failed: {}", e),
@@ -0,0 +1,134 @@
+use crate::docker::Docker;
+use anyhow::Result;
+use serde_json::{json, Value};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+/// A container engine the agents can run on. Both speak the Docker Engine API
+/// on a Unix socket; they differ in where that socket is and in how the host
+/// user is mapped into the containers.
+pub trait ContainerRuntime: Send + Sync {
+    fn name(&self) -> &'static str;
+    /// CLI `container/build.sh` builds the images with.
+    fn cli(&self) -> &'static str;
+    fn socket(&self) -> PathBuf;
+    /// Sets the user of a `POST /containers/create` body so that files the
+    /// agent writes in the workspace belong to the host user.
+    fn map_user(&self, spec: &mut Value);
+
+    fn client(&self) -> Docker {
+        Docker::new(self.socket())
+    }
+}
+
+pub struct DockerRuntime;
+
+impl ContainerRuntime for DockerRuntime {
+    fn name(&self) -> &'static str {
+        "docker"
+    }
+
+    fn cli(&self) -> &'static str {
+        "docker"
+    }
+
+    /// `DOCKER_HOST` when it names a Unix socket, the system socket otherwise.
+    fn socket(&self) -> PathBuf {
+        std::env::var("DOCKER_HOST")
+            .ok()
+            .and_then(|host| host.strip_prefix("unix://").map(PathBuf::from))
+            .unwrap_or_else(|| PathBuf::from("/var/run/docker.sock"))
+    }
+
+    fn map_user(&self, spec: &mut Value) {
+        spec["User"] = json!(host_user());
+    }
+}
+
+/// Rootless Podman, through the API service of the user's session
+/// (`systemctl --user enable --now podman.socket`).
+pub struct PodmanRuntime;
+
+impl ContainerRuntime for PodmanRuntime {
+    fn name(&self) -> &'static str {
+        "podman"
+    }
+
+    fn cli(&self) -> &'static str {
+        "podman"
+    }
+
+    fn socket(&self) -> PathBuf {
+        if let Some(socket) = std::env::var("CONTAINER_HOST")
+            .ok()
+            .and_then(|host| host.strip_prefix("unix://").map(PathBuf::from))
+        {
+            return socket;
+        }
+        let runtime_dir = std::env::var("XDG_RUNTIME_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| PathBuf::from(format!("/run/user/{}", unsafe { libc::getuid() })));
+        runtime_dir.join("podman/podman.sock")
+    }
+
+    /// Rootless Podman maps container root to the host user and every other
+    /// uid to a subordinate one, so `User` alone would leave the workspace
+    /// files to an unknown owner. `keep-id` maps the host uid onto itself.
+    fn map_user(&self, spec: &mut Value) {
+        spec["User"] = json!(host_user());
+        spec["HostConfig"]["UsernsMode"] = json!("keep-id");
+    }
+}
+
+static DOCKER: DockerRuntime = DockerRuntime;
+static PODMAN: PodmanRuntime = PodmanRuntime;
+
+fn host_user() -> String {
+    let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+    format!("{}:{}", uid, gid)
+}
+
+/// Looks a runtime up by its name in the configuration. `auto` picks the
+/// first whose socket accepts connections.
+pub fn container_runtime(name: &str) -> Result<&'static dyn ContainerRuntime> {
+    match name {
+        "auto" => Ok(detect_runtime()),
+        "docker" => Ok(&DOCKER),
+        "podman" => Ok(&PODMAN),
+        other => anyhow::bail!("Unknown container runtime '{}' (expected auto, docker or podman)", other),
+    }
+}
+
+/// Docker first, then Podman. When neither answers it stays Docker, whose
+/// socket then shows up in the connection errors.
+fn detect_runtime() -> &'static dyn ContainerRuntime {
+    static DETECTED: OnceLock<&'static dyn ContainerRuntime> = OnceLock::new();
+    *DETECTED.get_or_init(|| {
+        let runtimes: [&'static dyn ContainerRuntime; 2] = [&DOCKER, &PODMAN];
+        runtimes
+            .into_iter()
+            .find(|runtime| UnixStream::connect(runtime.socket()).is_ok())
+            .unwrap_or(&DOCKER)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_host_user_per_runtime() {
+        let mut docker = json!({"HostConfig": {}});
+        container_runtime("docker").unwrap().map_user(&mut docker);
+        assert_eq!(docker["User"], host_user());
+        assert!(docker["HostConfig"].get("UsernsMode").is_none());
+
+        let mut podman = json!({"HostConfig": {}});
+        container_runtime("podman").unwrap().map_user(&mut podman);
+        assert_eq!(podman["User"], host_user());
+        assert_eq!(podman["HostConfig"]["UsernsMode"], "keep-id");
+
+        assert!(container_runtime("lxc").is_err());
+    }
+}
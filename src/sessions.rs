@@ -0,0 +1,128 @@
+use crate::container::ContainerOutput;
+use crate::db::{Db, Session, SessionTurn};
+use anyhow::{Context, Result};
+use chrono::Utc;
+
+/// Characters of the first prompt kept as a session's title.
+const TITLE_LEN: usize = 50;
+
+/// The session a prompt continues: `current` when it belongs to the group and
+/// ran with the same backend, since a CLI cannot resume another one's
+/// conversation, and a new session otherwise.
+pub fn open_session(db: &Db, current: Option<&str>, group_folder: &str, backend: &str, prompt: &str) -> Result<Session> {
+    if let Some(id) = current {
+        let session = db
+            .get_session(id)?
+            .with_context(|| format!("Session '{}' not found", id))?;
+        if session.group_folder == group_folder && session.backend == backend {
+            return Ok(session);
+        }
+    }
+
+    let now = Utc::now().to_rfc3339();
+    let session = Session {
+        id: uuid::Uuid::new_v4().simple().to_string()[..8].to_string(),
+        group_folder: group_folder.to_string(),
+        backend: backend.to_string(),
+        agent_session_id: None,
+        title: session_title(prompt),
+        turns: 0,
+        created_at: now.clone(),
+        updated_at: now,
+    };
+    db.create_session(&session)?;
+    Ok(session)
+}
+
+/// Stores a finished prompt along with the CLI session it ran in.
+pub fn record_turn(db: &Db, session: &Session, prompt: &str, output: &ContainerOutput) -> Result<()> {
+    let turn = SessionTurn {
+        prompt: prompt.to_string(),
+        events: output.events.clone(),
+        created_at: Utc::now().to_rfc3339(),
+    };
+    db.add_session_turn(&session.id, output.new_session_id.as_deref(), &turn)?;
+    Ok(())
+}
+
+/// A session of the group by its position in `list_sessions` (from 1) or its id.
+pub fn find_session(db: &Db, group_folder: &str, key: &str) -> Result<Session> {
+    if let Ok(position) = key.parse::<usize>() {
+        let sessions = db.list_sessions(group_folder, position)?;
+        if let Some(session) = sessions.into_iter().nth(position.wrapping_sub(1)) {
+            return Ok(session);
+        }
+    }
+    db.get_session(key)?
+        .filter(|s| s.group_folder == group_folder)
+        .with_context(|| format!("No session '{}'", key))
+}
+
+/// Numbered list of the group's recent sessions, the active one marked.
+pub fn describe_sessions(sessions: &[Session], active: Option<&str>) -> String {
+    if sessions.is_empty() {
+        return "No sessions yet. Your next message starts one.".to_string();
+    }
+    let mut lines = vec!["Recent sessions (/switch <n> to continue one, /new to start another):".to_string()];
+    for (i, session) in sessions.iter().enumerate() {
+        let marker = if Some(session.id.as_str()) == active { "▶" } else { " " };
+        let updated = session.updated_at.get(..16).unwrap_or(&session.updated_at).replace('T', " ");
+        lines.push(format!(
+            "{} {}. {} [{}, {} turns, {}] ({})",
+            marker,
+            i + 1,
+            session.title,
+            session.backend,
+            session.turns,
+            updated,
+            session.id
+        ));
+    }
+    lines.join("\n")
+}
+
+fn session_title(prompt: &str) -> String {
+    let first_line = prompt.lines().next().unwrap_or("").trim();
+    if first_line.chars().count() > TITLE_LEN {
+        format!("{}…", first_line.chars().take(TITLE_LEN).collect::<String>())
+    } else {
+        first_line.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn continues_sessions_of_the_same_backend() {
+        let db = Db::new(":memory:").unwrap();
+        let first = open_session(&db, None, "main", "gemini", "Plan my week\nwith details").unwrap();
+        assert_eq!(first.title, "Plan my week");
+
+        let output = ContainerOutput {
+            status: "success".to_string(),
+            result: Some("Done".to_string()),
+            new_session_id: Some("cli-1".to_string()),
+            error: None,
+            exit_code: Some(0),
+            network: None,
+            image: None,
+            diagnostics: Vec::new(),
+            failure: None,
+            artifacts: Vec::new(),
+            events: Vec::new(),
+        };
+        record_turn(&db, &first, "Plan my week", &output).unwrap();
+
+        let next = open_session(&db, Some(&first.id), "main", "gemini", "And next week?").unwrap();
+        assert_eq!(next.id, first.id);
+        assert_eq!(next.agent_session_id.as_deref(), Some("cli-1"));
+        assert_eq!(next.turns, 1);
+
+        // Claude cannot resume a Gemini conversation
+        let other = open_session(&db, Some(&first.id), "main", "claude", "Hi").unwrap();
+        assert_ne!(other.id, first.id);
+        assert_eq!(find_session(&db, "main", "2").unwrap().id, first.id);
+    }
+}
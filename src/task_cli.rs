@@ -0,0 +1,390 @@
+use crate::config::{parse_timezone, ApprovalMode, Config};
+use crate::container::{agent_backend, check_known_group, tool_label, AgentEvent};
+use crate::db::{Db, Task, TaskRun};
+use crate::diagnostics::DiagnosticLevel;
+use crate::task_scheduler::{BackoffStrategy, CatchUpPolicy, Delivery, TaskScheduler};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use clap::Subcommand;
+use std::str::FromStr;
+use std::sync::Arc;
+
+// Parsed once per invocation, so the size of `Add` does not matter
+#[allow(clippy::large_enum_variant)]
+#[derive(Subcommand)]
+pub enum TaskCommand {
+    /// Create a scheduled task
+    Add {
+        /// Cron expression ("0 0 8 * * *"), "every X[s/m/h/d]", "at YYYY-MM-DDTHH:MM" or "in X[s/m/h/d]"
+        #[arg(short, long)]
+        schedule: String,
+        #[arg(short, long)]
+        prompt: String,
+        #[arg(short, long, default_value = "main")]
+        group: String,
+        /// What to do with runs missed while rclaw was stopped: skip, once or all
+        #[arg(long, default_value = "once")]
+        catch_up: String,
+        /// IANA timezone for the cron expression (defaults to scheduler.timezone)
+        #[arg(long)]
+        timezone: Option<String>,
+        /// Extra attempts after a failed run
+        #[arg(long, default_value_t = 0)]
+        max_retries: u32,
+        /// Wait between retries: fixed or exponential
+        #[arg(long, default_value = "exponential")]
+        backoff: String,
+        /// Where results go: tui, file[:path] (in the group workspace), channel:<jid> or none
+        #[arg(long, default_value = "tui")]
+        deliver: String,
+        /// Limit for each run, in seconds (defaults to scheduler.task_timeout_secs, 0 for none)
+        #[arg(long)]
+        timeout: Option<u64>,
+        /// Agent backend for this task: gemini or claude (defaults to the group's)
+        #[arg(long)]
+        backend: Option<String>,
+        /// How its tool calls are approved: yolo, prompt or policy (defaults to the group's)
+        #[arg(long)]
+        approval: Option<String>,
+        /// Task id (generated if omitted)
+        #[arg(long)]
+        id: Option<String>,
+    },
+    /// List all tasks
+    List,
+    /// Show the details of a task
+    Show { id: String },
+    /// Stop a task from being scheduled
+    Pause { id: String },
+    /// Schedule a paused or completed task again from now on
+    Resume { id: String },
+    /// Delete a task
+    Remove { id: String },
+    /// Run a task immediately and deliver its result, without changing its schedule
+    RunNow { id: String },
+    /// Stop the run of a task that is in progress
+    Cancel { id: String },
+    /// Show past runs of a task
+    History {
+        id: String,
+        /// Number of runs to list
+        #[arg(short, long, default_value_t = 20)]
+        limit: usize,
+        /// Print the full output of a single run
+        #[arg(short, long)]
+        run: Option<i64>,
+    },
+}
+
+pub async fn handle_task_command(db: Arc<Db>, config: &Config, command: &TaskCommand) -> Result<()> {
+    let scheduler = TaskScheduler::new(db.clone(), config.clone());
+
+    match command {
+        TaskCommand::Add {
+            schedule,
+            prompt,
+            group,
+            catch_up,
+            timezone,
+            max_retries,
+            backoff,
+            deliver,
+            timeout,
+            backend,
+            approval,
+            id,
+        } => {
+            check_known_group(config, group)?;
+            let policy = CatchUpPolicy::from_str(catch_up)?;
+            let backoff = BackoffStrategy::from_str(backoff)?;
+            let delivery = Delivery::from_str(deliver)?;
+            if let Some(name) = backend {
+                agent_backend(name)?;
+            }
+            if let Some(name) = timezone {
+                parse_timezone(name)?;
+            }
+            if let Some(mode) = approval {
+                ApprovalMode::from_str(mode).map_err(anyhow::Error::msg)?;
+            }
+            let id = id
+                .clone()
+                .unwrap_or_else(|| uuid::Uuid::new_v4().simple().to_string()[..8].to_string());
+
+            if db.get_task(&id)?.is_some() {
+                anyhow::bail!("A task with id '{}' already exists", id);
+            }
+
+            let mut task = Task {
+                id: id.clone(),
+                group_folder: group.clone(),
+                prompt: prompt.clone(),
+                schedule: schedule.clone(),
+                last_run: None,
+                next_run: None,
+                status: "active".to_string(),
+                catch_up: policy.as_str().to_string(),
+                timezone: timezone.clone(),
+                max_retries: *max_retries,
+                backoff: backoff.as_str().to_string(),
+                consecutive_failures: 0,
+                last_error: None,
+                delivery: delivery.to_string(),
+                timeout_secs: *timeout,
+                backend: backend.clone(),
+                approval: approval.clone(),
+            };
+            let upcoming = scheduler.schedule_new_task(&mut task, 3)?;
+            let tz = scheduler.task_timezone(&task)?;
+
+            println!("Task '{}' created for group '{}'.", id, group);
+            println!("Next runs:");
+            for dt in &upcoming {
+                println!("  {}", format_time(dt, tz));
+            }
+        }
+        TaskCommand::List => {
+            let tasks = db.list_tasks()?;
+            if tasks.is_empty() {
+                println!("No tasks. Create one with 'rclaw task add'.");
+                return Ok(());
+            }
+
+            println!(
+                "{:<10} {:<12} {:<8} {:<6} {:<20} {:<24} NEXT RUN",
+                "ID", "GROUP", "STATUS", "FAILS", "SCHEDULE", "LAST RUN"
+            );
+            for task in &tasks {
+                let tz = scheduler.task_timezone(task).unwrap_or(Tz::UTC);
+                println!(
+                    "{:<10} {:<12} {:<8} {:<6} {:<20} {:<24} {}",
+                    task.id,
+                    task.group_folder,
+                    task.status,
+                    task.consecutive_failures,
+                    task.schedule,
+                    format_stored_time(task.last_run.as_deref(), tz),
+                    format_stored_time(task.next_run.as_deref(), tz),
+                );
+            }
+        }
+        TaskCommand::Show { id } => {
+            let task = find_task(&db, id)?;
+            let tz = scheduler.task_timezone(&task)?;
+            println!("ID:        {}", task.id);
+            println!("Group:     {}", task.group_folder);
+            println!("Status:    {}", task.status);
+            println!("Schedule:  {}", task.schedule);
+            println!("Timezone:  {}", tz.name());
+            println!("Catch-up:  {}", task.catch_up);
+            println!("Last run:  {}", format_stored_time(task.last_run.as_deref(), tz));
+            println!("Next run:  {}", format_stored_time(task.next_run.as_deref(), tz));
+            println!("Retries:   {} ({} backoff)", task.max_retries, task.backoff);
+            println!("Delivery:  {}", task.delivery);
+            println!(
+                "Backend:   {}",
+                task.backend.as_deref().unwrap_or_else(|| config.backend_for(&task.group_folder))
+            );
+            println!(
+                "Approval:  {}",
+                task.approval
+                    .clone()
+                    .unwrap_or_else(|| config.approval_for(&task.group_folder).mode.to_string())
+            );
+            println!(
+                "Timeout:   {}",
+                scheduler
+                    .task_timeout(&task)
+                    .map(|t| format_duration(t.as_millis() as i64))
+                    .unwrap_or_else(|| "none".to_string())
+            );
+            println!("Failures:  {} in a row", task.consecutive_failures);
+            if let Some(err) = &task.last_error {
+                println!("Last error:\n{}", err);
+            }
+            println!("Prompt:\n{}", task.prompt);
+        }
+        TaskCommand::Pause { id } => {
+            find_task(&db, id)?;
+            db.set_task_status(id, "paused")?;
+            println!("Task '{}' paused.", id);
+        }
+        TaskCommand::Resume { id } => {
+            let mut task = find_task(&db, id)?;
+            scheduler.resume_task(&mut task)?;
+            let tz = scheduler.task_timezone(&task)?;
+            println!(
+                "Task '{}' resumed, next run: {}",
+                id,
+                format_stored_time(task.next_run.as_deref(), tz)
+            );
+        }
+        TaskCommand::Remove { id } => {
+            if db.remove_task(id)? {
+                println!("Task '{}' removed.", id);
+            } else {
+                anyhow::bail!("Task '{}' not found", id);
+            }
+        }
+        TaskCommand::RunNow { id } => {
+            let mut task = find_task(&db, id)?;
+            println!("Running task '{}'...", id);
+            let tz = scheduler.task_timezone(&task).unwrap_or(Tz::UTC);
+            let run = scheduler.run_now(&mut task).await?;
+            print_run(&run, tz);
+        }
+        TaskCommand::Cancel { id } => {
+            find_task(&db, id)?;
+            match db.request_run_cancel(id)? {
+                0 => anyhow::bail!("Task '{}' is not running", id),
+                _ => println!("Cancellation requested for task '{}'.", id),
+            }
+        }
+        TaskCommand::History { id, limit, run } => {
+            let task = find_task(&db, id)?;
+            let tz = scheduler.task_timezone(&task).unwrap_or(Tz::UTC);
+
+            if let Some(run_id) = run {
+                let run = db
+                    .get_task_run(*run_id)?
+                    .filter(|r| &r.task_id == id)
+                    .with_context(|| format!("Run {} not found for task '{}'", run_id, id))?;
+                print_run(&run, tz);
+                return Ok(());
+            }
+
+            let runs = db.get_task_runs(id, *limit)?;
+            if runs.is_empty() {
+                println!("Task '{}' has not run yet.", id);
+                return Ok(());
+            }
+
+            println!(
+                "{:<6} {:<24} {:<10} {:<9} {:<8} {:<5} OUTPUT",
+                "RUN", "STARTED", "DURATION", "STATUS", "ATTEMPT", "EXIT"
+            );
+            for r in &runs {
+                let output = r.error.as_deref().or(r.result.as_deref()).unwrap_or("");
+                println!(
+                    "{:<6} {:<24} {:<10} {:<9} {:<8} {:<5} {}",
+                    r.id,
+                    format_stored_time(Some(&r.started_at), tz),
+                    r.duration_ms.map(format_duration).unwrap_or_else(|| "-".to_string()),
+                    r.status,
+                    r.attempt,
+                    r.exit_code.map(|c| c.to_string()).unwrap_or_else(|| "-".to_string()),
+                    summarize(output, 60),
+                );
+            }
+            println!("\nUse --run <RUN> to see the full output of a run.");
+        }
+    }
+    Ok(())
+}
+
+fn find_task(db: &Db, id: &str) -> Result<Task> {
+    db.get_task(id)?
+        .with_context(|| format!("Task '{}' not found", id))
+}
+
+fn print_run(run: &TaskRun, tz: Tz) {
+    println!("Run:       {}", run.id);
+    println!("Task:      {}", run.task_id);
+    println!("Status:    {}", run.status);
+    println!("Attempt:   {}", run.attempt);
+    println!("Started:   {}", format_stored_time(Some(&run.started_at), tz));
+    println!("Finished:  {}", format_stored_time(run.finished_at.as_deref(), tz));
+    if let Some(ms) = run.duration_ms {
+        println!("Duration:  {}", format_duration(ms));
+    }
+    if let Some(code) = run.exit_code {
+        println!("Exit code: {}", code);
+    }
+    if let Some(network) = &run.network {
+        println!("Network:   {}", network);
+    }
+    if let Some(image) = &run.image {
+        println!("Image:     {}", image);
+    }
+    if let Some(failure) = &run.failure {
+        println!("Failure:   {}", failure);
+    }
+    if let Some(err) = &run.error {
+        println!("Error:\n{}", err);
+    }
+    // The error already holds the stderr of failed runs
+    if run.error.is_none() {
+        let diagnostics: Vec<String> = run
+            .diagnostics
+            .iter()
+            .filter(|d| d.level != DiagnosticLevel::Info)
+            .map(|d| format!("  {} {}", if d.level == DiagnosticLevel::Error { "❌" } else { "⚠️" }, d.message))
+            .collect();
+        if !diagnostics.is_empty() {
+            println!("Diagnostics:\n{}", diagnostics.join("\n"));
+        }
+    }
+    if let Some(stats) = run.events.iter().rev().find_map(|e| match e {
+        AgentEvent::Stats(stats) => Some(stats),
+        _ => None,
+    }) {
+        println!("Stats:     {}", stats.summary());
+    }
+    let activity: Vec<String> = run
+        .events
+        .iter()
+        .filter_map(|e| match e {
+            AgentEvent::ToolUse { name, parameters } => Some(format!("  🔨 {}", tool_label(name, parameters))),
+            AgentEvent::ToolResult { is_error: true, output } => Some(format!("     failed: {}", summarize(output, 60))),
+            AgentEvent::Error { message } => Some(format!("  ⚠️ {}", message)),
+            AgentEvent::Approval { tool, allowed: false, reason } => Some(format!("  🚫 {} denied: {}", tool, reason)),
+            _ => None,
+        })
+        .collect();
+    if !activity.is_empty() {
+        println!("Activity:\n{}", activity.join("\n"));
+    }
+    if !run.artifacts.is_empty() {
+        let files: Vec<String> = run.artifacts.iter().map(|a| format!("  📎 {}", a.label())).collect();
+        println!("Files:\n{}", files.join("\n"));
+    }
+    if let Some(res) = &run.result {
+        println!("Result:\n{}", res);
+    }
+}
+
+fn format_duration(ms: i64) -> String {
+    if ms < 1000 {
+        format!("{}ms", ms)
+    } else if ms < 60_000 {
+        format!("{:.1}s", ms as f64 / 1000.0)
+    } else {
+        format!("{}m{:02}s", ms / 60_000, (ms % 60_000) / 1000)
+    }
+}
+
+/// First line of `text`, cut to `max` characters.
+fn summarize(text: &str, max: usize) -> String {
+    let line = text.lines().next().unwrap_or("");
+    if line.chars().count() > max || text.lines().nth(1).is_some() {
+        let cut: String = line.chars().take(max).collect();
+        format!("{}…", cut)
+    } else {
+        line.to_string()
+    }
+}
+
+/// Formats an instant as wall-clock time in `tz`.
+fn format_time(dt: &DateTime<Utc>, tz: Tz) -> String {
+    dt.with_timezone(&tz).format("%Y-%m-%d %H:%M:%S %Z").to_string()
+}
+
+fn format_stored_time(value: Option<&str>, tz: Tz) -> String {
+    match value {
+        Some(s) => DateTime::parse_from_rfc3339(s)
+            .map(|dt| format_time(&dt.with_timezone(&Utc), tz))
+            .unwrap_or_else(|_| s.to_string()),
+        None => "-".to_string(),
+    }
+}
@@ -1,212 +1,1282 @@
-use crate::container::{run_container_agent, ContainerInput, RegisteredGroup};
-use crate::db::{Db, Task};
-use anyhow::Result;
-use chrono::{DateTime, Duration, Utc};
+use crate::artifacts::{open_artifact, ArtifactChange};
+use crate::config::{parse_timezone, timeout_from_secs, ApprovalMode, Config};
+use crate::container::{
+    group_workspace, run_container_agent, CancelToken, ContainerInput, RegisteredGroup, RunOptions,
+};
+use crate::credentials::CredentialStore;
+use crate::db::{Db, Task, TaskRun};
+use crate::diagnostics::FailureCategory;
+use crate::ipc::start_session;
+use crate::ui::{approval_prompter, WorkerEvent};
+use crate::workspace_fs::WorkspaceDir;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, LocalResult, NaiveDateTime, TimeZone, Timelike, Utc};
+use chrono_tz::Tz;
 use cron::Schedule;
+use std::collections::HashSet;
+use std::fmt;
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
 use std::str::FromStr;
-use std::sync::Arc;
-use tokio::time::{self, Duration as TokioDuration};
-use tracing::{error, info};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Semaphore;
+use tokio::time::{self, Duration as TokioDuration, MissedTickBehavior};
+use tracing::{error, info, warn};
+
+/// Slots older than this when the scheduler sees them are considered missed
+/// (rclaw was stopped, the machine was asleep...) rather than just late by a tick.
+const MISSED_RUN_GRACE_SECS: i64 = 90;
+
+/// Upper bound on the missed slots replayed for a single task in one tick.
+const MAX_CATCH_UP_RUNS: usize = 100;
 
 pub enum TaskSchedule {
-    Cron(Schedule),
+    Cron(Box<Schedule>),
     Every(chrono::Duration),
+    /// Runs once at a wall-clock time in the task's timezone.
+    At(NaiveDateTime),
+    /// Runs once, this long after the task is scheduled.
+    In(chrono::Duration),
+}
+
+/// Accepted formats:
+/// - a cron expression with seconds: `0 0 8 * * *`
+/// - `every X[s/m/h/d]`: `every 30m`
+/// - `at YYYY-MM-DDTHH:MM[:SS]`: `at 2026-10-20T09:00`
+/// - `in X[s/m/h/d]`: `in 2h`
+impl FromStr for TaskSchedule {
+    type Err = anyhow::Error;
+
+    fn from_str(schedule: &str) -> Result<Self> {
+        let schedule = schedule.trim();
+
+        if let Some(rest) = schedule.strip_prefix("every ") {
+            Ok(TaskSchedule::Every(parse_duration(rest)?))
+        } else if let Some(rest) = schedule.strip_prefix("in ") {
+            Ok(TaskSchedule::In(parse_duration(rest)?))
+        } else if let Some(rest) = schedule.strip_prefix("at ") {
+            let rest = rest.trim();
+            let at = ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%dT%H:%M", "%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"]
+                .iter()
+                .find_map(|format| NaiveDateTime::parse_from_str(rest, format).ok())
+                .ok_or_else(|| {
+                    anyhow::anyhow!("Invalid 'at' time '{}' (expected YYYY-MM-DDTHH:MM)", rest)
+                })?;
+            Ok(TaskSchedule::At(at))
+        } else {
+            let schedule = Schedule::from_str(schedule)
+                .map_err(|e| anyhow::anyhow!("Invalid cron schedule: {}", e))?;
+            Ok(TaskSchedule::Cron(Box::new(schedule)))
+        }
+    }
+}
+
+/// Parses the "X[s/m/h/d]" amount used by `every` and `in`.
+fn parse_duration(value: &str) -> Result<Duration> {
+    let value = value.trim();
+    if value.contains(char::is_whitespace) {
+        anyhow::bail!("Invalid duration '{}' (expected e.g. 30m)", value);
+    }
+
+    let amount_str = value.trim_end_matches(|c: char| !c.is_ascii_digit());
+    let unit_str = value.trim_start_matches(|c: char| c.is_ascii_digit());
+
+    let amount = amount_str
+        .parse::<i64>()
+        .map_err(|_| anyhow::anyhow!("Invalid duration amount: '{}'", amount_str))?;
+    if amount <= 0 {
+        anyhow::bail!("Duration must be greater than zero: '{}'", value);
+    }
+
+    let duration = match unit_str {
+        "s" => Duration::try_seconds(amount),
+        "m" => Duration::try_minutes(amount),
+        "h" => Duration::try_hours(amount),
+        "d" => Duration::try_days(amount),
+        _ => anyhow::bail!("Invalid duration unit '{}' (expected s, m, h or d)", unit_str),
+    };
+
+    duration.ok_or_else(|| anyhow::anyhow!("Duration too large: '{}'", value))
+}
+
+impl TaskSchedule {
+    /// One-shot schedules run a single time and then the task is completed.
+    pub fn is_one_shot(&self) -> bool {
+        matches!(self, TaskSchedule::At(_) | TaskSchedule::In(_))
+    }
+
+    /// First occurrence strictly after `after`. Cron fields are matched
+    /// against the wall-clock time in `tz`.
+    pub fn next_after(&self, after: DateTime<Utc>, tz: Tz) -> Option<DateTime<Utc>> {
+        match self {
+            TaskSchedule::Cron(schedule) => next_cron_after(schedule, after, tz),
+            TaskSchedule::Every(duration) => Some(after + *duration),
+            TaskSchedule::At(local) => resolve_local(tz, *local).filter(|at| *at > after),
+            // Relative to when the task is first scheduled; afterwards it has
+            // no further occurrences (see `is_one_shot`).
+            TaskSchedule::In(duration) => Some(after + *duration),
+        }
+    }
+
+    /// The next `count` occurrences after `after`.
+    pub fn upcoming(&self, after: DateTime<Utc>, count: usize, tz: Tz) -> Vec<DateTime<Utc>> {
+        let mut occurrences = Vec::with_capacity(count);
+        let mut cursor = after;
+        while occurrences.len() < count {
+            match self.next_after(cursor, tz) {
+                Some(next) => {
+                    occurrences.push(next);
+                    cursor = next;
+                }
+                None => break,
+            }
+            if self.is_one_shot() {
+                break;
+            }
+        }
+        occurrences
+    }
+}
+
+/// The cron crate drops local times that don't map to exactly one instant, so
+/// the expression is evaluated over naive wall-clock times (carried as `Utc`)
+/// and each match is resolved in `tz` afterwards: a time inside a DST gap fires
+/// when the gap ends, and a time repeated by a DST overlap fires only once, on
+/// its first occurrence.
+fn next_cron_after(schedule: &Schedule, after: DateTime<Utc>, tz: Tz) -> Option<DateTime<Utc>> {
+    let mut cursor = Utc.from_utc_datetime(&after.with_timezone(&tz).naive_local());
+    loop {
+        let wall = schedule.after(&cursor).next()?;
+        let instant = resolve_local(tz, wall.naive_utc())?;
+        // Inside an overlap the wall clock can be behind `after`'s first pass.
+        if instant > after {
+            return Some(instant);
+        }
+        cursor = wall;
+    }
+}
+
+fn resolve_local(tz: Tz, local: NaiveDateTime) -> Option<DateTime<Utc>> {
+    match tz.from_local_datetime(&local) {
+        LocalResult::Single(dt) => Some(dt.with_timezone(&Utc)),
+        LocalResult::Ambiguous(earliest, _) => Some(earliest.with_timezone(&Utc)),
+        LocalResult::None => {
+            // DST gap: the first minute that exists again is when the clocks jumped.
+            let mut probe = local.with_second(0)?;
+            for _ in 0..(24 * 60) {
+                probe += Duration::minutes(1);
+                if let Some(dt) = tz.from_local_datetime(&probe).earliest() {
+                    return Some(dt.with_timezone(&Utc));
+                }
+            }
+            None
+        }
+    }
+}
+
+/// Where the result of a successful scheduled run is sent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Delivery {
+    /// A message in the TUI chat.
+    Tui,
+    /// Appended to a file in the group workspace, `tasks/<id>.md` if no path is given.
+    File(Option<String>),
+    /// A `message_queue` entry for a channel jid.
+    Channel(String),
+    /// Only kept in the run history.
+    None,
+}
+
+impl FromStr for Delivery {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split_once(':') {
+            None if s == "tui" => Ok(Delivery::Tui),
+            None if s == "none" => Ok(Delivery::None),
+            None if s == "file" => Ok(Delivery::File(None)),
+            Some(("file", path)) => {
+                let relative = Path::new(path);
+                if path.is_empty()
+                    || !relative
+                        .components()
+                        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+                {
+                    anyhow::bail!("Delivery file '{}' must be a path inside the group workspace", path);
+                }
+                Ok(Delivery::File(Some(path.to_string())))
+            }
+            Some(("channel", jid)) if !jid.is_empty() => Ok(Delivery::Channel(jid.to_string())),
+            _ => anyhow::bail!(
+                "Unknown delivery '{}' (expected tui, file, file:<path>, channel:<jid> or none)",
+                s
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Delivery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Delivery::Tui => write!(f, "tui"),
+            Delivery::File(None) => write!(f, "file"),
+            Delivery::File(Some(path)) => write!(f, "file:{}", path),
+            Delivery::Channel(jid) => write!(f, "channel:{}", jid),
+            Delivery::None => write!(f, "none"),
+        }
+    }
+}
+
+/// What to do with occurrences that were missed while rclaw was not running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CatchUpPolicy {
+    /// Drop missed occurrences and wait for the next one.
+    Skip,
+    /// Run once for all the missed occurrences together.
+    Once,
+    /// Run once per missed occurrence.
+    All,
+}
+
+impl FromStr for CatchUpPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "skip" => Ok(CatchUpPolicy::Skip),
+            "once" => Ok(CatchUpPolicy::Once),
+            "all" => Ok(CatchUpPolicy::All),
+            other => anyhow::bail!("Unknown catch-up policy '{}' (expected skip, once or all)", other),
+        }
+    }
+}
+
+impl CatchUpPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CatchUpPolicy::Skip => "skip",
+            CatchUpPolicy::Once => "once",
+            CatchUpPolicy::All => "all",
+        }
+    }
+}
+
+/// How the wait between retries of a failed run grows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackoffStrategy {
+    /// Always wait the base delay.
+    Fixed,
+    /// Double the wait after every failed attempt.
+    Exponential,
+}
+
+impl FromStr for BackoffStrategy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "fixed" => Ok(BackoffStrategy::Fixed),
+            "exponential" => Ok(BackoffStrategy::Exponential),
+            other => anyhow::bail!("Unknown backoff strategy '{}' (expected fixed or exponential)", other),
+        }
+    }
+}
+
+impl BackoffStrategy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BackoffStrategy::Fixed => "fixed",
+            BackoffStrategy::Exponential => "exponential",
+        }
+    }
+
+    /// Wait before the retry that follows failed attempt number `attempt` (1-based).
+    pub fn delay(&self, base: TokioDuration, attempt: u32) -> TokioDuration {
+        match self {
+            BackoffStrategy::Fixed => base,
+            BackoffStrategy::Exponential => base.saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1))),
+        }
+    }
+}
+
+/// Source of the current time for the scheduler, so tests can drive it by hand.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A task whose stored `next_run` has been reached, with the slots to execute.
+pub struct DueTask {
+    pub task: Task,
+    pub runs: Vec<DateTime<Utc>>,
+    /// The task is marked completed once these runs are done.
+    pub one_shot: bool,
 }
 
 pub struct TaskScheduler {
     db: Arc<Db>,
+    config: Config,
+    clock: Arc<dyn Clock>,
+    /// Bounds how many agent runs the scheduler has in flight at once.
+    permits: Arc<Semaphore>,
+    /// Ids of tasks that are executing or waiting for a permit.
+    running: Arc<Mutex<HashSet<String>>>,
+    /// Where failures are reported besides the logs (the TUI).
+    notifier: Option<Sender<WorkerEvent>>,
+}
+
+/// Removes a task from the running set when its execution ends, even if the
+/// spawned job panics.
+struct RunningGuard {
+    running: Arc<Mutex<HashSet<String>>>,
+    task_id: String,
+}
+
+impl Drop for RunningGuard {
+    fn drop(&mut self) {
+        self.running.lock().unwrap().remove(&self.task_id);
+    }
 }
 
 impl TaskScheduler {
-    pub fn new(db: Arc<Db>) -> Self {
-        TaskScheduler { db }
+    pub fn new(db: Arc<Db>, config: Config) -> Self {
+        Self::with_clock(db, config, Arc::new(SystemClock))
+    }
+
+    pub fn with_clock(db: Arc<Db>, config: Config, clock: Arc<dyn Clock>) -> Self {
+        let permits = Arc::new(Semaphore::new(config.scheduler.max_concurrent_tasks.max(1)));
+        TaskScheduler {
+            db,
+            config,
+            clock,
+            permits,
+            running: Arc::new(Mutex::new(HashSet::new())),
+            notifier: None,
+        }
+    }
+
+    pub fn with_notifier(mut self, notifier: Sender<WorkerEvent>) -> Self {
+        self.notifier = Some(notifier);
+        self
+    }
+
+    /// The timezone a task's cron expression is evaluated in.
+    pub fn task_timezone(&self, task: &Task) -> Result<Tz> {
+        match &task.timezone {
+            Some(name) => parse_timezone(name),
+            None => Ok(self.config.scheduler.timezone()),
+        }
+    }
+
+    /// How long a run of the task may take, if limited.
+    pub fn task_timeout(&self, task: &Task) -> Option<std::time::Duration> {
+        timeout_from_secs(task.timeout_secs.unwrap_or(self.config.scheduler.task_timeout_secs))
+    }
+
+    /// Validates a new task's schedule and timezone, sets its first `next_run`
+    /// and stores it. Returns the next `preview` occurrences.
+    pub fn schedule_new_task(&self, task: &mut Task, preview: usize) -> Result<Vec<DateTime<Utc>>> {
+        let parsed = TaskSchedule::from_str(&task.schedule)?;
+        let tz = self.task_timezone(task)?;
+        let upcoming = parsed.upcoming(self.clock.now(), preview.max(1), tz);
+        if upcoming.is_empty() {
+            anyhow::bail!("Schedule '{}' has no upcoming runs", task.schedule);
+        }
+
+        task.next_run = upcoming.first().map(|dt| dt.to_rfc3339());
+        self.db.add_task(task)?;
+        Ok(upcoming)
+    }
+
+    /// Schedules a paused or completed task again. Occurrences that fell inside
+    /// the pause are not "missed", so the schedule restarts from now instead of
+    /// going through catch-up.
+    pub fn resume_task(&self, task: &mut Task) -> Result<()> {
+        let parsed = TaskSchedule::from_str(&task.schedule)?;
+        let tz = self.task_timezone(task)?;
+        task.next_run = parsed.next_after(self.clock.now(), tz).map(|dt| dt.to_rfc3339());
+        if task.next_run.is_none() {
+            anyhow::bail!("Task '{}' has no upcoming runs left", task.id);
+        }
+        task.status = "active".to_string();
+        task.consecutive_failures = 0;
+        // Targeted updates, so a scheduler working on the task in another
+        // process does not lose them (nor have its own writes undone)
+        self.db.set_task_next_run(&task.id, task.next_run.as_deref())?;
+        self.db.set_task_failures(&task.id, 0, task.last_error.as_deref())?;
+        self.db.set_task_status(&task.id, "active")?;
+        Ok(())
     }
 
-    pub async fn run(&self) {
-        info!("Task scheduler started.");
+    /// Runs a task now, outside its schedule, and delivers the result. Fails
+    /// if the task is already running, here or in another rclaw process.
+    pub async fn run_now(&self, task: &mut Task) -> Result<TaskRun> {
+        let _guard = self.claim(&task.id)?;
+        let run = {
+            let Ok(_permit) = self.permits.acquire().await else {
+                anyhow::bail!("Scheduler is shutting down");
+            };
+            self.run_task(task, 1).await?
+        };
+        if run.status == "success" {
+            if let Err(e) = self.deliver(task, &run) {
+                error!("Task {} result could not be delivered: {:#}", task.id, e);
+            }
+        }
+        Ok(run)
+    }
+
+    /// Adds the task to the running set, or fails if it is already running.
+    fn claim(&self, task_id: &str) -> Result<RunningGuard> {
+        let mut running = self.running.lock().unwrap();
+        if running.contains(task_id) || self.db.has_running_run(task_id)? {
+            anyhow::bail!("Task '{}' is already running", task_id);
+        }
+        running.insert(task_id.to_string());
+        Ok(RunningGuard {
+            running: self.running.clone(),
+            task_id: task_id.to_string(),
+        })
+    }
+
+    /// Ticks every minute until the process exits. Due tasks are handed to
+    /// background jobs, so a tick never waits for an agent to finish.
+    pub async fn run(self: Arc<Self>) {
+        info!(
+            "Task scheduler started (max {} concurrent runs).",
+            self.config.scheduler.max_concurrent_tasks
+        );
+        match self.db.fail_interrupted_runs(&self.clock.now().to_rfc3339()) {
+            Ok(0) => {}
+            Ok(n) => warn!("{} task runs were interrupted by the last shutdown.", n),
+            Err(e) => error!("Error closing interrupted task runs: {:?}", e),
+        }
         let mut interval = time::interval(TokioDuration::from_secs(60)); // Check every minute
+        interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
 
         loop {
             interval.tick().await;
             info!("Scheduler tick: Checking for tasks to run...");
-            if let Err(e) = self.check_and_run_tasks().await {
+            if let Err(e) = self.dispatch_due_tasks() {
                 error!("Error in scheduler tick: {:?}", e);
             }
+            if let Err(e) = self.prune_history() {
+                error!("Error pruning task history: {:?}", e);
+            }
         }
     }
 
-    async fn check_and_run_tasks(&self) -> Result<()> {
-        let active_tasks = self.db.get_active_tasks()?;
+    fn dispatch_due_tasks(self: &Arc<Self>) -> Result<()> {
+        for due in self.collect_due_tasks()? {
+            let guard = RunningGuard {
+                running: self.running.clone(),
+                task_id: due.task.id.clone(),
+            };
+            self.running.lock().unwrap().insert(due.task.id.clone());
 
-        for mut task in active_tasks {
-            let parsed_schedule = if task.schedule.starts_with("every ") {
-                let parts: Vec<&str> = task.schedule.split_whitespace().collect();
-                if parts.len() == 2 {
-                    let amount_str = parts[1].trim_end_matches(|c: char| !c.is_ascii_digit());
-                    let unit_str = parts[1].trim_start_matches(|c: char| c.is_ascii_digit());
-
-                    if let Ok(amount) = amount_str.parse::<i64>() {
-                        let duration = match unit_str {
-                            "s" => {
-                                Some(Duration::try_seconds(amount).unwrap_or_else(Duration::zero))
-                            }
-                            "m" => {
-                                Some(Duration::try_minutes(amount).unwrap_or_else(Duration::zero))
-                            }
-                            "h" => Some(Duration::try_hours(amount).unwrap_or_else(Duration::zero)),
-                            "d" => Some(Duration::try_days(amount).unwrap_or_else(Duration::zero)),
-                            _ => None,
-                        };
-
-                        if let Some(d) = duration {
-                            TaskSchedule::Every(d)
-                        } else {
-                            error!("Invalid 'every X' unit for task {}: {}", task.id, unit_str);
-                            continue;
-                        }
-                    } else {
-                        error!(
-                            "Invalid 'every X' amount for task {}: {}",
-                            task.id, amount_str
-                        );
-                        continue;
+            let scheduler = self.clone();
+            tokio::spawn(async move {
+                let _guard = guard;
+                let mut task = due.task;
+
+                for slot in &due.runs {
+                    info!("Running task: {} (scheduled for {})", task.id, slot.to_rfc3339());
+                    if let Err(e) = scheduler.run_occurrence(&mut task).await {
+                        error!("Task {} could not be recorded: {:?}", task.id, e);
+                    }
+                    if task.status != "active" {
+                        break;
                     }
-                } else {
-                    error!(
-                        "Invalid 'every X' format for task {}: {}",
-                        task.id, task.schedule
-                    );
-                    continue;
                 }
-            } else {
-                match Schedule::from_str(&task.schedule) {
-                    Ok(s) => TaskSchedule::Cron(s),
-                    Err(e) => {
-                        error!("Invalid cron schedule for task {}: {}", task.id, e);
-                        continue;
+
+                // A task paused by its failures stays paused, to be looked at
+                if due.one_shot && task.status == "active" {
+                    task.status = "completed".to_string();
+                    if let Err(e) = scheduler.db.set_task_status(&task.id, "completed") {
+                        error!("Task {} could not be marked completed: {:?}", task.id, e);
                     }
                 }
+
+                info!("Task {} completed, next run: {:?}", task.id, task.next_run);
+            });
+        }
+        Ok(())
+    }
+
+    /// Runs one scheduled occurrence of a task, retrying with backoff while it
+    /// fails and it has retries left. A final failure counts towards the
+    /// consecutive failures that pause the task.
+    async fn run_occurrence(&self, task: &mut Task) -> Result<()> {
+        let backoff = BackoffStrategy::from_str(&task.backoff).unwrap_or_else(|e| {
+            warn!("Task {}: {}. Falling back to 'exponential'.", task.id, e);
+            BackoffStrategy::Exponential
+        });
+        let base_delay = TokioDuration::from_secs(self.config.scheduler.retry_base_delay_secs);
+
+        let mut attempt = 1;
+        loop {
+            let run = {
+                let Ok(_permit) = self.permits.acquire().await else {
+                    return Ok(());
+                };
+                self.run_task(task, attempt).await?
             };
 
-            let now_utc = Utc::now();
-            let next_occurrence = match &parsed_schedule {
-                TaskSchedule::Cron(schedule) => schedule.after(&now_utc).next(),
-                TaskSchedule::Every(duration) => {
-                    let last_run_dt = task.last_run.as_ref().and_then(|s| {
-                        DateTime::parse_from_rfc3339(s)
-                            .ok()
-                            .map(|dt| dt.with_timezone(&Utc))
+            if run.status == "success" {
+                if task.consecutive_failures > 0 {
+                    task.consecutive_failures = 0;
+                    task.last_error = None;
+                    self.db.set_task_failures(&task.id, 0, None)?;
+                }
+                if let Err(e) = self.deliver(task, &run) {
+                    error!("Task {} result could not be delivered: {:#}", task.id, e);
+                }
+                return Ok(());
+            }
+
+            if run.status == "cancelled" {
+                info!("Task {} run {} was cancelled, not retrying", task.id, run.id);
+                return Ok(());
+            }
+
+            // Retrying cannot fix the configuration
+            if attempt > task.max_retries || run.failure == Some(FailureCategory::Config.to_string()) {
+                return self.record_failure(task, &run);
+            }
+
+            let delay = backoff.delay(base_delay, attempt);
+            warn!(
+                "Task {} failed (attempt {}/{}), retrying in {:?}",
+                task.id,
+                attempt,
+                task.max_retries + 1,
+                delay
+            );
+            time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    fn record_failure(&self, task: &mut Task, run: &TaskRun) -> Result<()> {
+        let error = run
+            .error
+            .clone()
+            .unwrap_or_else(|| format!("Run finished with status '{}'", run.status));
+
+        task.consecutive_failures += 1;
+        task.last_error = Some(error.clone());
+        self.db
+            .set_task_failures(&task.id, task.consecutive_failures, task.last_error.as_deref())?;
+
+        let threshold = self.config.scheduler.pause_after_failures;
+        let paused = threshold > 0 && task.consecutive_failures >= threshold;
+        if paused {
+            task.status = "paused".to_string();
+            self.db.set_task_status(&task.id, "paused")?;
+            error!(
+                "Task {} paused after {} consecutive failures. Last error: {}",
+                task.id, task.consecutive_failures, error
+            );
+        }
+
+        if let Some(notifier) = &self.notifier {
+            let _ = notifier.send(WorkerEvent::TaskFailed {
+                task_id: task.id.clone(),
+                error,
+                consecutive_failures: task.consecutive_failures,
+                paused,
+            });
+        }
+        Ok(())
+    }
+
+    /// Sends the result of a successful run to the task's delivery target.
+    fn deliver(&self, task: &Task, run: &TaskRun) -> Result<()> {
+        let result = run.result.as_deref().unwrap_or("");
+        match Delivery::from_str(&task.delivery)? {
+            Delivery::Tui => {
+                if let Some(tx) = &self.notifier {
+                    let _ = tx.send(WorkerEvent::TaskResult {
+                        task_id: task.id.clone(),
+                        events: run.events.clone(),
+                        artifacts: run.artifacts.clone(),
                     });
+                }
+            }
+            Delivery::File(path) => {
+                let relative = PathBuf::from(path.unwrap_or_else(|| format!("tasks/{}.md", task.id)));
+                let workspace = group_workspace(&task.group_folder)?;
+                let Some(name) = relative.file_name() else {
+                    anyhow::bail!("Delivery file '{}' has no file name", relative.display());
+                };
+                // The agent can swap any directory or file of the workspace
+                // for a symlink to a host file; none of them is followed
+                let dir = WorkspaceDir::open(&workspace)
+                    .and_then(|root| root.create_dir_all(relative.parent().unwrap_or(Path::new(""))))
+                    .with_context(|| format!("Failed to open the directory of {}", relative.display()))?;
+                let mut file = dir
+                    .append(name)
+                    .with_context(|| format!("Failed to open {} for appending", relative.display()))?;
+                let path = workspace.join(&relative);
+                let tz = self.task_timezone(task)?;
+                writeln!(
+                    file,
+                    "## {} (task {})\n\n{}\n",
+                    run.started_at
+                        .parse::<DateTime<Utc>>()
+                        .map(|dt| dt.with_timezone(&tz).format("%Y-%m-%d %H:%M %Z").to_string())
+                        .unwrap_or_else(|_| run.started_at.clone()),
+                    task.id,
+                    result
+                )?;
+                info!("Task {} result appended to {}", task.id, path.display());
+            }
+            Delivery::Channel(jid) => {
+                // The files the run produced go along for the adapter to attach, as
+                // long as they are still regular files
+                let attachments: Vec<PathBuf> = run
+                    .artifacts
+                    .iter()
+                    .filter(|a| a.change != ArtifactChange::Deleted)
+                    .filter(|a| open_artifact(&a.path).is_ok())
+                    .map(|a| a.path.clone())
+                    .collect();
+                self.db.queue_message(&jid, result, &attachments)?;
+                info!("Task {} result queued for {}", task.id, jid);
+            }
+            Delivery::None => {}
+        }
+        Ok(())
+    }
+
+    /// Executes a task once in its group's container, records the run in
+    /// `task_runs` and updates `last_run`. Does not touch `next_run` nor the
+    /// failure counter, so it is also used for manual runs.
+    pub async fn run_task(&self, task: &mut Task, attempt: u32) -> Result<TaskRun> {
+        let started_at = self.clock.now();
+        let mut run = TaskRun {
+            id: 0,
+            task_id: task.id.clone(),
+            started_at: started_at.to_rfc3339(),
+            finished_at: None,
+            duration_ms: None,
+            status: "running".to_string(),
+            result: None,
+            error: None,
+            exit_code: None,
+            attempt,
+            events: Vec::new(),
+            network: None,
+            image: None,
+            diagnostics: Vec::new(),
+            failure: None,
+            artifacts: Vec::new(),
+        };
+        run.id = self.db.insert_task_run(&run)?;
+        // Recorded as a failed run, so it counts towards pausing the task
+        let group_config = match self.task_group(task) {
+            Ok(group) => group,
+            Err(e) => {
+                error!("Task {} cannot run: {:#}", task.id, e);
+                run.status = "error".to_string();
+                run.error = Some(format!("{:#}", e));
+                run.failure = Some(FailureCategory::Config.to_string());
+                return self.finish_run(task, run, started_at);
+            }
+        };
+
+        let mut input = ContainerInput {
+            prompt: task.prompt.clone(),
+            // Every run starts a fresh conversation
+            session_id: None,
+            group_folder: task.group_folder.clone(),
+            chat_jid: format!("scheduled-task-{}", task.id),
+            is_main: false,
+            is_scheduled_task: Some(true),
+            ipc_dir: None,
+        };
+        let ipc = start_session(self.db.clone(), self.config.clone(), &mut input);
+        let timeout = self.task_timeout(task);
 
-                    if let Some(last_run) = last_run_dt {
-                        let mut calculated_next = last_run + *duration;
-                        while calculated_next <= now_utc {
-                            calculated_next = calculated_next + *duration;
-                        }
-                        Some(calculated_next)
-                    } else {
-                        // If no last_run, schedule for now + duration
-                        Some(now_utc + *duration)
+        // `rclaw task cancel` flags the run in the database, possibly from another process
+        let cancel = CancelToken::new();
+        let watcher = {
+            let db = self.db.clone();
+            let cancel = cancel.clone();
+            let run_id = run.id;
+            tokio::spawn(async move {
+                let mut interval = time::interval(TokioDuration::from_secs(1));
+                loop {
+                    interval.tick().await;
+                    if db.is_run_cancel_requested(run_id).unwrap_or(false) {
+                        cancel.cancel();
+                        break;
                     }
                 }
+            })
+        };
+
+        // Tool calls in `prompt` mode are asked in the TUI, and denied without it
+        let approvals = self.notifier.clone().map(approval_prompter);
+        let credentials = CredentialStore::new(self.db.clone(), &self.config.agent);
+        let outcome = tokio::task::spawn_blocking(move || {
+            let options = RunOptions {
+                timeout,
+                cancel,
+                events: None,
+                approvals,
+                credentials: Some(credentials),
             };
+            let output = run_container_agent(&group_config, &input, &options);
+            drop(ipc);
+            output
+        })
+        .await;
+        watcher.abort();
 
-            if let Some(next_occurrence) = next_occurrence {
-                // If next_run in DB is empty or older than calculated next_occurrence, update it
-                let db_next_run = task.next_run.as_ref().and_then(|s| {
-                    DateTime::parse_from_rfc3339(s)
-                        .ok()
-                        .map(|dt| dt.with_timezone(&Utc))
-                });
+        match outcome {
+            Ok(Ok(output)) => {
+                info!("Task {} agent finished with status {}", task.id, output.status);
+                if let Some(err) = &output.error {
+                    error!("Task {} error: {}", task.id, err);
+                }
+                run.status = output.status;
+                run.result = output.result;
+                run.error = output.error;
+                run.exit_code = output.exit_code;
+                run.events = output.events;
+                run.network = output.network;
+                run.image = output.image;
+                run.diagnostics = output.diagnostics;
+                run.failure = output.failure.map(|f| f.to_string());
+                run.artifacts = output.artifacts;
+            }
+            Ok(Err(e)) => {
+                error!("Task {} agent failed: {:?}", task.id, e);
+                run.status = "error".to_string();
+                run.error = Some(format!("{:#}", e));
+            }
+            Err(e) => {
+                error!("Task {} join error: {:?}", task.id, e);
+                run.status = "error".to_string();
+                run.error = Some(e.to_string());
+            }
+        }
+        self.finish_run(task, run, started_at)
+    }
 
-                if db_next_run.is_none()
-                    || (db_next_run.is_some() && db_next_run.unwrap() < next_occurrence)
-                {
-                    task.next_run = Some(next_occurrence.to_rfc3339());
-                    // Persistir el cambio en next_run. Solo el campo next_run es mutable aquí, por lo que crearemos un nuevo Task
-                    let task_to_update = Task {
-                        id: task.id.clone(),
-                        group_folder: task.group_folder.clone(),
-                        prompt: task.prompt.clone(),
-                        schedule: task.schedule.clone(),
-                        last_run: task.last_run.clone(),
-                        next_run: task.next_run.clone(),
-                        status: task.status.clone(),
-                    };
-                    self.db.add_task(&task_to_update)?; // Usar add_task para actualizar
-                    info!("Updated next_run for task {}: {:?}", task.id, task.next_run);
+    /// The group the task runs in, with the task's own settings applied.
+    fn task_group(&self, task: &Task) -> Result<RegisteredGroup> {
+        let mut group = RegisteredGroup::from_config(&self.config, &task.group_folder, task.backend.as_deref())?;
+        // Never falls back to the group's mode, which may be less strict
+        if let Some(mode) = task.approval.as_deref() {
+            group.approval.mode = ApprovalMode::from_str(mode)
+                .map_err(anyhow::Error::msg)
+                .with_context(|| format!("Task {} has an invalid approval mode", task.id))?;
+        }
+        Ok(group)
+    }
+
+    /// Stores the outcome of a run and updates the task's `last_run`.
+    fn finish_run(&self, task: &mut Task, mut run: TaskRun, started_at: DateTime<Utc>) -> Result<TaskRun> {
+        let finished_at = self.clock.now();
+        run.finished_at = Some(finished_at.to_rfc3339());
+        run.duration_ms = Some((finished_at - started_at).num_milliseconds());
+        self.db.update_task_run(&run)?;
+
+        task.last_run = Some(finished_at.to_rfc3339());
+        self.db.set_task_last_run(&task.id, &finished_at.to_rfc3339())?;
+        Ok(run)
+    }
+
+    /// Drops task runs older than the retention period.
+    fn prune_history(&self) -> Result<()> {
+        let days = self.config.scheduler.history_retention_days;
+        let cutoff = self
+            .clock
+            .now()
+            .checked_sub_signed(self.config.scheduler.history_retention()?)
+            .ok_or_else(|| anyhow::anyhow!("history_retention_days {} reaches before the earliest date", days))?;
+        let pruned = self.db.prune_task_runs(&cutoff.to_rfc3339())?;
+        if pruned > 0 {
+            info!("Pruned {} task runs older than {} days.", pruned, days);
+        }
+        Ok(())
+    }
+
+    /// Loads the active tasks, works out which ones are due according to their
+    /// stored `next_run` and catch-up policy, and advances `next_run` past now.
+    ///
+    /// `next_run` is persisted before anything is executed, so a slot is never
+    /// handed out twice. Tasks that are still running are left untouched; once
+    /// they finish, the slots they overran are handled by the catch-up policy.
+    pub fn collect_due_tasks(&self) -> Result<Vec<DueTask>> {
+        let now = self.clock.now();
+        let active_tasks = self.db.get_active_tasks()?;
+        let running = self.running.lock().unwrap().clone();
+        let mut due_tasks = Vec::new();
+
+        for mut task in active_tasks {
+            // Also skips runs in progress elsewhere, such as `rclaw task run-now`
+            if running.contains(&task.id) || self.db.has_running_run(&task.id)? {
+                continue;
+            }
+
+            let parsed_schedule = match TaskSchedule::from_str(&task.schedule) {
+                Ok(s) => s,
+                Err(e) => {
+                    error!("Invalid schedule for task {}: {}", task.id, e);
+                    continue;
                 }
+            };
 
-                // Check if it's time to run the task
-                if next_occurrence <= now_utc {
-                    info!("Running task: {}", task.id);
-
-                    let group_config = RegisteredGroup {
-                        name: task.group_folder.clone(),
-                        folder: task.group_folder.clone(),
-                    };
-
-                    let input = ContainerInput {
-                        prompt: task.prompt.clone(),
-                        session_id: "scheduled-task".to_string(),
-                        group_folder: task.group_folder.clone(),
-                        chat_jid: format!("scheduled-task-{}", task.id),
-                        is_main: false,
-                        is_scheduled_task: Some(true),
-                    };
-
-                    match tokio::task::spawn_blocking(move || {
-                        run_container_agent(&group_config, &input)
-                    })
-                    .await
-                    {
-                        Ok(Ok(output)) => {
-                            info!("Task {} agent finished: {:?}", task.id, output);
-                            if let Some(res) = output.result {
-                                info!("Task {} result: {}", task.id, res);
-                            }
-                            if let Some(err) = output.error {
-                                error!("Task {} error: {}", task.id, err);
-                            }
-                        }
-                        Ok(Err(e)) => {
-                            error!("Task {} agent failed: {:?}", task.id, e);
-                        }
-                        Err(e) => {
-                            error!("Task {} join error: {:?}", task.id, e);
-                        }
-                    }
+            let tz = match self.task_timezone(&task) {
+                Ok(tz) => tz,
+                Err(e) => {
+                    error!("Invalid timezone for task {}: {}", task.id, e);
+                    continue;
+                }
+            };
 
-                    // After running, update last_run and recalculate next_run
-                    task.last_run = Some(now_utc.to_rfc3339());
-                    let new_next_run_calc = match &parsed_schedule {
-                        TaskSchedule::Cron(schedule) => {
-                            schedule.after(&now_utc).next().map(|dt| dt.to_rfc3339())
-                        }
-                        TaskSchedule::Every(duration) => Some((now_utc + *duration).to_rfc3339()),
-                    };
-                    task.next_run = new_next_run_calc;
-
-                    // Persistir el cambio en last_run y next_run
-                    let task_to_update = Task {
-                        id: task.id.clone(),
-                        group_folder: task.group_folder.clone(),
-                        prompt: task.prompt.clone(),
-                        schedule: task.schedule.clone(),
-                        last_run: task.last_run.clone(),
-                        next_run: task.next_run.clone(),
-                        status: task.status.clone(),
-                    };
-                    self.db.add_task(&task_to_update)?;
-                    info!("Task {} completed, next run: {:?}", task.id, task.next_run);
+            let policy = match CatchUpPolicy::from_str(&task.catch_up) {
+                Ok(p) => p,
+                Err(e) => {
+                    warn!("Task {}: {}. Falling back to 'once'.", task.id, e);
+                    CatchUpPolicy::Once
                 }
-            } else {
+            };
+
+            let db_next_run = task.next_run.as_ref().and_then(|s| {
+                DateTime::parse_from_rfc3339(s)
+                    .ok()
+                    .map(|dt| dt.with_timezone(&Utc))
+            });
+
+            // A task that was never scheduled (or whose next_run is unreadable)
+            // just gets its first occurrence; it is not due yet.
+            let Some(next_run) = db_next_run else {
+                task.next_run = parsed_schedule.next_after(now, tz).map(|dt| dt.to_rfc3339());
+                self.db.set_task_next_run(&task.id, task.next_run.as_deref())?;
+                info!("Scheduled task {}: next run {:?}", task.id, task.next_run);
+                continue;
+            };
+
+            if next_run > now {
+                continue;
+            }
+
+            let (runs, upcoming) = plan_runs(&parsed_schedule, tz, next_run, policy, now);
+            if runs.is_empty() {
+                info!(
+                    "Task {} missed its run at {} (catch-up: {}), skipping.",
+                    task.id,
+                    next_run.to_rfc3339(),
+                    policy.as_str()
+                );
+            }
+
+            task.next_run = upcoming.map(|dt| dt.to_rfc3339());
+            self.db.set_task_next_run(&task.id, task.next_run.as_deref())?;
+
+            let one_shot = parsed_schedule.is_one_shot();
+            if one_shot && runs.is_empty() {
+                // Missed and skipped: it will never run.
+                self.db.set_task_status(&task.id, "completed")?;
+                info!("One-shot task {} completed without running.", task.id);
+            } else if upcoming.is_none() && !one_shot {
                 info!(
                     "No upcoming runs for task {}. Consider deactivating.",
                     task.id
                 );
             }
+
+            if !runs.is_empty() {
+                due_tasks.push(DueTask {
+                    task,
+                    runs,
+                    one_shot,
+                });
+            }
         }
-        Ok(())
+
+        Ok(due_tasks)
+    }
+}
+
+/// Given a task whose `next_run` has passed, returns the slots that should be
+/// executed now under `policy`, and the first occurrence after `now`.
+fn plan_runs(
+    schedule: &TaskSchedule,
+    tz: Tz,
+    next_run: DateTime<Utc>,
+    policy: CatchUpPolicy,
+    now: DateTime<Utc>,
+) -> (Vec<DateTime<Utc>>, Option<DateTime<Utc>>) {
+    let mut slots = Vec::new();
+    let mut slot = Some(next_run);
+    let mut truncated = false;
+
+    while let Some(s) = slot {
+        if s > now {
+            break;
+        }
+        if slots.len() == MAX_CATCH_UP_RUNS {
+            truncated = true;
+            break;
+        }
+        slots.push(s);
+        slot = if schedule.is_one_shot() {
+            None
+        } else {
+            schedule.next_after(s, tz)
+        };
+    }
+
+    let upcoming = if truncated {
+        warn!(
+            "More than {} missed occurrences since {}, the rest are dropped.",
+            MAX_CATCH_UP_RUNS,
+            next_run.to_rfc3339()
+        );
+        schedule.next_after(now, tz)
+    } else {
+        slot
+    };
+
+    let grace = Duration::seconds(MISSED_RUN_GRACE_SECS);
+    let runs = match policy {
+        CatchUpPolicy::Skip => slots.pop().filter(|s| now - *s <= grace).into_iter().collect(),
+        CatchUpPolicy::Once => slots.pop().into_iter().collect(),
+        CatchUpPolicy::All => slots,
+    };
+
+    (runs, upcoming)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    struct ManualClock(Mutex<DateTime<Utc>>);
+
+    impl ManualClock {
+        fn at(rfc3339: &str) -> Arc<Self> {
+            let dt = DateTime::parse_from_rfc3339(rfc3339).unwrap().with_timezone(&Utc);
+            Arc::new(ManualClock(Mutex::new(dt)))
+        }
+
+        fn advance(&self, by: Duration) {
+            let mut now = self.0.lock().unwrap();
+            *now += by;
+        }
+    }
+
+    impl Clock for ManualClock {
+        fn now(&self) -> DateTime<Utc> {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    fn scheduler_with_task(schedule: &str, catch_up: &str) -> (TaskScheduler, Arc<ManualClock>) {
+        let db = Arc::new(Db::new(":memory:").unwrap());
+        db.add_task(&Task {
+            id: "t1".to_string(),
+            group_folder: "main".to_string(),
+            prompt: "ping".to_string(),
+            schedule: schedule.to_string(),
+            last_run: None,
+            next_run: None,
+            status: "active".to_string(),
+            catch_up: catch_up.to_string(),
+            timezone: None,
+            max_retries: 0,
+            backoff: "exponential".to_string(),
+            consecutive_failures: 0,
+            last_error: None,
+            delivery: "tui".to_string(),
+            timeout_secs: None,
+            backend: None,
+            approval: None,
+        })
+        .unwrap();
+        let clock = ManualClock::at("2026-01-05T10:00:30Z");
+        (
+            TaskScheduler::with_clock(db, Config::default(), clock.clone()),
+            clock,
+        )
+    }
+
+    fn run_count(scheduler: &TaskScheduler) -> usize {
+        scheduler
+            .collect_due_tasks()
+            .unwrap()
+            .iter()
+            .map(|d| d.runs.len())
+            .sum()
+    }
+
+    #[test]
+    fn cron_task_fires_when_stored_next_run_is_reached() {
+        let (scheduler, clock) = scheduler_with_task("0 * * * * *", "once");
+
+        // First tick only schedules the task.
+        assert_eq!(run_count(&scheduler), 0);
+        clock.advance(Duration::seconds(20));
+        assert_eq!(run_count(&scheduler), 0);
+
+        // 10:01:00 has passed.
+        clock.advance(Duration::seconds(15));
+        assert_eq!(run_count(&scheduler), 1);
+
+        // The slot is not handed out twice.
+        assert_eq!(run_count(&scheduler), 0);
+        clock.advance(Duration::seconds(60));
+        assert_eq!(run_count(&scheduler), 1);
+    }
+
+    #[test]
+    fn every_task_fires_on_its_interval() {
+        let (scheduler, clock) = scheduler_with_task("every 5m", "once");
+
+        assert_eq!(run_count(&scheduler), 0);
+        clock.advance(Duration::minutes(4));
+        assert_eq!(run_count(&scheduler), 0);
+        clock.advance(Duration::minutes(1));
+        assert_eq!(run_count(&scheduler), 1);
+    }
+
+    #[test]
+    fn missed_runs_follow_catch_up_policy() {
+        for (policy, expected) in [("skip", 0), ("once", 1), ("all", 5)] {
+            let (scheduler, clock) = scheduler_with_task("0 0 * * * *", policy);
+            assert_eq!(run_count(&scheduler), 0);
+
+            // Stopped from 10:00:30 to 15:30:30: the 11:00..15:00 slots were missed.
+            clock.advance(Duration::minutes(330));
+            assert_eq!(run_count(&scheduler), expected, "policy {}", policy);
+
+            // Afterwards the task is back on its normal cadence.
+            clock.advance(Duration::minutes(30));
+            assert_eq!(run_count(&scheduler), 1, "policy {}", policy);
+        }
+    }
+
+    #[test]
+    fn running_task_is_not_started_again() {
+        let (scheduler, clock) = scheduler_with_task("0 * * * * *", "once");
+        assert_eq!(run_count(&scheduler), 0);
+
+        scheduler.running.lock().unwrap().insert("t1".to_string());
+        clock.advance(Duration::minutes(3));
+        assert_eq!(run_count(&scheduler), 0);
+
+        // Once it is done, the overrun slots are caught up.
+        scheduler.running.lock().unwrap().remove("t1");
+        assert_eq!(run_count(&scheduler), 1);
+    }
+
+    #[test]
+    fn task_running_in_another_process_is_not_started_again() {
+        let (scheduler, clock) = scheduler_with_task("0 * * * * *", "once");
+        assert_eq!(run_count(&scheduler), 0);
+
+        // A `task run-now` in progress elsewhere
+        scheduler
+            .db
+            .insert_task_run(&TaskRun {
+                id: 0,
+                task_id: "t1".to_string(),
+                started_at: clock.now().to_rfc3339(),
+                finished_at: None,
+                duration_ms: None,
+                status: "running".to_string(),
+                result: None,
+                error: None,
+                exit_code: None,
+                attempt: 1,
+                events: Vec::new(),
+                network: None,
+                image: None,
+                diagnostics: Vec::new(),
+                failure: None,
+                artifacts: Vec::new(),
+            })
+            .unwrap();
+        clock.advance(Duration::minutes(3));
+        assert_eq!(run_count(&scheduler), 0);
+        assert!(scheduler.claim("t1").is_err());
+
+        // A run left behind by a process that exited does not block the task
+        assert_eq!(scheduler.db.fail_interrupted_runs(&clock.now().to_rfc3339()).unwrap(), 1);
+        assert_eq!(run_count(&scheduler), 1);
+    }
+
+    #[tokio::test]
+    async fn misconfigured_task_is_recorded_as_a_failed_run() {
+        let (scheduler, _) = scheduler_with_task("0 * * * * *", "once");
+        let mut task = scheduler.db.get_task("t1").unwrap().unwrap();
+        task.approval = Some("sometimes".to_string());
+        task.max_retries = 2;
+        scheduler.run_occurrence(&mut task).await.unwrap();
+
+        // Not retried, as it would fail the same way
+        let runs = scheduler.db.get_task_runs("t1", 10).unwrap();
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].status, "error");
+        assert_eq!(runs[0].failure.as_deref(), Some("invalid config"));
+        assert!(runs[0].error.as_deref().unwrap().contains("invalid approval mode"));
+        assert_eq!(task.consecutive_failures, 1);
+        assert!(task.last_run.is_some());
+    }
+
+    #[test]
+    fn skip_still_runs_a_slot_that_is_only_late_by_a_tick() {
+        let (scheduler, clock) = scheduler_with_task("0 * * * * *", "skip");
+        assert_eq!(run_count(&scheduler), 0);
+        clock.advance(Duration::seconds(80));
+        assert_eq!(run_count(&scheduler), 1);
+    }
+
+    #[test]
+    fn backoff_delays() {
+        let base = TokioDuration::from_secs(60);
+        assert_eq!(BackoffStrategy::Fixed.delay(base, 3), base);
+        assert_eq!(BackoffStrategy::Exponential.delay(base, 1), base);
+        assert_eq!(
+            BackoffStrategy::Exponential.delay(base, 3),
+            TokioDuration::from_secs(240)
+        );
+    }
+
+    #[test]
+    fn parses_schedule_formats() {
+        assert!(matches!("0 0 8 * * *".parse(), Ok(TaskSchedule::Cron(_))));
+        assert!(matches!(
+            "every 30m".parse(),
+            Ok(TaskSchedule::Every(d)) if d == Duration::minutes(30)
+        ));
+        assert!(matches!(
+            "in 2h".parse(),
+            Ok(TaskSchedule::In(d)) if d == Duration::hours(2)
+        ));
+        let at = NaiveDateTime::parse_from_str("2026-10-20 09:00", "%Y-%m-%d %H:%M").unwrap();
+        assert!(matches!("at 2026-10-20T09:00".parse(), Ok(TaskSchedule::At(t)) if t == at));
+        assert!(matches!("at 2026-10-20 09:00:00".parse(), Ok(TaskSchedule::At(t)) if t == at));
+    }
+
+    #[test]
+    fn rejects_invalid_schedules() {
+        for bad in [
+            "every",
+            "every 5",
+            "every 5w",
+            "every 0m",
+            "every 5 m",
+            "in -1h",
+            "at tomorrow",
+            "at 2026-13-01T09:00",
+            "not a cron",
+        ] {
+            assert!(bad.parse::<TaskSchedule>().is_err(), "{} should not parse", bad);
+        }
+    }
+
+    #[test]
+    fn parses_delivery_targets() {
+        for valid in ["tui", "none", "file", "file:briefings/daily.md", "channel:123@s.whatsapp.net"] {
+            let delivery: Delivery = valid.parse().unwrap();
+            assert_eq!(delivery.to_string(), valid);
+        }
+        for bad in ["email", "file:", "file:/etc/passwd", "file:../other/notes.md", "channel:"] {
+            assert!(bad.parse::<Delivery>().is_err(), "{} should not parse", bad);
+        }
+    }
+
+    #[test]
+    fn one_shot_task_runs_once() {
+        let (scheduler, clock) = scheduler_with_task("in 5m", "once");
+        assert_eq!(run_count(&scheduler), 0);
+        clock.advance(Duration::minutes(5));
+        assert_eq!(run_count(&scheduler), 1);
+
+        let task = scheduler.db.get_task("t1").unwrap().unwrap();
+        assert_eq!(task.next_run, None);
+        clock.advance(Duration::minutes(5));
+        assert_eq!(run_count(&scheduler), 0);
+    }
+
+    #[test]
+    fn missed_one_shot_with_skip_is_completed() {
+        let (scheduler, clock) = scheduler_with_task("at 2026-01-05T11:00", "skip");
+        assert_eq!(run_count(&scheduler), 0);
+        clock.advance(Duration::hours(3));
+        assert_eq!(run_count(&scheduler), 0);
+
+        let task = scheduler.db.get_task("t1").unwrap().unwrap();
+        assert_eq!(task.status, "completed");
+    }
+
+    fn utc(rfc3339: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(rfc3339).unwrap().with_timezone(&Utc)
+    }
+
+    fn next_in(schedule: &str, tz: &str, after: &str) -> DateTime<Utc> {
+        TaskSchedule::from_str(schedule)
+            .unwrap()
+            .next_after(utc(after), tz.parse().unwrap())
+            .unwrap()
+    }
+
+    #[test]
+    fn cron_is_evaluated_in_the_task_timezone() {
+        assert_eq!(
+            next_in("0 0 8 * * *", "Europe/Madrid", "2026-01-05T00:00:00Z"),
+            utc("2026-01-05T07:00:00Z")
+        );
+        assert_eq!(
+            next_in("0 0 8 * * *", "America/New_York", "2026-07-01T00:00:00Z"),
+            utc("2026-07-01T12:00:00Z")
+        );
+    }
+
+    #[test]
+    fn cron_time_in_dst_gap_fires_when_the_gap_ends() {
+        // Madrid jumps from 02:00 CET to 03:00 CEST on 2026-03-29.
+        assert_eq!(
+            next_in("0 30 2 * * *", "Europe/Madrid", "2026-03-28T12:00:00Z"),
+            utc("2026-03-29T01:00:00Z")
+        );
+        assert_eq!(
+            next_in("0 30 2 * * *", "Europe/Madrid", "2026-03-29T01:00:00Z"),
+            utc("2026-03-30T00:30:00Z")
+        );
+    }
+
+    #[test]
+    fn cron_time_in_dst_overlap_fires_once() {
+        // Madrid goes back from 03:00 CEST to 02:00 CET on 2026-10-25,
+        // so 02:30 happens at 00:30 UTC and again at 01:30 UTC.
+        assert_eq!(
+            next_in("0 30 2 * * *", "Europe/Madrid", "2026-10-24T12:00:00Z"),
+            utc("2026-10-25T00:30:00Z")
+        );
+        assert_eq!(
+            next_in("0 30 2 * * *", "Europe/Madrid", "2026-10-25T00:30:00Z"),
+            utc("2026-10-26T01:30:00Z")
+        );
+        // Starting from inside the repeated hour does not fire the repeat either.
+        assert_eq!(
+            next_in("0 30 2 * * *", "Europe/Madrid", "2026-10-25T01:10:00Z"),
+            utc("2026-10-26T01:30:00Z")
+        );
     }
 }
@@ -1,45 +1,103 @@
+use crate::approval::ApprovalRequest;
+use crate::artifacts::{open_artifact, Artifact, ArtifactChange};
+use crate::container::{tool_label, AgentEvent};
+use crate::db::SessionTurn;
 use crossterm::{
-    event::{self, Event, KeyCode},
+    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, MouseButton, MouseEvent, MouseEventKind},
     execute,
-    style::Stylize,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 use ratatui::{
     backend::CrosstermBackend,
-    layout::{Constraint, Direction, Layout},
-    style::{Color, Style},
+    layout::{Constraint, Direction, Layout, Position, Rect},
+    style::{Color, Modifier, Style},
     text::{Line, Span},
-    widgets::{Block, Borders, Padding, Paragraph, Wrap},
+    widgets::{Block, Borders, Clear, Padding, Paragraph, Wrap},
     Frame, Terminal,
 };
 use std::collections::VecDeque;
 use std::io;
-use std::sync::mpsc::{Receiver, Sender};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::info;
 use tracing_subscriber::fmt::MakeWriter;
 
 // Mensajes que enviamos de la TUI al worker
 pub enum AppEvent {
     Input(String),
+    /// Stop the prompt in progress.
+    Cancel,
+    /// `/new`: the next prompt starts a new agent session.
+    NewSession,
+    /// `/sessions`: list the recent sessions.
+    ListSessions,
+    /// `/switch <n|id>`: continue another session.
+    SwitchSession(String),
+    /// Sent by the worker itself when a prompt's run ends, to start the next
+    /// queued one.
+    RunFinished,
 }
 
 // Mensajes que recibimos del worker en la TUI
 pub enum WorkerEvent {
-    Response(String),
-    Log(String),
+    /// Final events of the prompt in progress and the files it changed.
+    Response { events: Vec<AgentEvent>, artifacts: Vec<Artifact> },
+    /// A prompt ended, or was dropped from the queue, without a result.
+    Failed(String),
+    /// An event streamed while the prompt runs.
+    Agent(AgentEvent),
+    /// Result of a scheduled task delivered to the chat.
+    TaskResult {
+        task_id: String,
+        events: Vec<AgentEvent>,
+        artifacts: Vec<Artifact>,
+    },
+    /// Information for the user, such as the list of sessions.
+    Notice(String),
+    /// The prompt just sent started a new session.
+    SessionStarted { title: String },
+    /// The chat now shows another session; `None` until its first prompt.
+    SessionSwitched { title: Option<String>, turns: Vec<SessionTurn> },
+    /// A tool call waits for the user's approval.
+    Approval(ApprovalRequest),
+    /// A scheduled task failed all its attempts.
+    TaskFailed {
+        task_id: String,
+        error: String,
+        consecutive_failures: u32,
+        paused: bool,
+    },
+}
+
+/// A sender for `RunOptions::approvals` that shows each question in the TUI.
+pub fn approval_prompter(tui: Sender<WorkerEvent>) -> Sender<ApprovalRequest> {
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        for request in rx {
+            let _ = tui.send(WorkerEvent::Approval(request));
+        }
+    });
+    tx
 }
 
 #[derive(Clone, Debug)]
 pub enum MessageAuthor {
     User,
     Assistant,
+    System,
+    Info,
 }
 
 #[derive(Clone, Debug)]
 pub struct ChatMessage {
     pub author: MessageAuthor,
     pub text: String,
+    /// What the agent did, rendered after `text` for assistant messages.
+    pub events: Vec<AgentEvent>,
+    /// Files the agent's run changed, listed last; they open on click.
+    pub artifacts: Vec<Artifact>,
 }
 
 // Estructura para capturar logs en memoria para la TUI
@@ -96,6 +154,34 @@ pub struct App {
     pub content_height: u16,
     pub input_mode: InputMode,
     pub is_loading: bool,
+    /// Prompts sent that have not been answered yet; they run in order.
+    pub pending_prompts: usize,
+    /// Index in `messages` of the reply being streamed.
+    pub streaming: Option<usize>,
+    /// Title of the agent session the chat continues.
+    pub session_title: Option<String>,
+    /// Tool calls waiting for an answer; the first one is shown.
+    pub approvals: VecDeque<ApprovalRequest>,
+    /// Inside of the chat box as last drawn, to map clicks to its rows.
+    pub chat_area: Rect,
+    /// Files listed in the chat, by the content row they were drawn on.
+    pub links: Vec<(u16, PathBuf)>,
+}
+
+impl App {
+    /// Adds an event to the reply being streamed, starting it if needed.
+    fn push_stream_event(&mut self, event: AgentEvent) {
+        let index = *self.streaming.get_or_insert_with(|| {
+            self.messages.push(ChatMessage {
+                author: MessageAuthor::Assistant,
+                text: String::new(),
+                events: Vec::new(),
+                artifacts: Vec::new(),
+            });
+            self.messages.len() - 1
+        });
+        self.messages[index].events.push(event);
+    }
 }
 
 #[derive(PartialEq)]
@@ -110,7 +196,9 @@ impl App {
             input: String::new(),
             messages: vec![ChatMessage {
                 author: MessageAuthor::Assistant,
-                text: "🚀 TUI initialized. Press 'i' to type, 'Esc' to scroll chat.".to_string(),
+                text: "🚀 TUI initialized. Press 'i' to type, 'Esc' to scroll chat. /sessions lists earlier conversations, /new starts another.".to_string(),
+                events: Vec::new(),
+                artifacts: Vec::new(),
             }],
             logger,
             tx,
@@ -119,14 +207,202 @@ impl App {
             content_height: 0,
             input_mode: InputMode::Editing, // Empezar en modo edición por comodidad
             is_loading: false,
+            pending_prompts: 0,
+            streaming: None,
+            session_title: None,
+            approvals: VecDeque::new(),
+            chat_area: Rect::default(),
+            links: Vec::new(),
+        }
+    }
+
+    /// Scrolls the chat with the wheel and opens the file under a click.
+    fn handle_mouse(&mut self, mouse: MouseEvent) {
+        match mouse.kind {
+            MouseEventKind::ScrollUp => self.scroll = self.scroll.saturating_sub(1),
+            MouseEventKind::ScrollDown => self.scroll = self.scroll.saturating_add(1),
+            MouseEventKind::Down(MouseButton::Left) if self.chat_area.contains(Position::new(mouse.column, mouse.row)) => {
+                let row = (mouse.row - self.chat_area.y).saturating_add(self.scroll);
+                let Some((_, path)) = self.links.iter().find(|(r, _)| *r == row) else {
+                    return;
+                };
+                match open_file(path) {
+                    Ok(()) => info!("Opened {}", path.display()),
+                    Err(e) => self.messages.push(ChatMessage {
+                        author: MessageAuthor::System,
+                        text: format!("Could not open {}: {}", path.display(), e),
+                        events: Vec::new(),
+                        artifacts: Vec::new(),
+                    }),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Handles a `/command` typed in the input. Returns false for unknown ones.
+    fn run_command(&mut self, command: &str) -> bool {
+        let (name, arg) = command.split_once(' ').unwrap_or((command, ""));
+        let event = match (name, arg.trim()) {
+            ("/new", _) => AppEvent::NewSession,
+            ("/sessions", _) => AppEvent::ListSessions,
+            ("/switch", key) if !key.is_empty() => AppEvent::SwitchSession(key.to_string()),
+            _ => return false,
+        };
+        let _ = self.tx.send(event);
+        true
+    }
+}
+
+fn push_text_lines(lines: &mut Vec<Line<'_>>, text: &str) {
+    for line in text.lines() {
+        if line.trim().is_empty() {
+            lines.push(Line::from(""));
+        } else {
+            lines.push(Line::from(Span::styled(
+                format!("  {}", line),
+                Style::default().fg(Color::White),
+            )));
+        }
+    }
+}
+
+/// Renders an agent's events: text in white, tool calls in magenta with
+/// their output dimmed, errors in red and the run stats at the end.
+fn push_event_lines(lines: &mut Vec<Line<'_>>, events: &[AgentEvent]) {
+    // Streamed text arrives in fragments that may split lines
+    let mut text = String::new();
+    for event in events {
+        if let AgentEvent::Text { text: fragment } = event {
+            text.push_str(fragment);
+            continue;
+        }
+        if !text.is_empty() {
+            push_text_lines(lines, text.trim());
+            lines.push(Line::from(""));
+            text.clear();
+        }
+        match event {
+            AgentEvent::Text { .. } => {}
+            AgentEvent::ToolUse { name, parameters } => {
+                lines.push(Line::from(Span::styled(
+                    format!("  🔨 {}", tool_label(name, parameters)),
+                    Style::default().fg(Color::Magenta),
+                )));
+            }
+            AgentEvent::ToolResult { output, is_error } => {
+                let color = if *is_error { Color::Red } else { Color::DarkGray };
+                for line in output.trim_end().lines() {
+                    lines.push(Line::from(Span::styled(
+                        format!("  {}", line),
+                        Style::default().fg(color),
+                    )));
+                }
+                lines.push(Line::from(""));
+            }
+            AgentEvent::Stats(stats) => {
+                let summary = stats.summary();
+                if !summary.is_empty() {
+                    lines.push(Line::from(Span::styled(
+                        format!("  ⏱ {}", summary),
+                        Style::default().fg(Color::DarkGray),
+                    )));
+                }
+            }
+            AgentEvent::Error { message } => {
+                lines.push(Line::from(Span::styled(
+                    format!("  ⚠️ {}", message),
+                    Style::default().fg(Color::Red),
+                )));
+            }
+            AgentEvent::Approval { tool, allowed, reason } => {
+                let (mark, color) = if *allowed { ("✅", Color::Green) } else { ("🚫", Color::Red) };
+                lines.push(Line::from(Span::styled(
+                    format!("  {} {} ({})", mark, tool, reason),
+                    Style::default().fg(color),
+                )));
+            }
+            AgentEvent::Session { .. } => {}
         }
     }
+    if !text.is_empty() {
+        push_text_lines(lines, text.trim());
+    }
+}
+
+/// Lists the files a run changed. The index of each line that can be
+/// opened goes to `links` along with its file.
+fn push_artifact_lines(lines: &mut Vec<Line<'_>>, artifacts: &[Artifact], links: &mut Vec<(usize, PathBuf)>) {
+    for artifact in artifacts {
+        let style = if artifact.change == ArtifactChange::Deleted {
+            Style::default().fg(Color::DarkGray).add_modifier(Modifier::CROSSED_OUT)
+        } else {
+            links.push((lines.len(), artifact.path.clone()));
+            Style::default().fg(Color::Cyan).add_modifier(Modifier::UNDERLINED)
+        };
+        lines.push(Line::from(Span::styled(format!("  📎 {}", artifact.label()), style)));
+    }
+}
+
+/// Opens a file with the desktop's default application.
+fn open_file(path: &Path) -> io::Result<()> {
+    // Checked right before, so a symlink swapped in after the run is not opened
+    open_artifact(path)?;
+    let opener = if cfg!(target_os = "macos") { "open" } else { "xdg-open" };
+    let mut child = std::process::Command::new(opener)
+        .arg(path)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()?;
+    std::thread::spawn(move || child.wait());
+    Ok(())
+}
+
+/// A dialog over the chat asking whether a tool call may run.
+fn render_approval(f: &mut Frame, request: &ApprovalRequest, pending: usize) {
+    let area = f.area();
+    let width = area.width.saturating_sub(8).min(80);
+    let dialog = Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + area.height.saturating_sub(7) / 2,
+        width,
+        height: 7.min(area.height),
+    };
+    let mut text = vec![
+        Line::from(Span::styled(
+            format!("The agent of '{}' wants to run:", request.group),
+            Style::default().fg(Color::White),
+        )),
+        Line::from(Span::styled(
+            format!("🔨 {}", request.label),
+            Style::default().fg(Color::Magenta),
+        )),
+        Line::from(""),
+        Line::from(Span::styled("[y] allow   [n] deny", Style::default().fg(Color::Yellow))),
+    ];
+    if pending > 1 {
+        text.push(Line::from(Span::styled(
+            format!("{} more waiting", pending - 1),
+            Style::default().fg(Color::DarkGray),
+        )));
+    }
+    let paragraph = Paragraph::new(text)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(" Tool approval ")
+                .border_style(Style::default().yellow()),
+        )
+        .wrap(Wrap { trim: false });
+    f.render_widget(Clear, dialog);
+    f.render_widget(paragraph, dialog);
 }
 
 pub fn run_tui(mut app: App) -> io::Result<()> {
     enable_raw_mode()?;
     let mut stdout = io::stdout();
-    execute!(stdout, EnterAlternateScreen)?;
+    execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
 
@@ -134,14 +410,105 @@ pub fn run_tui(mut app: App) -> io::Result<()> {
         // Procesar respuestas del worker
         while let Ok(event) = app.rx.try_recv() {
             match event {
-                WorkerEvent::Response(res) => {
-                    app.is_loading = false;
+                WorkerEvent::Response { events, artifacts } => {
+                    app.pending_prompts = app.pending_prompts.saturating_sub(1);
+                    app.is_loading = app.pending_prompts > 0;
+                    // The final events replace what was streamed
+                    match app.streaming.take() {
+                        Some(index) => {
+                            app.messages[index].events = events;
+                            app.messages[index].artifacts = artifacts;
+                        }
+                        None if !events.is_empty() || !artifacts.is_empty() => app.messages.push(ChatMessage {
+                            author: MessageAuthor::Assistant,
+                            text: String::new(),
+                            events,
+                            artifacts,
+                        }),
+                        None => {}
+                    }
+                }
+                WorkerEvent::Failed(err) => {
+                    app.pending_prompts = app.pending_prompts.saturating_sub(1);
+                    app.is_loading = app.pending_prompts > 0;
+                    app.streaming = None;
+                    app.messages.push(ChatMessage {
+                        author: MessageAuthor::System,
+                        text: format!("Error: {}", err),
+                        events: Vec::new(),
+                        artifacts: Vec::new(),
+                    });
+                }
+                WorkerEvent::Agent(event) => app.push_stream_event(event),
+                WorkerEvent::Notice(text) => app.messages.push(ChatMessage {
+                    author: MessageAuthor::Info,
+                    text,
+                    events: Vec::new(),
+                    artifacts: Vec::new(),
+                }),
+                WorkerEvent::SessionStarted { title } => app.session_title = Some(title),
+                WorkerEvent::SessionSwitched { title, turns } => {
+                    app.streaming = None;
+                    app.messages.clear();
+                    app.messages.push(ChatMessage {
+                        author: MessageAuthor::Info,
+                        text: match &title {
+                            Some(title) => format!("Continuing '{}'", title),
+                            None => "New session".to_string(),
+                        },
+                        events: Vec::new(),
+                        artifacts: Vec::new(),
+                    });
+                    for turn in turns {
+                        app.messages.push(ChatMessage {
+                            author: MessageAuthor::User,
+                            text: turn.prompt,
+                            events: Vec::new(),
+                            artifacts: Vec::new(),
+                        });
+                        app.messages.push(ChatMessage {
+                            author: MessageAuthor::Assistant,
+                            text: String::new(),
+                            events: turn.events,
+                            artifacts: Vec::new(),
+                        });
+                    }
+                    app.session_title = title;
+                    app.scroll = u16::MAX;
+                }
+                WorkerEvent::TaskResult {
+                    task_id,
+                    events,
+                    artifacts,
+                } => {
                     app.messages.push(ChatMessage {
                         author: MessageAuthor::Assistant,
-                        text: res,
+                        text: format!("📅 Task '{}':", task_id),
+                        events,
+                        artifacts,
+                    });
+                }
+                WorkerEvent::Approval(request) => app.approvals.push_back(request),
+                WorkerEvent::TaskFailed {
+                    task_id,
+                    error,
+                    consecutive_failures,
+                    paused,
+                } => {
+                    let mut text = format!(
+                        "Task '{}' failed ({} in a row): {}",
+                        task_id, consecutive_failures, error
+                    );
+                    if paused {
+                        text.push_str("\nThe task has been paused. Resume it with 'rclaw task resume'.");
+                    }
+                    app.messages.push(ChatMessage {
+                        author: MessageAuthor::System,
+                        text,
+                        events: Vec::new(),
+                        artifacts: Vec::new(),
                     });
                 }
-                WorkerEvent::Log(_msg) => {}
             }
         }
 
@@ -162,6 +529,7 @@ pub fn run_tui(mut app: App) -> io::Result<()> {
 
             // 1. Chat Area
             let mut chat_text = Vec::new();
+            let mut link_lines = Vec::new();
             for msg in &app.messages {
                 match msg.author {
                     MessageAuthor::User => {
@@ -170,54 +538,28 @@ pub fn run_tui(mut app: App) -> io::Result<()> {
                             Span::styled(&msg.text, Style::default().fg(Color::Yellow)),
                         ]));
                     }
-                    MessageAuthor::Assistant => {
-                        let mut in_tool_result = false;
+                    MessageAuthor::System => {
+                        for (i, line) in msg.text.lines().enumerate() {
+                            let prefix = if i == 0 { "⚠️ " } else { "   " };
+                            chat_text.push(Line::from(Span::styled(
+                                format!("{}{}", prefix, line),
+                                Style::default().fg(Color::Red),
+                            )));
+                        }
+                    }
+                    MessageAuthor::Info => {
                         for line in msg.text.lines() {
-                            if line.starts_with("[RCLAW_USE_TOOL]") {
-                                in_tool_result = false;
-                                let content = line.trim_start_matches("[RCLAW_USE_TOOL]");
-                                chat_text.push(Line::from(Span::styled(
-                                    format!("  🔨 {}", content),
-                                    Style::default().fg(Color::Magenta),
-                                )));
-                            } else if line.starts_with("[RCLAW_TOOL_RESULT]") {
-                                in_tool_result = true;
-                                let mut content = line.trim_start_matches("[RCLAW_TOOL_RESULT]").to_string();
-                                
-                                // Si la línea contiene el marcador de fin, lo quitamos y cerramos el estado gris
-                                if content.contains("[RCLAW_END_RESULT]") {
-                                    content = content.replace("[RCLAW_END_RESULT]", "");
-                                    in_tool_result = false;
-                                }
-
-                                if !content.is_empty() {
-                                    chat_text.push(Line::from(Span::styled(
-                                        format!("  {}", content),
-                                        Style::default().fg(Color::DarkGray),
-                                    )));
-                                }
-                            } else {
-                                if line.trim().is_empty() {
-                                    chat_text.push(Line::from(""));
-                                    continue;
-                                }
-
-                                let style = if in_tool_result {
-                                    Style::default().fg(Color::DarkGray)
-                                } else {
-                                    Style::default().fg(Color::White)
-                                };
-
-                                chat_text.push(Line::from(Span::styled(
-                                    format!("  {}", line),
-                                    style,
-                                )));
-                                
-                                // Si no estamos en un bloque de resultado explícito, cualquier línea de texto es normal
-                                // Nota: in_tool_result solo se mantiene true si no encontramos el marcador de fin
-                            }
+                            chat_text.push(Line::from(Span::styled(
+                                format!("ℹ️ {}", line),
+                                Style::default().fg(Color::Cyan),
+                            )));
                         }
                     }
+                    MessageAuthor::Assistant => {
+                        push_text_lines(&mut chat_text, &msg.text);
+                        push_event_lines(&mut chat_text, &msg.events);
+                        push_artifact_lines(&mut chat_text, &msg.artifacts, &mut link_lines);
+                    }
                 }
                 chat_text.push(Line::from(""));
             }
@@ -249,24 +591,40 @@ pub fn run_tui(mut app: App) -> io::Result<()> {
             }
             app.content_height = line_count;
 
+            let session = app
+                .session_title
+                .as_ref()
+                .map(|title| format!(" · {}", title))
+                .unwrap_or_default();
             let chat_title = if app.input_mode == InputMode::Normal {
-                " Rclaw Chat (SCROLL MODE - Up/Down to navigate, 'i' to type) "
+                format!(" Rclaw Chat{} (SCROLL MODE - Up/Down to navigate, 'i' to type) ", session)
             } else {
-                " Rclaw Chat "
+                format!(" Rclaw Chat{} ", session)
             };
 
+            let chat_block = Block::default()
+                .borders(Borders::ALL)
+                .title(chat_title)
+                .padding(Padding::uniform(1))
+                .border_style(if app.input_mode == InputMode::Normal {
+                    Style::default().yellow()
+                } else {
+                    Style::default()
+                });
+
+            // Rows each line takes once wrapped, so a click finds its file
+            app.chat_area = chat_block.inner(chunks[0]);
+            let width = app.chat_area.width.max(1) as usize;
+            let mut rows = Vec::with_capacity(chat_text.len());
+            let mut row: u16 = 0;
+            for line in &chat_text {
+                rows.push(row);
+                row = row.saturating_add(line.width().div_ceil(width).max(1) as u16);
+            }
+            app.links = link_lines.into_iter().map(|(i, path)| (rows[i], path)).collect();
+
             let chat_paragraph = Paragraph::new(chat_text)
-                .block(
-                    Block::default()
-                        .borders(Borders::ALL)
-                        .title(chat_title)
-                        .padding(Padding::uniform(1))
-                        .border_style(if app.input_mode == InputMode::Normal {
-                            Style::default().yellow()
-                        } else {
-                            Style::default()
-                        }),
-                )
+                .block(chat_block)
                 .wrap(Wrap { trim: false })
                 .scroll((app.scroll, 0));
 
@@ -296,6 +654,7 @@ pub fn run_tui(mut app: App) -> io::Result<()> {
             // 3. Input Area
             let input_title = match app.input_mode {
                 InputMode::Normal => " Press 'i' to type",
+                InputMode::Editing if app.is_loading => " Your Message (Ctrl+X to cancel the running prompt)",
                 InputMode::Editing => " Your Message (Enter to send, Esc to exit typing mode)",
             };
 
@@ -316,13 +675,49 @@ pub fn run_tui(mut app: App) -> io::Result<()> {
                 );
             f.render_widget(input, chunks[2]);
 
-            if app.input_mode == InputMode::Editing {
+            if let Some(request) = app.approvals.front() {
+                render_approval(f, request, app.approvals.len());
+            } else if app.input_mode == InputMode::Editing {
                 f.set_cursor_position((chunks[2].x + app.input.len() as u16 + 1, chunks[2].y + 1));
             }
         })?;
 
         if event::poll(Duration::from_millis(50))? {
-            if let Event::Key(key) = event::read()? {
+            let event = event::read()?;
+            if let Event::Mouse(mouse) = event {
+                if app.approvals.is_empty() {
+                    app.handle_mouse(mouse);
+                }
+                continue;
+            }
+            if let Event::Key(key) = event {
+                // An approval question takes every key until it is answered
+                if !app.approvals.is_empty() {
+                    let answer = match key.code {
+                        KeyCode::Char('y') | KeyCode::Char('Y') => Some(true),
+                        KeyCode::Char('n') | KeyCode::Char('N') | KeyCode::Esc => Some(false),
+                        _ => None,
+                    };
+                    if let Some(allow) = answer {
+                        if let Some(request) = app.approvals.pop_front() {
+                            request.answer(allow);
+                        }
+                    }
+                    continue;
+                }
+                if app.is_loading
+                    && key.code == KeyCode::Char('x')
+                    && key.modifiers.contains(event::KeyModifiers::CONTROL)
+                {
+                    let _ = app.tx.send(AppEvent::Cancel);
+                    app.messages.push(ChatMessage {
+                        author: MessageAuthor::System,
+                        text: "Cancelling the current prompt...".to_string(),
+                        events: Vec::new(),
+                        artifacts: Vec::new(),
+                    });
+                    continue;
+                }
                 match app.input_mode {
                     InputMode::Normal => match key.code {
                         KeyCode::Char('i') => {
@@ -345,20 +740,33 @@ pub fn run_tui(mut app: App) -> io::Result<()> {
                         {
                             break;
                         }
-                        KeyCode::Enter => {
-                            if !app.input.is_empty() {
-                                let input_text = app.input.clone();
+                        KeyCode::Enter if app.input.starts_with('/') => {
+                            let command = app.input.trim().to_string();
+                            if !app.run_command(&command) {
                                 app.messages.push(ChatMessage {
-                                    author: MessageAuthor::User,
-                                    text: input_text.clone(),
+                                    author: MessageAuthor::System,
+                                    text: format!("Unknown command '{}'. Try /new, /sessions or /switch <n>.", command),
+                                    events: Vec::new(),
+                                    artifacts: Vec::new(),
                                 });
-                                app.is_loading = true;
-                                if input_text == "quit" || input_text == "exit" {
-                                    break;
-                                }
-                                let _ = app.tx.send(AppEvent::Input(input_text));
-                                app.input.clear();
                             }
+                            app.input.clear();
+                        }
+                        KeyCode::Enter if !app.input.is_empty() => {
+                            let input_text = app.input.clone();
+                            app.messages.push(ChatMessage {
+                                author: MessageAuthor::User,
+                                text: input_text.clone(),
+                                events: Vec::new(),
+                                artifacts: Vec::new(),
+                            });
+                            app.is_loading = true;
+                            app.pending_prompts += 1;
+                            if input_text == "quit" || input_text == "exit" {
+                                break;
+                            }
+                            let _ = app.tx.send(AppEvent::Input(input_text));
+                            app.input.clear();
                         }
                         KeyCode::Char(c) => {
                             app.input.push(c);
@@ -377,7 +785,7 @@ pub fn run_tui(mut app: App) -> io::Result<()> {
     }
 
     disable_raw_mode()?;
-    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen, DisableMouseCapture)?;
     terminal.show_cursor()?;
 
     Ok(())
@@ -0,0 +1,224 @@
+use std::ffi::{CStr, CString, OsStr, OsString};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+/// A directory inside a workspace, opened without following symlinks.
+///
+/// The agent can write to the workspace, so it can replace any file or
+/// directory there with a symlink to a host path. Everything the host does in
+/// the workspace on its own behalf goes through this type: names are
+/// resolved relative to the open directory and symlinks are never followed,
+/// so swapping a path component later has no effect on the host.
+pub struct WorkspaceDir {
+    fd: OwnedFd,
+}
+
+fn cstr(name: &OsStr) -> io::Result<CString> {
+    if name.is_empty() || name == "." || name == ".." || name.as_bytes().contains(&b'/') {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid file name {:?}", name)));
+    }
+    CString::new(name.as_bytes()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+fn check(result: libc::c_int) -> io::Result<libc::c_int> {
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
+
+impl WorkspaceDir {
+    /// Opens `path`, which must be a directory the agent cannot replace, such
+    /// as the root of a workspace mount.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let path = CString::new(path.as_os_str().as_bytes()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let fd = check(unsafe {
+            libc::open(path.as_ptr(), libc::O_RDONLY | libc::O_DIRECTORY | libc::O_NOFOLLOW | libc::O_CLOEXEC)
+        })?;
+        Ok(WorkspaceDir {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+        })
+    }
+
+    /// Opens the subdirectory `name`; fails if it is a symlink.
+    pub fn open_dir(&self, name: impl AsRef<OsStr>) -> io::Result<Self> {
+        let name = cstr(name.as_ref())?;
+        let fd = check(unsafe {
+            libc::openat(
+                self.fd.as_raw_fd(),
+                name.as_ptr(),
+                libc::O_RDONLY | libc::O_DIRECTORY | libc::O_NOFOLLOW | libc::O_CLOEXEC,
+            )
+        })?;
+        Ok(WorkspaceDir {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+        })
+    }
+
+    /// Opens the subdirectory `name`, creating it if needed.
+    pub fn create_dir(&self, name: impl AsRef<OsStr>) -> io::Result<Self> {
+        let c_name = cstr(name.as_ref())?;
+        if let Err(e) = check(unsafe { libc::mkdirat(self.fd.as_raw_fd(), c_name.as_ptr(), 0o755) }) {
+            if e.kind() != io::ErrorKind::AlreadyExists {
+                return Err(e);
+            }
+        }
+        self.open_dir(name)
+    }
+
+    /// Opens each directory of a relative `path` in turn, creating them.
+    pub fn create_dir_all(&self, path: &Path) -> io::Result<Self> {
+        let mut dir = self.try_clone()?;
+        for component in path.iter().filter(|c| *c != ".") {
+            dir = dir.create_dir(component)?;
+        }
+        Ok(dir)
+    }
+
+    fn try_clone(&self) -> io::Result<Self> {
+        Ok(WorkspaceDir {
+            fd: self.fd.try_clone()?,
+        })
+    }
+
+    /// Names of the entries in the directory.
+    pub fn entries(&self) -> io::Result<Vec<OsString>> {
+        // A fresh descriptor for each listing: a dup would share the read
+        // offset, leaving later listings empty. `fdopendir` takes ownership.
+        let fd = check(unsafe {
+            libc::openat(
+                self.fd.as_raw_fd(),
+                c".".as_ptr(),
+                libc::O_RDONLY | libc::O_DIRECTORY | libc::O_NOFOLLOW | libc::O_CLOEXEC,
+            )
+        })?;
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let dir = unsafe { libc::fdopendir(fd.as_raw_fd()) };
+        if dir.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        std::mem::forget(fd);
+
+        let mut names = Vec::new();
+        loop {
+            let entry = unsafe { libc::readdir(dir) };
+            if entry.is_null() {
+                break;
+            }
+            let name = unsafe { CStr::from_ptr((*entry).d_name.as_ptr()) };
+            if name.to_bytes() != b"." && name.to_bytes() != b".." {
+                names.push(OsStr::from_bytes(name.to_bytes()).to_os_string());
+            }
+        }
+        unsafe { libc::closedir(dir) };
+        Ok(names)
+    }
+
+    /// Opens `name` with `flags` plus `O_NOFOLLOW`, so a symlink is an error.
+    fn open_file(&self, name: &OsStr, flags: libc::c_int) -> io::Result<File> {
+        let name = cstr(name)?;
+        let fd = check(unsafe {
+            libc::openat(
+                self.fd.as_raw_fd(),
+                name.as_ptr(),
+                flags | libc::O_NOFOLLOW | libc::O_CLOEXEC,
+                0o644 as libc::c_uint,
+            )
+        })?;
+        Ok(unsafe { File::from_raw_fd(fd) })
+    }
+
+    /// Contents of the regular file `name`. Symlinks, FIFOs, devices and
+    /// directories are refused without blocking on them.
+    pub fn read_to_string(&self, name: impl AsRef<OsStr>) -> io::Result<String> {
+        let mut file = self.open_file(name.as_ref(), libc::O_RDONLY | libc::O_NONBLOCK)?;
+        if !file.metadata()?.is_file() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "not a regular file"));
+        }
+        let mut content = String::new();
+        file.read_to_string(&mut content)?;
+        Ok(content)
+    }
+
+    /// Writes `content` to a new file `name`, replacing whatever entry had
+    /// that name (a planted symlink is removed, not followed).
+    pub fn write_new(&self, name: impl AsRef<OsStr>, content: &[u8]) -> io::Result<()> {
+        let name = name.as_ref();
+        match self.remove(name) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        let mut file = self.open_file(name, libc::O_WRONLY | libc::O_CREAT | libc::O_EXCL)?;
+        file.write_all(content)
+    }
+
+    /// Opens the regular file `name` to append to it, creating it if needed.
+    pub fn append(&self, name: impl AsRef<OsStr>) -> io::Result<File> {
+        let file = self.open_file(name.as_ref(), libc::O_WRONLY | libc::O_APPEND | libc::O_CREAT | libc::O_NONBLOCK)?;
+        if !file.metadata()?.is_file() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "not a regular file"));
+        }
+        Ok(file)
+    }
+
+    /// Renames `from` to `to` inside this directory. A symlink at `to` is
+    /// replaced, never followed.
+    pub fn rename(&self, from: impl AsRef<OsStr>, to: impl AsRef<OsStr>) -> io::Result<()> {
+        let (from, to) = (cstr(from.as_ref())?, cstr(to.as_ref())?);
+        let fd = self.fd.as_raw_fd();
+        check(unsafe { libc::renameat(fd, from.as_ptr(), fd, to.as_ptr()) })?;
+        Ok(())
+    }
+
+    /// Removes the file or symlink `name`.
+    pub fn remove(&self, name: impl AsRef<OsStr>) -> io::Result<()> {
+        let name = cstr(name.as_ref())?;
+        check(unsafe { libc::unlinkat(self.fd.as_raw_fd(), name.as_ptr(), 0) })?;
+        Ok(())
+    }
+
+    /// Removes the empty directory `name`.
+    pub fn remove_dir(&self, name: impl AsRef<OsStr>) -> io::Result<()> {
+        let name = cstr(name.as_ref())?;
+        check(unsafe { libc::unlinkat(self.fd.as_raw_fd(), name.as_ptr(), libc::AT_REMOVEDIR) })?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::os::unix::fs::symlink;
+
+    #[test]
+    fn does_not_follow_planted_symlinks() {
+        let root = std::env::temp_dir().join(format!("rclaw-workspace-fs-{}", uuid::Uuid::new_v4().simple()));
+        let host = root.join("host");
+        let workspace = root.join("workspace");
+        fs::create_dir_all(&host).unwrap();
+        fs::create_dir_all(workspace.join("responses")).unwrap();
+        fs::write(host.join("secret"), "host file").unwrap();
+        symlink(host.join("secret"), workspace.join("request.json")).unwrap();
+        symlink(host.join("secret"), workspace.join("responses/1.json.tmp")).unwrap();
+        symlink(&host, workspace.join("tasks")).unwrap();
+
+        let dir = WorkspaceDir::open(&workspace).unwrap();
+        assert!(dir.read_to_string("request.json").is_err());
+        assert!(dir.open_dir("tasks").is_err());
+        assert!(dir.create_dir_all(Path::new("tasks/daily")).is_err());
+        assert!(dir.append("request.json").is_err());
+
+        let responses = dir.open_dir("responses").unwrap();
+        responses.write_new("1.json.tmp", b"{}").unwrap();
+        responses.rename("1.json.tmp", "1.json").unwrap();
+        assert_eq!(responses.read_to_string("1.json").unwrap(), "{}");
+        assert_eq!(fs::read_to_string(host.join("secret")).unwrap(), "host file");
+        fs::remove_dir_all(&root).unwrap();
+    }
+}